@@ -0,0 +1,140 @@
+; one of every encoding the decoder knows, for decoding only. The jumps and loops all go back to the
+; same label so it never reaches the hlt at the end.
+bits 16
+
+start:
+    add ax, bx
+    add [bx + si], cl
+    add dx, [bp + di - 4]
+    add byte [bx], 200
+    add word [bx + 1000], -3
+    add ax, 1000
+    add al, 7
+    or cx, 4660
+    or word [es:si + 8], 112
+    adc ah, [di]
+    sbb sp, 1
+    and byte [bp + si], 240
+    sub word [4660], 2
+    sub al, 16
+    xor si, di
+    cmp word [bx + di + 768], 65336
+    cmp al, 65
+    test bl, 1
+    test ax, 32768
+    test word [bp], 257
+    test [bx], dx
+    inc byte [bx + 2]
+    inc bx
+    dec word [si]
+    dec al
+    neg word [bp - 2]
+    not cl
+    mul cx
+    imul byte [bx]
+    div word [16]
+    idiv bl
+    cbw
+    cwd
+    aaa
+    aas
+    aam 10
+    aad 10
+    daa
+    das
+    shl ax, 1
+    shr byte [bx], cl
+    sar dx, cl
+    rol word [bp + 4], 1
+    ror al, 1
+    rcl bx, cl
+    rcr byte [di], 1
+    rep movsb
+    movsw
+    repne cmpsb
+    repe scasw
+    lodsb
+    rep stosw
+    es movsb
+    clc
+    cmc
+    stc
+    cld
+    std
+    cli
+    sti
+    push word [bx]
+    push ax
+    push es
+    push ds
+    pop word [bp + 6]
+    pop cx
+    pop es
+    pushf
+    popf
+    call start
+    call bx
+    call word [bx + si]
+    call far [bx]
+    call 4096:32
+    jmp far [di]
+    jmp 8192:48
+    jmp bx
+    jmp word [1024]
+    ret
+    ret 4
+    retf
+    retf 8
+    mov ax, [256]
+    mov [512], al
+    mov ds, ax
+    mov es, [bx]
+    mov [bp], ss
+    mov dx, cs
+    mov byte [bx], 5
+    mov word [ss:bx + si + 2], 17185
+    mov cl, 9
+    mov di, 43981
+    mov al, [cs:bx]
+    xchg ax, dx
+    xchg cl, [bx + 3]
+    nop
+    lea si, [bp + di + 4]
+    lds bx, [0x200]
+    les di, [bx]
+    xlatb
+    lahf
+    sahf
+    in al, 0x60
+    in ax, dx
+    out 0x20, al
+    out dx, ax
+    lock inc word [bx]
+    pop ss
+    int 0x21
+    int3
+    iret
+    jmp near start
+jumps:
+    je jumps
+    jl jumps
+    jle jumps
+    jb jumps
+    jbe jumps
+    jg jumps
+    ja jumps
+    jp jumps
+    jo jumps
+    js jumps
+    jne jumps
+    jnl jumps
+    jnb jumps
+    jnp jumps
+    jno jumps
+    jns jumps
+    jcxz jumps
+    loop jumps
+    loopz jumps
+    loopnz jumps
+    jmp jumps
+    hlt
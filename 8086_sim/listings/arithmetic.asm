@@ -0,0 +1,29 @@
+; add, adc, sbb, and, or, xor, test, not, neg, inc and dec with register, memory and immediate
+; operands, including sign extended byte immediates
+bits 16
+
+    add ax, bx
+    adc dx, cx
+    sbb al, [bx + si + 4]
+    and word [bp], 0x0f0f
+    or cl, 3
+    xor ax, ax
+    xor ax, 0x1234
+    test ax, bx
+    test byte [di - 2], 7
+    test al, 0x80
+    test cx, 0x100
+    not word [bx]
+    neg dx
+    inc ax
+    dec si
+    inc byte [bx + 6]
+    dec cl
+    add word [bx], -1
+    sub sp, 2
+    adc ax, 5
+    sbb byte [1000], 5
+    or ah, dl
+    and bp, [si + 300]
+    cmp ax, -3
+    hlt
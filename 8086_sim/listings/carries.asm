@@ -0,0 +1,20 @@
+; carry, overflow and auxiliary carry out of additions, subtractions and logic ops
+bits 16
+
+    mov ax, 0xffff
+    mov bx, 1
+    add ax, bx              ; carries out of ax
+    adc bx, 0
+    mov cx, 0xf0
+    and cl, 0x3c
+    or ch, 0x80
+    xor dx, dx
+    not dx
+    neg bx
+    inc ax
+    dec ax
+    sbb ax, 0
+    test ah, 0x80
+    mov ah, 0x7f
+    add ah, 1               ; signed overflow
+    hlt
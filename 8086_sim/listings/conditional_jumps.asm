@@ -0,0 +1,55 @@
+; loops and every conditional jump, each taken one way or the other
+bits 16
+
+    mov cx, 3
+    mov ax, 0
+count:
+    add ax, 1
+    loop count
+
+    mov cx, 5
+until_four:
+    cmp ax, 4
+    loopnz until_four
+
+    mov cx, 5
+while_three:
+    cmp ax, 3
+    loopz while_three
+    jcxz skip_bx
+    mov bx, 1
+skip_bx:
+
+    cmp ax, 0x8000
+    jl less
+    mov dx, 1
+less:
+    jg greater
+greater:
+    cmp al, 2
+    ja above
+    jbe above
+above:
+    jo overflow
+    jno overflow
+overflow:
+    js sign
+    jns sign
+sign:
+    jp parity
+    jnp parity
+parity:
+    jb below
+    jnb below
+below:
+    je equal
+    jne equal
+equal:
+    jle less_or_equal
+    jnl less_or_equal
+less_or_equal:
+    mov cx, 0
+    jcxz done
+done:
+    mov cx, 1
+    hlt
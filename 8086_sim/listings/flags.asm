@@ -0,0 +1,24 @@
+; flags set by arithmetic and by the flag instructions, saved and restored with pushf and popf
+bits 16
+
+    mov ax, 0xff
+    add al, 1               ; carry, zero, auxiliary carry
+    add ax, 3
+    stc
+    cmc
+    stc
+    sti
+    pushf
+    clc
+    cli
+    mov bx, 0x100
+    add word [bx], -1
+    add byte [bx], 0x7f
+    popf
+
+    mov cx, 3
+count_down:
+    sub cx, 1
+    jne count_down
+    xor ax, ax
+    hlt
@@ -0,0 +1,31 @@
+; near jumps through a register and memory, far jumps and calls, direct and through memory. The
+; targets are absolute addresses, their labels are only there to show where they go.
+bits 16
+
+    mov bx, 5               ; next_instruction
+    jmp bx
+next_instruction:
+    mov word [0x400], 16    ; through_memory
+    jmp word [0x400]
+    inc dx                  ; skipped
+through_memory:
+    jmp short over
+    inc dx                  ; skipped
+over:
+    mov word [0x402], 35    ; far_target
+    mov word [0x404], 0
+    jmp far [0x402]
+far_target:
+    call 0:61               ; far_call
+    mov word [0x406], 63    ; far_call_through_memory
+    mov word [0x408], 0
+    call far [0x406]
+    jmp 0:67                ; done
+far_call:
+    inc ax
+    retf
+far_call_through_memory:
+    inc cx
+    retf 0
+done:
+    hlt
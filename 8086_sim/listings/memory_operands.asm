@@ -0,0 +1,24 @@
+; every kind of alu, shift and move on a memory operand, bp based so it's in the stack segment
+bits 16
+
+    mov bp, 0x100
+    mov word [bp + 4], 16
+    sub word [bp + 4], 4
+    cmp word [bp + 4], 12
+    add byte [bp + 4], 248
+    adc byte [bp + 5], 0
+    sbb word [bp + 4], 1
+    and word [bp + 4], 0xff
+    or byte [bp + 5], 0x80
+    xor [bp + 4], ax
+    test word [bp + 4], 0x8000
+    inc word [bp + 4]
+    dec byte [bp + 4]
+    neg word [bp + 4]
+    not byte [bp + 5]
+    shl word [bp + 4], 1
+    mov cl, 3
+    ror byte [bp + 4], cl
+    mov ax, [bp + 4]
+    mov [es:bp + 4], ax
+    hlt
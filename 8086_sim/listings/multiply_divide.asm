@@ -0,0 +1,42 @@
+; mul, imul, div and idiv on bytes and words, sign extension and the decimal adjusts
+bits 16
+
+    mov sp, 0x1000
+    mov word [0], 0x200
+    mov word [2], 0
+    mov ax, 300
+    mov bx, 400
+    mul bx
+    mov ax, -2
+    mov cx, 5
+    imul cx
+    mov al, 200
+    mov cl, 3
+    mul cl
+    mov ax, 1000
+    mov dl, 7
+    div dl
+    mov dx, 1               ; dx:ax is 0x10000
+    mov ax, 0
+    mov bx, 3
+    div bx
+    mov ax, -100
+    mov cl, 7
+    idiv cl
+    mov al, 0x85
+    cbw
+    cwd
+
+    mov ax, 9
+    add al, 8
+    aaa
+    mov al, 0x38
+    add al, 0x45
+    daa
+    mov al, 0x31
+    sub al, 0x45
+    das
+    mov al, 97
+    aam 10
+    aad 10
+    hlt
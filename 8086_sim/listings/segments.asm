@@ -0,0 +1,27 @@
+; segment registers, default segments for bx and bp addressing and segment override prefixes
+bits 16
+
+    mov ax, 0x1000
+    mov ds, ax
+    mov ax, 0x2000
+    mov es, ax
+    mov ax, 0x3000
+    mov ss, ax
+    mov sp, 0x100
+    mov bx, 4
+    mov bp, 4
+    mov word [bx], 0x1111   ; ds
+    mov word [bp], 0x2222   ; ss
+    mov word [es:bx], 0x3333
+    mov word [ds:bp], 0x4444
+    mov cx, [ds:bp]
+    mov dx, [ss:bx]
+    mov si, 4
+    mov di, 8
+    movsw
+    es lodsw
+    push cs
+    pop ax
+    mov [bx + si], es
+    mov ax, cs
+    hlt
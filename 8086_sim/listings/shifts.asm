@@ -0,0 +1,17 @@
+; shifts and rotates by 1 and by cl, on registers and memory
+bits 16
+
+    mov ax, 0x8001
+    shl ax, 1
+    mov cl, 4
+    mov bx, 0xf00f
+    rol bx, cl
+    sar bx, 1
+    shr bh, 1
+    rcr bl, 1
+    rcl ax, cl
+    ror dx, 1
+    shl word [bx], cl
+    sar byte [bp + si + 3], 1
+    shl cl, cl
+    hlt
@@ -0,0 +1,34 @@
+; push and pop of registers, memory and segment registers, near calls and returns
+bits 16
+
+    mov sp, 0x1000
+    mov ax, 5
+    push ax
+    call double
+    pop bx
+    mov bx, 57              ; address of increment_dx
+    call bx
+    mov word [0x500], 57
+    call word [0x500]
+    push word [0x500]
+    pop word [0x502]
+    push cs
+    pop es
+    pushf
+    mov ax, 0xffff
+    add ax, 1
+    popf
+    hlt
+
+; doubles the word pushed before the call into cx
+double:
+    push bp
+    mov bp, sp
+    mov cx, [bp + 4]
+    add cx, cx
+    pop bp
+    ret 2
+
+increment_dx:
+    inc dx
+    ret
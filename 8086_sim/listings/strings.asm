@@ -0,0 +1,32 @@
+; string instructions with and without rep prefixes and the direction flag
+bits 16
+
+    mov si, 0x100
+    mov di, 0x200
+    mov cx, 4
+    mov word [si], 0x1111
+    mov word [si + 2], 0x2222
+    mov word [si + 4], 0x3333
+    mov word [si + 6], 0x4444
+    rep movsw
+
+    mov si, 0x100
+    mov di, 0x200
+    mov cx, 8
+    mov byte [0x205], 0x99
+    repe cmpsb
+
+    mov di, 0x200
+    mov al, 0x33
+    mov cx, 10
+    repne scasb
+
+    std
+    mov si, 0x207
+    lodsb
+    cld
+    mov di, 0x300
+    mov ax, 0xabcd
+    stosw
+    movsb
+    hlt
@@ -0,0 +1,680 @@
+use std::{
+    collections::HashMap,
+    fmt,
+};
+
+use crate::decoder::{
+    *,
+    table::*,
+};
+
+// Assembles the syntax the disassembler prints, using the same instruction table the decoder does.
+// When more than one encoding fits an instruction the shortest is used, ties go to the first in the
+// table, which matches what nasm picks for everything the disassembler prints.
+
+#[derive(Debug, PartialEq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Specifier { Byte, Word, Far, Near, Short }
+
+enum JumpTarget {
+    Label(String),
+    Relative(i32), // from the start of the instruction like nasm's $
+}
+
+enum SourceOperand {
+    Register { encoding: u8, wide: bool },
+    SegmentRegister(u8),
+    // displacement is only encoded if the mode has one
+    Memory { mode: u8, reg_or_mem: u8, displacement: u16, segment_override: Option<u8> },
+    Immediate(i32),
+    Jump(JumpTarget),
+    Far { segment: u16, offset: u16 },
+}
+
+struct ParsedOperand {
+    operand: SourceOperand,
+    specifier: Option<Specifier>,
+    strict: bool, // an immediate keeps the size of its specifier instead of being sign extended
+}
+
+struct Statement {
+    line: usize,
+    prefixes: Vec<u8>,
+    mnemonic: String,
+    operands: Vec<ParsedOperand>,
+}
+
+enum Item {
+    Label(String, usize), // name and line
+    Statement(Statement),
+    Data(Vec<u8>), // db
+}
+
+const SEGMENT_OVERRIDE_PREFIX: u8 = 0b00100110;
+const REP_PREFIX: u8 = 0b11110010;
+const LOCK_PREFIX: u8 = 0b11110000;
+const MAX_LAYOUT_PASSES: usize = 16;
+
+// other names assemblers accept for the same instructions
+const MNEMONIC_ALIASES: [(&str, &str); 17] = [
+    ("jz", "je"), ("jnz", "jne"), ("jc", "jb"), ("jnae", "jb"), ("jnc", "jnb"), ("jae", "jnb"),
+    ("jna", "jbe"), ("jnbe", "ja"), ("jnge", "jl"), ("jge", "jnl"), ("jng", "jle"), ("jnle", "jg"),
+    ("jpe", "jp"), ("jpo", "jnp"), ("loope", "loopz"), ("loopne", "loopnz"), ("sal", "shl"),
+];
+
+fn parse_number(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits.trim_start()),
+        None => (false, text.strip_prefix('+').unwrap_or(text).trim_start()),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i32::from_str_radix(hex, 16).ok()?,
+        None if !digits.is_empty() && digits.bytes().all(|digit| digit.is_ascii_digit()) => digits.parse().ok()?,
+        None => return None,
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(name: &str) -> Option<SourceOperand> {
+    for encoding in 0..8 {
+        for wide in [ false, true ] {
+            if get_register_name(encoding, wide) == Some(name) { return Some(SourceOperand::Register { encoding, wide }); }
+        }
+    }
+
+    (0..4).find(|encoding| get_segment_register_name(*encoding) == Some(name)).map(SourceOperand::SegmentRegister)
+}
+
+fn parse_segment_register(name: &str) -> Option<u8> {
+    match parse_register(name) {
+        Some(SourceOperand::SegmentRegister(encoding)) => Some(encoding),
+        _ => None,
+    }
+}
+
+// nasm's byte or word in front of an address, which forces the size of the displacement
+fn parse_displacement_size(text: &str) -> (Option<Specifier>, &str) {
+    let text = text.trim_start();
+    for (keyword, specifier) in [ ("byte ", Specifier::Byte), ("word ", Specifier::Word) ] {
+        if let Some(rest) = text.strip_prefix(keyword) { return (Some(specifier), rest); }
+    }
+
+    (None, text)
+}
+
+fn parse_memory(text: &str) -> Result<SourceOperand, String> {
+    let (mut displacement_size, mut inner) = parse_displacement_size(text);
+    let mut segment_override = None;
+    if let Some((segment, rest)) = inner.split_once(':') {
+        segment_override = Some(parse_segment_register(segment.trim()).ok_or(format!("invalid segment register {}", segment))?);
+        inner = rest;
+        if displacement_size.is_none() { (displacement_size, inner) = parse_displacement_size(inner); }
+    }
+
+    // split into terms keeping the sign of each
+    let mut terms: Vec<String> = vec![];
+    for (index, character) in inner.char_indices() {
+        if index == 0 || character == '+' || character == '-' { terms.push(String::new()); }
+        terms.last_mut().unwrap().push(character);
+    }
+
+    let mut registers: Vec<&str> = vec![];
+    let mut displacement: i32 = 0;
+    for term in &terms {
+        let term = term.trim();
+        match term.strip_prefix('+').unwrap_or(term).trim() {
+            register @ ("bx" | "bp" | "si" | "di") => registers.push(register),
+            _ => displacement += parse_number(term).ok_or(format!("invalid address term {}", term))?,
+        }
+    }
+    if !(-32768..=65535).contains(&displacement) { return Err(format!("displacement {} doesn't fit in 16 bits", displacement)); }
+
+    registers.sort();
+    let reg_or_mem = match registers[..] {
+        [] if displacement_size.is_some() => return Err(format!("a direct address always has a word displacement [{}]", text)),
+        [] => return Ok(SourceOperand::Memory { mode: 0b00, reg_or_mem: 0b110, displacement: displacement as u16, segment_override }),
+        [ "bx", "si" ] => 0b000,
+        [ "bx", "di" ] => 0b001,
+        [ "bp", "si" ] => 0b010,
+        [ "bp", "di" ] => 0b011,
+        [ "si" ] => 0b100,
+        [ "di" ] => 0b101,
+        [ "bp" ] => 0b110,
+        [ "bx" ] => 0b111,
+        _ => return Err(format!("invalid address [{}]", text)),
+    };
+
+    // [bp] has no mode without a displacement, it's encoded as [bp + 0]
+    let mode = if displacement_size == Some(Specifier::Word) {
+        0b10
+    } else if displacement_size == Some(Specifier::Byte) {
+        if !fits_in_signed_byte(displacement) { return Err(format!("displacement {} doesn't fit in a byte", displacement)); }
+        0b01
+    } else if displacement == 0 && reg_or_mem != 0b110 {
+        0b00
+    } else if (-128..=127).contains(&(displacement as u16 as i16)) {
+        0b01
+    } else {
+        0b10
+    };
+
+    Ok(SourceOperand::Memory { mode, reg_or_mem, displacement: displacement as u16, segment_override })
+}
+
+fn parse_operand(text: &str) -> Result<ParsedOperand, String> {
+    let mut text = text.trim();
+    let strict = text.starts_with("strict ");
+    if strict { text = text["strict ".len()..].trim_start(); }
+    let mut specifier = None;
+    for (keyword, keyword_specifier) in [
+        ("byte", Specifier::Byte), ("word", Specifier::Word), ("far", Specifier::Far),
+        ("near", Specifier::Near), ("short", Specifier::Short),
+    ] {
+        if let Some(rest) = text.strip_prefix(keyword).filter(|rest| rest.starts_with([ ' ', '[' ])) {
+            specifier = Some(keyword_specifier);
+            text = rest.trim_start();
+            break;
+        }
+    }
+
+    let operand = if let Some(memory) = text.strip_prefix('[') {
+        parse_memory(memory.strip_suffix(']').ok_or(format!("unterminated address {}", text))?)?
+    } else if let Some(relative) = text.strip_prefix('$') {
+        let relative = if relative.is_empty() { 0 } else { parse_number(relative).ok_or(format!("invalid offset {}", text))? };
+        SourceOperand::Jump(JumpTarget::Relative(relative))
+    } else if let Some(register) = parse_register(text) {
+        register
+    } else if let Some(value) = parse_number(text) {
+        SourceOperand::Immediate(value)
+    } else if let Some((segment, offset)) = text.split_once(':') {
+        let segment = parse_number(segment.trim()).filter(|value| (0..=0xffff).contains(value));
+        let offset = parse_number(offset.trim()).filter(|value| (0..=0xffff).contains(value));
+        match (segment, offset) {
+            (Some(segment), Some(offset)) => SourceOperand::Far { segment: segment as u16, offset: offset as u16 },
+            _ => return Err(format!("invalid far address {}", text)),
+        }
+    } else if text.chars().all(|character| character.is_ascii_alphanumeric() || character == '_' || character == '.') && !text.is_empty() {
+        SourceOperand::Jump(JumpTarget::Label(text.to_string()))
+    } else {
+        return Err(format!("invalid operand {}", text));
+    };
+
+    if strict && !matches!(operand, SourceOperand::Immediate(_)) { return Err(format!("only an immediate can be strict {}", text)); }
+
+    Ok(ParsedOperand { operand, specifier, strict })
+}
+
+fn parse_line(line: usize, text: &str, items: &mut Vec<Item>) -> Result<(), String> {
+    let mut text = text.split(';').next().unwrap().trim();
+    if text.is_empty() { return Ok(()); }
+    if let Some(bits) = text.strip_prefix("bits ") {
+        return if bits.trim() == "16" { Ok(()) } else { Err(String::from("only bits 16 is supported")) };
+    }
+
+    if let Some((label, rest)) = text.split_once(':') {
+        let is_label = !label.is_empty() && label.chars().all(|character| character.is_ascii_alphanumeric() || character == '_' || character == '.');
+        if is_label && parse_register(label).is_none() {
+            items.push(Item::Label(label.to_string(), line));
+            text = rest.trim();
+            if text.is_empty() { return Ok(()); }
+        }
+    }
+
+    if let Some(data) = text.strip_prefix("db ") {
+        let bytes = data.split(',')
+            .map(|value| parse_number(value.trim()).filter(|value| fits_in_byte(*value)).map(|value| value as u8).ok_or(format!("invalid byte {}", value.trim())))
+            .collect::<Result<Vec<_>, _>>()?;
+        items.push(Item::Data(bytes));
+        return Ok(());
+    }
+
+    let mut prefixes = vec![];
+    let mut words = text.splitn(2, char::is_whitespace);
+    let mut mnemonic = words.next().unwrap().to_lowercase();
+    let mut rest = words.next().unwrap_or("").trim();
+    loop {
+        let prefix = match mnemonic.as_str() {
+            "lock" => LOCK_PREFIX,
+            "rep" | "repe" | "repz" => REP_PREFIX | 1,
+            "repne" | "repnz" => REP_PREFIX,
+            name => match parse_segment_register(name) {
+                // a segment register on its own is an override prefix for a string instruction
+                Some(segment) if !rest.is_empty() && !rest.starts_with(',') => SEGMENT_OVERRIDE_PREFIX | segment << 3,
+                _ => break,
+            },
+        };
+        prefixes.push(prefix);
+
+        let mut words = rest.splitn(2, char::is_whitespace);
+        mnemonic = words.next().unwrap().to_lowercase();
+        rest = words.next().unwrap_or("").trim();
+    }
+    if let Some((_, name)) = MNEMONIC_ALIASES.iter().find(|(alias, _)| *alias == mnemonic) {
+        mnemonic = name.to_string();
+    }
+
+    let operands = if rest.is_empty() {
+        vec![]
+    } else {
+        rest.split(',').map(parse_operand).collect::<Result<Vec<_>, _>>()?
+    };
+    if operands.len() > 2 { return Err(format!("{} has too many operands", mnemonic)); }
+
+    items.push(Item::Statement(Statement { line, prefixes, mnemonic, operands }));
+    Ok(())
+}
+
+// Field values of an encoding being built up from the operands
+#[derive(Default)]
+struct Fields { d: u8, w: u8, s: u8, v: u8, mode: u8, reg: u8, reg_or_mem: u8, segment_register: u8 }
+
+enum Trailing {
+    Bytes(Vec<u8>),
+    Rel8(i32), // absolute target address
+    Rel16(i32),
+}
+
+fn get_target(target: &JumpTarget, address: usize, labels: Option<&HashMap<String, usize>>) -> Result<i32, String> {
+    match target {
+        JumpTarget::Relative(relative) => Ok(address as i32 + relative),
+        JumpTarget::Label(label) => match labels {
+            Some(labels) => labels.get(label).map(|target| *target as i32).ok_or(format!("unknown label {}", label)),
+            // addresses aren't known yet while sizing, assume the closest possible target
+            None => Ok(address as i32),
+        },
+    }
+}
+
+fn fits_in_byte(value: i32) -> bool { (-128..=255).contains(&value) }
+fn fits_in_word(value: i32) -> bool { (-32768..=65535).contains(&value) }
+fn fits_in_signed_byte(value: i32) -> bool { (-128..=127).contains(&(value as u16 as i16)) }
+
+// Encodes the statement as one specific encoding, None if the operands don't fit it. The operands
+// are matched in table order unless `swap` reverses them, which is the d bit being clear.
+fn encode_as(
+    statement: &Statement,
+    encoding: &InstructionEncoding,
+    swap: bool,
+    address: usize,
+    labels: Option<&HashMap<String, usize>>,
+) -> Result<Option<Vec<u8>>, String> {
+    if statement.operands.len() != encoding.operands.len() { return Ok(None); }
+    let mut operands: Vec<&ParsedOperand> = statement.operands.iter().collect();
+    if swap { operands.reverse(); }
+
+    let has_w_field = encoding.bits.iter().any(|field| matches!(field, BitField::W));
+    let is_wide_implied = encoding.bits.iter().any(|field| matches!(field, BitField::WideImplied));
+
+    // the width has to agree across every operand that has one
+    let mut wide: Option<bool> = None;
+    if encoding.operation.is_string() {
+        wide = Some(statement.mnemonic.ends_with('w'));
+    }
+    for (operand, kind) in operands.iter().zip(encoding.operands) {
+        let operand_wide = match (&operand.operand, operand.specifier, kind) {
+            // the shift count is always cl or 1 and a port dx, whatever the size of the data
+            (_, _, OperandKind::ShiftCount | OperandKind::Dx) => None,
+            // the size of an immediate is only the size of the operation when nothing else sets it
+            (SourceOperand::Immediate(_), _, _) => None,
+            (SourceOperand::Register { wide, .. }, _, _) => Some(*wide),
+            (SourceOperand::SegmentRegister(_), _, _) => Some(true),
+            (_, Some(Specifier::Byte), _) => Some(false),
+            (_, Some(Specifier::Word), _) => Some(true),
+            _ => None,
+        };
+        match (wide, operand_wide) {
+            (Some(wide), Some(operand_wide)) if wide != operand_wide => return Ok(None),
+            (None, Some(_)) => wide = operand_wide,
+            _ => {},
+        }
+    }
+    let immediate_specifier = operands.iter().find_map(|operand| match operand.operand {
+        SourceOperand::Immediate(_) => operand.specifier,
+        _ => None,
+    });
+    if wide.is_none() {
+        wide = match immediate_specifier {
+            Some(Specifier::Byte) => Some(false),
+            Some(Specifier::Word) => Some(true),
+            _ => None,
+        };
+    }
+    let wide = if is_wide_implied {
+        if wide == Some(false) { return Ok(None); }
+        true
+    } else if has_w_field {
+        match wide {
+            Some(wide) => wide,
+            None => return Err(format!("operand size of {} isn't known, add byte or word", statement.mnemonic)),
+        }
+    } else {
+        false
+    };
+
+    let is_far = operands.iter().any(|operand| operand.specifier == Some(Specifier::Far));
+    if is_far != encoding.operation.is_indirect_intersegment() { return Ok(None); }
+
+    let mut fields = Fields { d: !swap as u8, w: wide as u8, mode: 0b11, ..Default::default() };
+    let mut segment_override = None;
+    let mut displacement: Vec<u8> = vec![];
+    let mut trailing: Vec<Trailing> = vec![];
+    for (operand, kind) in operands.iter().zip(encoding.operands) {
+        let matched = match (kind, &operand.operand) {
+            (OperandKind::Reg, SourceOperand::Register { encoding, .. }) => { fields.reg = *encoding; true },
+            (OperandKind::RegMem, SourceOperand::Register { encoding, .. }) => { fields.reg_or_mem = *encoding; true },
+            (OperandKind::RegMem | OperandKind::Memory, SourceOperand::Memory { mode, reg_or_mem, displacement: value, segment_override: segment }) => {
+                fields.mode = *mode;
+                fields.reg_or_mem = *reg_or_mem;
+                segment_override = *segment;
+                displacement = match (mode, reg_or_mem) {
+                    (0b01, _) => vec![ *value as u8 ],
+                    (0b10, _) | (0b00, 0b110) => value.to_le_bytes().to_vec(),
+                    _ => vec![],
+                };
+                true
+            },
+            (OperandKind::SegReg, SourceOperand::SegmentRegister(encoding)) => { fields.segment_register = *encoding; true },
+            (OperandKind::Acc, SourceOperand::Register { encoding: 0b000, .. }) => true,
+            (OperandKind::Dx, SourceOperand::Register { encoding: 0b010, wide: true }) => true,
+            (OperandKind::Direct, SourceOperand::Memory { mode: 0b00, reg_or_mem: 0b110, segment_override: segment, .. }) => {
+                segment_override = *segment;
+                true
+            },
+            (OperandKind::Imm, SourceOperand::Immediate(value)) => {
+                let has_s_field = encoding.bits.iter().any(|field| matches!(field, BitField::S));
+                let is_strict_word = operand.strict && operand.specifier == Some(Specifier::Word);
+                fields.s = (has_s_field && wide && fits_in_signed_byte(*value) && !is_strict_word) as u8;
+                // a byte immediate in a word operation has to be the sign extended form
+                let is_byte_in_word = wide && operand.specifier == Some(Specifier::Byte);
+                if is_byte_in_word {
+                    fields.s == 1
+                } else if wide {
+                    fits_in_word(*value)
+                } else {
+                    !is_strict_word && fits_in_byte(*value)
+                }
+            },
+            (OperandKind::ImmByte, SourceOperand::Immediate(value)) => fits_in_byte(*value),
+            (OperandKind::ImmWord, SourceOperand::Immediate(value)) => fits_in_word(*value),
+            (OperandKind::ShiftCount, SourceOperand::Immediate(1)) => { fields.v = 0; true },
+            (OperandKind::ShiftCount, SourceOperand::Register { encoding: 0b001, wide: false }) => { fields.v = 1; true },
+            (OperandKind::Rel8, SourceOperand::Jump(_)) => operand.specifier.is_none() || operand.specifier == Some(Specifier::Short),
+            (OperandKind::Rel16, SourceOperand::Jump(_)) => operand.specifier.is_none() || operand.specifier == Some(Specifier::Near),
+            (OperandKind::Far, SourceOperand::Far { .. }) => true,
+            _ => false,
+        };
+        if !matched { return Ok(None); }
+    }
+
+    // data follows the displacement in table order, whichever way round the operands are written
+    for (operand, kind) in operands.iter().zip(encoding.operands) {
+        match (kind, &operand.operand) {
+            (OperandKind::Direct, SourceOperand::Memory { displacement, .. }) => trailing.push(Trailing::Bytes(displacement.to_le_bytes().to_vec())),
+            (OperandKind::Imm, SourceOperand::Immediate(value)) => trailing.push(Trailing::Bytes(match (wide, fields.s) {
+                (true, 0) => (*value as u16).to_le_bytes().to_vec(),
+                _ => vec![ *value as u8 ],
+            })),
+            (OperandKind::ImmByte, SourceOperand::Immediate(value)) => trailing.push(Trailing::Bytes(vec![ *value as u8 ])),
+            (OperandKind::ImmWord, SourceOperand::Immediate(value)) => trailing.push(Trailing::Bytes((*value as u16).to_le_bytes().to_vec())),
+            (OperandKind::Rel8, SourceOperand::Jump(target)) => trailing.push(Trailing::Rel8(get_target(target, address, labels)?)),
+            (OperandKind::Rel16, SourceOperand::Jump(target)) => trailing.push(Trailing::Rel16(get_target(target, address, labels)?)),
+            (OperandKind::Far, SourceOperand::Far { segment, offset }) => {
+                trailing.push(Trailing::Bytes([ offset.to_le_bytes(), segment.to_le_bytes() ].concat()));
+            },
+            _ => {},
+        }
+    }
+
+    // prefixes go out in the order nasm emits them, rep then lock then the segment override
+    let mut bytes: Vec<u8> = statement.prefixes.clone();
+    if let Some(segment) = segment_override { bytes.push(SEGMENT_OVERRIDE_PREFIX | segment << 3); }
+    bytes.sort_by_key(|prefix| match prefix {
+        _ if prefix & !1 == REP_PREFIX => 0,
+        &LOCK_PREFIX => 1,
+        _ => 2,
+    });
+
+    let mut bit_index = 0;
+    for field in encoding.bits {
+        let bit_count = field.get_bit_count();
+        if bit_count == 0 { continue; }
+
+        let value = match field {
+            BitField::Literal(_, value) => *value,
+            BitField::D => fields.d,
+            BitField::W => fields.w,
+            BitField::S => fields.s,
+            BitField::V => fields.v,
+            BitField::Mod => fields.mode,
+            BitField::Reg => fields.reg,
+            BitField::Rm => fields.reg_or_mem,
+            BitField::Sr => fields.segment_register,
+            BitField::WideImplied => unreachable!(),
+        };
+        if bit_index % 8 == 0 { bytes.push(0); }
+        *bytes.last_mut().unwrap() |= value << (8 - bit_index % 8 - bit_count as usize);
+        bit_index += bit_count as usize;
+    }
+    bytes.extend(displacement);
+
+    let size: usize = bytes.len() + trailing.iter().map(|trailing| match trailing {
+        Trailing::Bytes(bytes) => bytes.len(),
+        Trailing::Rel8(_) => 1,
+        Trailing::Rel16(_) => 2,
+    }).sum::<usize>();
+    let next_address = (address + size) as i32;
+    for trailing in trailing {
+        match trailing {
+            Trailing::Bytes(data) => bytes.extend(data),
+            Trailing::Rel8(target) => {
+                let offset = target - next_address;
+                if !(-128..=127).contains(&offset) { return Ok(None); }
+                bytes.push(offset as u8);
+            },
+            Trailing::Rel16(target) => bytes.extend(((target - next_address) as u16).to_le_bytes()),
+        }
+    }
+
+    Ok(Some(bytes))
+}
+
+fn encode_statement(statement: &Statement, address: usize, labels: Option<&HashMap<String, usize>>) -> Result<Vec<u8>, String> {
+    // only the 8086 and 8088 can pop or move into cs, nasm refuses it unless told to target them
+    let is_cs_destination = matches!(statement.operands.first(), Some(ParsedOperand { operand: SourceOperand::SegmentRegister(CS), .. }));
+    if is_cs_destination && (statement.mnemonic == "mov" || statement.mnemonic == "pop") {
+        return Err(format!("{} can't load cs, write it as db", statement.mnemonic));
+    }
+
+    let mut best: Option<Vec<u8>> = None;
+    let mut mnemonic_found = false;
+    for encoding in INSTRUCTION_TABLE {
+        let is_string_match = encoding.operation.is_string()
+            && (statement.mnemonic.strip_suffix('b') == Some(encoding.mnemonic) || statement.mnemonic.strip_suffix('w') == Some(encoding.mnemonic));
+        if encoding.mnemonic != statement.mnemonic && !is_string_match { continue; }
+        mnemonic_found = true;
+
+        // with a d bit the operands can go either way round, nasm prefers reg/mem as the destination.
+        // xchg can be written either way round too, nasm takes xchg bx, ax as 93 like xchg ax, bx.
+        let has_d_field = encoding.bits.iter().any(|field| matches!(field, BitField::D));
+        let orders: &[bool] = if has_d_field { &[ true, false ] } else if encoding.mnemonic == "xchg" { &[ false, true ] } else { &[ false ] };
+        for swap in orders {
+            if let Some(bytes) = encode_as(statement, encoding, *swap, address, labels)? {
+                if best.as_ref().is_none_or(|best| bytes.len() < best.len()) { best = Some(bytes); }
+            }
+        }
+    }
+
+    if !mnemonic_found { return Err(format!("unknown instruction {}", statement.mnemonic)); }
+    let bytes = best.ok_or(format!("invalid operands for {}", statement.mnemonic))?;
+
+    let is_rep = statement.prefixes.iter().any(|prefix| prefix & !1 == REP_PREFIX);
+    let is_string = INSTRUCTION_TABLE.iter().any(|encoding| encoding.operation.is_string() && statement.mnemonic.starts_with(encoding.mnemonic));
+    if is_rep && !is_string { return Err(format!("rep can't prefix {}", statement.mnemonic)); }
+
+    Ok(bytes)
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    let mut items: Vec<Item> = vec![];
+    for (index, text) in source.lines().enumerate() {
+        parse_line(index + 1, text, &mut items).map_err(|message| AssembleError { line: index + 1, message })?;
+    }
+
+    // Sizes are found assuming every label is as close as possible, jumps that end up out of range
+    // of their short form grow and everything is laid out again until nothing changes
+    let mut sizes: Vec<usize> = vec![ 0; items.len() ];
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut is_sized = false;
+    for _ in 0..MAX_LAYOUT_PASSES {
+        let mut address = 0;
+        let mut changed = false;
+        let mut output: Vec<u8> = vec![];
+        for (index, item) in items.iter().enumerate() {
+            match item {
+                Item::Label(label, line) => {
+                    if labels.insert(label.clone(), address).is_some() && !is_sized {
+                        return Err(AssembleError { line: *line, message: format!("label {} is defined more than once", label) });
+                    }
+                },
+                Item::Statement(statement) => {
+                    let bytes = encode_statement(statement, address, if is_sized { Some(&labels) } else { None })
+                        .map_err(|message| AssembleError { line: statement.line, message })?;
+                    changed |= bytes.len() != sizes[index];
+                    sizes[index] = bytes.len();
+                    address += bytes.len();
+                    output.extend(bytes);
+                },
+                Item::Data(data) => {
+                    address += data.len();
+                    output.extend(data);
+                },
+            }
+        }
+
+        if is_sized && !changed { return Ok(output); }
+        is_sized = true;
+    }
+
+    Err(AssembleError { line: 0, message: String::from("jump sizes didn't settle, mark some jumps near") })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::*;
+
+    #[test]
+    fn encodes_like_nasm() {
+        let source = "mov ax, bx\nadd ax, 5\nadd ax, 1000\nmov al, [5]\nmov [bp], byte 7\nmov cx, [bx + si - 2]\nshl word [es:di], cl\n";
+        assert_eq!(assemble(source), Ok(vec![
+            0x89, 0xd8,
+            0x83, 0xc0, 0x05,
+            0x05, 0xe8, 0x03,
+            0xa0, 0x05, 0x00,
+            0xc6, 0x46, 0x00, 0x07,
+            0x8b, 0x48, 0xfe,
+            0x26, 0xd3, 0x25,
+        ]));
+    }
+
+    #[test]
+    fn exchanges_loads_and_ports() {
+        let source = "xchg al, bl\nxchg cx, [bx + si]\nxchg ax, dx\nnop\nlea si, [bp + di + 4]\nlds bx, [0x200]\nles di, [bx]\nes xlatb\nlahf\nsahf\nin al, 0x60\nin ax, dx\nout 0x20, al\nout dx, ax\nlock inc word [bx]\n";
+        assert_eq!(assemble(source), Ok(vec![
+            0x86, 0xc3,
+            0x87, 0x08,
+            0x92,
+            0x90,
+            0x8d, 0x73, 0x04,
+            0xc5, 0x1e, 0x00, 0x02,
+            0xc4, 0x3f,
+            0x26, 0xd7,
+            0x9f,
+            0x9e,
+            0xe4, 0x60,
+            0xed,
+            0xe6, 0x20,
+            0xef,
+            0xf0, 0xff, 0x07,
+        ]));
+    }
+
+    #[test]
+    fn byte_immediates_in_word_operations() {
+        let source = "xor ax, byte -1\nadd word [bx + 2], byte 5\nadd [bx], byte 5\n";
+        assert_eq!(assemble(source), Ok(vec![
+            0x83, 0xf0, 0xff,
+            0x83, 0x47, 0x02, 0x05,
+            0x80, 0x07, 0x05,
+        ]));
+        // a byte can't be sign extended to 128
+        assert_eq!(assemble("add ax, byte 128").err().map(|error| error.message), Some(String::from("invalid operands for add")));
+    }
+
+    #[test]
+    fn forced_sizes_and_data() {
+        let source = "mov [byte bx + 0], ax\nmov [word es:bx + 4], ax\nadd bx, strict word 5\nadd ax, strict word 5\nxchg bx, ax\ndb 0x80, 0xc0, 5\n";
+        assert_eq!(assemble(source), Ok(vec![
+            0x89, 0x47, 0x00,
+            0x26, 0x89, 0x87, 0x04, 0x00,
+            0x81, 0xc3, 0x05, 0x00,
+            0x05, 0x05, 0x00,
+            0x93,
+            0x80, 0xc0, 0x05,
+        ]));
+        assert_eq!(assemble("rep es movsb\nlock inc word [es:bx]\n"), Ok(vec![ 0xf3, 0x26, 0xa4, 0xf0, 0x26, 0xff, 0x07 ]));
+
+        assert!(assemble("mov [byte bx + 200], ax\n").is_err());
+        assert!(assemble("pop cs\n").is_err());
+        assert!(assemble("db 256\n").is_err());
+    }
+
+    #[test]
+    fn reports_line_of_error() {
+        assert_eq!(assemble("bits 16\n\nmov [bx], 5\n").err().map(|error| error.line), Some(3));
+        assert_eq!(assemble("jne nowhere\n").err().map(|error| error.line), Some(1));
+    }
+
+    fn get_listings() -> Vec<std::path::PathBuf> {
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/listings");
+        let mut listings: Vec<_> = std::fs::read_dir(directory).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "bin"))
+            .collect();
+        listings.sort();
+        assert!(!listings.is_empty());
+
+        listings
+    }
+
+    // the binaries were built from their sources by another assembler
+    #[test]
+    fn assembles_listing_sources() {
+        for path in get_listings() {
+            let source = std::fs::read_to_string(path.with_extension("asm")).unwrap();
+            assert_eq!(assemble(&source).as_ref(), Ok(&std::fs::read(&path).unwrap()), "{} didn't assemble to the same bytes", path.display());
+        }
+    }
+
+    #[test]
+    fn round_trips_listings() {
+        for path in get_listings() {
+            let bytes = std::fs::read(&path).unwrap();
+            let (listing, error) = disassemble(&bytes);
+            assert!(error.is_none(), "{} failed to decode", path.display());
+            assert_eq!(assemble(&listing).as_ref(), Ok(&bytes), "{} didn't reassemble to the same bytes", path.display());
+        }
+    }
+}
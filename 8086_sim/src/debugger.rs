@@ -0,0 +1,564 @@
+use std::{
+    collections::{
+        BTreeMap,
+        VecDeque,
+    },
+    fmt::{self, Write},
+};
+
+use crate::{
+    decoder::*,
+    machine::*,
+    trace::*,
+};
+
+// Interactive debugger on top of Machine with commands modelled on gdb. Every command takes a line
+// and returns what it printed so the prompt itself can live wherever the input comes from.
+
+const HELP: &str = "\
+step [count]         (s)  execute count instructions, printing each
+next                 (n)  step over calls and interrupts
+continue             (c)  run until a breakpoint, watchpoint or the end of the program
+break <location>     (b)  stop before the instruction at a label, offset in cs or seg:off
+watch <register>     (w)  stop when a register changes
+watch <address> [n]  (w)  stop when any of n bytes (default 1) at an offset in ds or seg:off change
+delete [number]      (d)  delete a breakpoint or watchpoint, or all of them
+info                 (i)  list breakpoints and watchpoints
+registers            (r)  print registers and flags
+set <register> <value>    change a register, ip included
+x/<count><x|d><b|w> <address>
+                          print memory as hex or decimal bytes or words, e.g. x/16xb 0x100
+list [count]         (l)  disassemble around ip
+quit                 (q)
+An empty line repeats the last command. Numbers are decimal unless they start with 0x, both halves
+of seg:off are hex and the segment can be a segment register.
+";
+
+// number of executed instructions list shows before ip
+const HISTORY_LENGTH: usize = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+enum RegisterName {
+    General(u8, RegisterAccess),
+    Segment(u8),
+    InstructionPointer,
+}
+
+impl RegisterName {
+    fn parse(name: &str) -> Option<Self> {
+        if name == "ip" { return Some(Self::InstructionPointer); }
+        for encoding in 0..8 {
+            if get_register_name(encoding, true) == Some(name) { return Some(Self::General(encoding, RegisterAccess::Full)); }
+            if get_register_name(encoding, false) == Some(name) {
+                let access = if encoding & 0b100 == 0 { RegisterAccess::Low } else { RegisterAccess::High };
+                return Some(Self::General(encoding, access));
+            }
+        }
+
+        (0..4).find(|encoding| get_segment_register_name(*encoding) == Some(name)).map(Self::Segment)
+    }
+
+    fn get_name(&self) -> &'static str {
+        match self {
+            Self::General(encoding, access) => get_register_name(*encoding, *access == RegisterAccess::Full).expect("Invalid register"),
+            Self::Segment(encoding) => get_segment_register_name(*encoding).expect("Invalid segment register"),
+            Self::InstructionPointer => "ip",
+        }
+    }
+
+    fn read(&self, machine: &Machine) -> u16 {
+        match self {
+            Self::General(encoding, access) => machine.registers.get_register_value(*encoding, access),
+            Self::Segment(encoding) => machine.registers.segment_registers[*encoding as usize],
+            Self::InstructionPointer => machine.instruction_pointer,
+        }
+    }
+
+    fn write(&self, machine: &mut Machine, value: u16) {
+        match self {
+            Self::General(encoding, access) => machine.registers.set_register_value(*encoding, access, value),
+            Self::Segment(encoding) => machine.registers.segment_registers[*encoding as usize] = value,
+            Self::InstructionPointer => machine.instruction_pointer = value,
+        }
+    }
+}
+
+enum Watch {
+    Register(RegisterName),
+    Memory { segment: u16, offset: u16, length: u16 },
+}
+
+impl Watch {
+    fn read(&self, machine: &Machine) -> Vec<u8> {
+        match self {
+            Self::Register(register) => register.read(machine).to_le_bytes().to_vec(),
+            Self::Memory { segment, offset, length } => (0..*length)
+                .map(|index| machine.memory[get_physical_address(*segment, offset.wrapping_add(index))])
+                .collect(),
+        }
+    }
+
+    fn format_value(&self, value: &[u8]) -> String {
+        match self {
+            Self::Register(_) => format!("{:#06x}", u16::from_le_bytes([ value[0], value[1] ])),
+            Self::Memory { .. } => value.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" "),
+        }
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Register(register) => write!(formatter, "{}", register.get_name()),
+            Self::Memory { segment, offset, length } => write!(formatter, "{:04x}:{:04x} ({} bytes)", segment, offset, length),
+        }
+    }
+}
+
+enum Point {
+    Breakpoint(usize), // physical address
+    Watchpoint(Watch),
+}
+
+pub struct Debugger {
+    labels: BTreeMap<usize, String>, // by physical address
+    points: BTreeMap<usize, Point>, // breakpoints and watchpoints share their numbers like in gdb
+    next_point_number: usize,
+    history: VecDeque<(u16, u16)>, // cs and ip of the last executed instructions
+    last_command: String,
+}
+
+// Decimal, or hex with a 0x prefix
+fn parse_number(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_hex(text: &str) -> Option<u16> {
+    u16::from_str_radix(text.strip_prefix("0x").unwrap_or(text), 16).ok()
+}
+
+impl Debugger {
+    // labels are the disassembler's labels for the loaded program, by physical address
+    pub fn new(labels: BTreeMap<usize, String>) -> Self {
+        Self { labels, points: BTreeMap::new(), next_point_number: 1, history: VecDeque::new(), last_command: String::new() }
+    }
+
+    // seg:off, or an offset in default_segment
+    fn parse_address(&self, text: &str, machine: &Machine, default_segment: u8) -> Result<(u16, u16), String> {
+        let invalid = || format!("invalid address {}", text);
+        match text.split_once(':') {
+            Some((segment, offset)) => {
+                let segment = match RegisterName::parse(segment) {
+                    Some(RegisterName::Segment(encoding)) => machine.registers.segment_registers[encoding as usize],
+                    _ => parse_hex(segment).ok_or_else(invalid)?,
+                };
+                Ok((segment, parse_hex(offset).ok_or_else(invalid)?))
+            },
+            None => Ok((machine.registers.segment_registers[default_segment as usize], parse_number(text).ok_or_else(invalid)?)),
+        }
+    }
+
+    // a label, or an address with offsets in cs
+    fn parse_location(&self, text: &str, machine: &Machine) -> Result<usize, String> {
+        match self.labels.iter().find(|(_, label)| label.as_str() == text) {
+            Some((address, _)) => Ok(*address),
+            None => self.parse_address(text, machine, CS).map(|(segment, offset)| get_physical_address(segment, offset)),
+        }
+    }
+
+    fn format_location(&self, segment: u16, offset: u16) -> String {
+        match self.labels.get(&get_physical_address(segment, offset)) {
+            Some(label) => format!("{:04x}:{:04x} <{}>", segment, offset, label),
+            None => format!("{:04x}:{:04x}", segment, offset),
+        }
+    }
+
+    fn format_instruction_at(&self, machine: &Machine, segment: u16, offset: u16) -> (String, Option<Instruction>) {
+        let address = get_physical_address(segment, offset);
+        match decode_instruction(&machine.memory, address) {
+            Ok(instruction) => {
+                let target = match instruction.operands {
+                    [ Some(Operand::LabelOffset(relative)), None ] => {
+                        let target = offset.wrapping_add(instruction.size as u16).wrapping_add(relative as u16);
+                        self.labels.get(&get_physical_address(segment, target))
+                    },
+                    _ => None,
+                };
+                let text = match target {
+                    Some(label) => LabelledInstruction { instruction: &instruction, label }.to_string(),
+                    None => instruction.to_string(),
+                };
+                (text, Some(instruction))
+            },
+            Err(error) => (error.to_string(), None),
+        }
+    }
+
+    // The instruction execution stops at, as shown after anything that runs the program
+    pub fn get_current_location(&self, machine: &Machine) -> String {
+        let segment = machine.registers.segment_registers[CS as usize];
+        let (instruction, _) = self.format_instruction_at(machine, segment, machine.instruction_pointer);
+        format!("=> {}  {}", self.format_location(segment, machine.instruction_pointer), instruction)
+    }
+
+    fn get_breakpoint_number(&self, machine: &Machine) -> Option<usize> {
+        let address = get_physical_address(machine.registers.segment_registers[CS as usize], machine.instruction_pointer);
+        self.points.iter().find_map(|(number, point)| match point {
+            Point::Breakpoint(breakpoint) if *breakpoint == address => Some(*number),
+            _ => None,
+        })
+    }
+
+    // Executes one instruction and reports what happened, returns whether execution has to stop
+    // because of an int 3, a watchpoint or the end of the program
+    fn step_once(&mut self, machine: &mut Machine, should_print_step: bool, output: &mut String) -> bool {
+        let watched: Vec<(usize, Vec<u8>)> = self.points.iter()
+            .filter_map(|(number, point)| match point {
+                Point::Watchpoint(watch) => Some((*number, watch.read(machine))),
+                Point::Breakpoint(_) => None,
+            })
+            .collect();
+
+        let step = match machine.step() {
+            Ok(step) => step,
+            Err(stop_reason) => {
+                writeln!(output, "program stopped: {}", stop_reason).unwrap();
+                return true;
+            },
+        };
+        self.history.push_back((step.code_segment, step.instruction_pointer_before));
+        if self.history.len() > HISTORY_LENGTH { self.history.pop_front(); }
+
+        if should_print_step {
+            let total_cycles = machine.prefetch_model.as_ref().map(|prefetch_model| prefetch_model.total_cycles);
+            writeln!(
+                output,
+                "{:04x}:{:04x}  {}",
+                step.code_segment, step.instruction_pointer_before, step_to_text(&step, machine.total_clocks, total_cycles, true, false)
+            ).unwrap();
+        }
+
+        let mut should_stop = false;
+        for event in &step.events {
+            match event {
+                StepEvent::Output(bytes) => output.push_str(&String::from_utf8_lossy(bytes)),
+                StepEvent::Breakpoint => {
+                    writeln!(output, "int3 at {}", self.format_location(step.code_segment, step.instruction_pointer_before)).unwrap();
+                    should_stop = true;
+                },
+                _ => {},
+            }
+        }
+        for (number, before) in watched {
+            let Some(Point::Watchpoint(watch)) = self.points.get(&number) else { continue };
+            let after = watch.read(machine);
+            if after != before {
+                writeln!(output, "watchpoint {} {}: {} -> {}", number, watch, watch.format_value(&before), watch.format_value(&after)).unwrap();
+                should_stop = true;
+            }
+        }
+
+        should_stop
+    }
+
+    // Runs until a breakpoint or anything else that stops a step, or until ip is back at return_to
+    // with the stack unwound to stack_pointer
+    fn resume(&mut self, machine: &mut Machine, return_to: Option<(usize, u16)>, output: &mut String) {
+        const SP: u8 = 4;
+        loop {
+            if self.step_once(machine, false, output) { break; }
+            let address = get_physical_address(machine.registers.segment_registers[CS as usize], machine.instruction_pointer);
+            let stack_pointer = machine.registers.get_register_value(SP, &RegisterAccess::Full);
+            if return_to.is_some_and(|(return_address, return_stack_pointer)| address == return_address && stack_pointer >= return_stack_pointer) {
+                break;
+            }
+            if let Some(number) = self.get_breakpoint_number(machine) {
+                writeln!(output, "breakpoint {}", number).unwrap();
+                break;
+            }
+        }
+        writeln!(output, "{}", self.get_current_location(machine)).unwrap();
+    }
+
+    fn add_point(&mut self, point: Point) -> usize {
+        let number = self.next_point_number;
+        self.points.insert(number, point);
+        self.next_point_number += 1;
+        number
+    }
+
+    fn print_registers(&self, machine: &Machine, output: &mut String) {
+        for encodings in [ 0..4, 4..8 ] {
+            let registers: Vec<String> = encodings
+                .map(|encoding| {
+                    let name = get_register_name(encoding, true).expect("Invalid register");
+                    format!("{} {:#06x}", name, machine.registers.get_register_value(encoding, &RegisterAccess::Full))
+                })
+                .collect();
+            writeln!(output, "{}", registers.join("  ")).unwrap();
+        }
+        let segment_registers: Vec<String> = machine.registers.segment_registers.iter().enumerate()
+            .map(|(encoding, value)| format!("{} {:#06x}", get_segment_register_name(encoding as u8).expect("Invalid segment register"), value))
+            .collect();
+        writeln!(output, "{}", segment_registers.join("  ")).unwrap();
+        writeln!(output, "ip {:#06x}  flags {}", machine.instruction_pointer, machine.flags.get_active_flags_string()).unwrap();
+    }
+
+    // x/<count><format><size> <address>
+    fn examine(&self, machine: &Machine, format: &str, address: Option<&str>, output: &mut String) -> Result<(), String> {
+        let digits = format.find(|character: char| !character.is_ascii_digit()).unwrap_or(format.len());
+        let count = if digits == 0 { 1 } else { format[..digits].parse().map_err(|_| format!("invalid count {}", &format[..digits]))? };
+        let mut is_decimal = false;
+        let mut is_wide = false;
+        for letter in format[digits..].chars() {
+            match letter {
+                'x' => is_decimal = false,
+                'd' => is_decimal = true,
+                'b' => is_wide = false,
+                'w' => is_wide = true,
+                letter => return Err(format!("unknown format letter {}", letter)),
+            }
+        }
+        let (segment, offset) = self.parse_address(address.ok_or("x needs an address")?, machine, DS)?;
+
+        let size: u16 = if is_wide { 2 } else { 1 };
+        let values: Vec<String> = (0..count)
+            .map(|index: u16| {
+                let value_offset = offset.wrapping_add(index.wrapping_mul(size));
+                let low = machine.memory[get_physical_address(segment, value_offset)] as u16;
+                let value = if is_wide { low | (machine.memory[get_physical_address(segment, value_offset.wrapping_add(1))] as u16) << 8 } else { low };
+                match (is_decimal, is_wide) {
+                    (true, _) => value.to_string(),
+                    (false, false) => format!("{:02x}", value),
+                    (false, true) => format!("{:04x}", value),
+                }
+            })
+            .collect();
+        let per_line = if is_wide { 8 } else { 16 };
+        for (line, chunk) in values.chunks(per_line).enumerate() {
+            let line_offset = offset.wrapping_add((line * per_line) as u16 * size);
+            writeln!(output, "{:04x}:{:04x}  {}", segment, line_offset, chunk.join(" ")).unwrap();
+        }
+        Ok(())
+    }
+
+    fn list(&self, machine: &Machine, count: usize, output: &mut String) {
+        let segment = machine.registers.segment_registers[CS as usize];
+        let write_line = |output: &mut String, segment: u16, offset: u16, marker: &str| -> Option<u8> {
+            if let Some(label) = self.labels.get(&get_physical_address(segment, offset)) {
+                writeln!(output, "{}:", label).unwrap();
+            }
+            let (text, instruction) = self.format_instruction_at(machine, segment, offset);
+            writeln!(output, "{} {:04x}:{:04x}  {}", marker, segment, offset, text).unwrap();
+            instruction.map(|instruction| instruction.size)
+        };
+
+        // executed instructions are the only reliable way to know what came before ip
+        for (history_segment, history_offset) in &self.history {
+            if *history_segment == segment { write_line(output, segment, *history_offset, "  "); }
+        }
+        let mut offset = machine.instruction_pointer;
+        for index in 0..count {
+            let Some(size) = write_line(output, segment, offset, if index == 0 { "=>" } else { "  " }) else { break };
+            offset = offset.wrapping_add(size as u16);
+        }
+    }
+
+    // Runs one command line, None once the debugger should quit
+    pub fn run_command(&mut self, machine: &mut Machine, line: &str) -> Option<String> {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => line.to_string(),
+        };
+        self.last_command = line.clone();
+
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else { return Some(String::new()) };
+        let arguments: Vec<&str> = words.collect();
+        let mut output = String::new();
+        let result = match (command, arguments.as_slice()) {
+            ("q" | "quit", _) => return None,
+            ("h" | "help", _) => {
+                output.push_str(HELP);
+                Ok(())
+            },
+
+            ("s" | "step", arguments) => match arguments.first().map(|count| parse_number(count)) {
+                Some(None) => Err(format!("invalid count {}", arguments[0])),
+                count => {
+                    for _ in 0..count.flatten().unwrap_or(1) {
+                        if self.step_once(machine, true, &mut output) { break; }
+                        if let Some(number) = self.get_breakpoint_number(machine) {
+                            writeln!(output, "breakpoint {}", number).unwrap();
+                            break;
+                        }
+                    }
+                    Ok(())
+                },
+            },
+
+            ("n" | "next", _) => {
+                const SP: u8 = 4;
+                let segment = machine.registers.segment_registers[CS as usize];
+                let (_, instruction) = self.format_instruction_at(machine, segment, machine.instruction_pointer);
+                let is_call = instruction.as_ref().is_some_and(|instruction| matches!(
+                    instruction.operation,
+                    Operation::Call_Direct_Within_Segment
+                    | Operation::Call_Indirect_Within_Segment
+                    | Operation::Call_Direct_Intersegment
+                    | Operation::Call_Indirect_Intersegment
+                    | Operation::Interrupt
+                ));
+                match instruction {
+                    Some(instruction) if is_call => {
+                        let return_address = get_physical_address(segment, machine.instruction_pointer.wrapping_add(instruction.size as u16));
+                        let stack_pointer = machine.registers.get_register_value(SP, &RegisterAccess::Full);
+                        self.resume(machine, Some((return_address, stack_pointer)), &mut output);
+                    },
+                    _ => { self.step_once(machine, true, &mut output); },
+                }
+                Ok(())
+            },
+
+            ("c" | "continue", _) => {
+                self.resume(machine, None, &mut output);
+                Ok(())
+            },
+
+            ("b" | "break", [ location ]) => self.parse_location(location, machine).map(|address| {
+                let number = self.add_point(Point::Breakpoint(address));
+                writeln!(output, "breakpoint {} at {:#07x}", number, address).unwrap();
+            }),
+
+            ("w" | "watch", [ name ]) if RegisterName::parse(name).is_some() => {
+                let watch = Watch::Register(RegisterName::parse(name).expect("checked by the guard"));
+                writeln!(output, "watchpoint {} on {}", self.next_point_number, watch).unwrap();
+                self.add_point(Point::Watchpoint(watch));
+                Ok(())
+            },
+            ("w" | "watch", [ address, length @ .. ]) if length.len() <= 1 => {
+                let length = match length.first() {
+                    Some(length) => parse_number(length).filter(|length| *length > 0).ok_or(format!("invalid length {}", length)),
+                    None => Ok(1),
+                };
+                length.and_then(|length| {
+                    let (segment, offset) = self.parse_address(address, machine, DS)?;
+                    let watch = Watch::Memory { segment, offset, length };
+                    writeln!(output, "watchpoint {} on {}", self.next_point_number, watch).unwrap();
+                    self.add_point(Point::Watchpoint(watch));
+                    Ok(())
+                })
+            },
+
+            ("d" | "delete", []) => {
+                self.points.clear();
+                Ok(())
+            },
+            ("d" | "delete", [ number ]) => match number.parse::<usize>().ok().and_then(|number| self.points.remove(&number)) {
+                Some(_) => Ok(()),
+                None => Err(format!("no breakpoint or watchpoint {}", number)),
+            },
+
+            ("i" | "info", _) => {
+                if self.points.is_empty() { output.push_str("no breakpoints or watchpoints\n"); }
+                for (number, point) in &self.points {
+                    match point {
+                        Point::Breakpoint(address) => match self.labels.get(address) {
+                            Some(label) => writeln!(output, "{}  breakpoint at {:#07x} <{}>", number, address, label),
+                            None => writeln!(output, "{}  breakpoint at {:#07x}", number, address),
+                        },
+                        Point::Watchpoint(watch) => writeln!(output, "{}  watchpoint on {}", number, watch),
+                    }.unwrap();
+                }
+                Ok(())
+            },
+
+            ("r" | "registers", _) => {
+                self.print_registers(machine, &mut output);
+                Ok(())
+            },
+
+            ("set", [ name, value ]) => match (RegisterName::parse(name), parse_number(value)) {
+                (Some(register), Some(value)) => {
+                    register.write(machine, value);
+                    Ok(())
+                },
+                (None, _) => Err(format!("unknown register {}", name)),
+                (_, None) => Err(format!("invalid value {}", value)),
+            },
+
+            ("l" | "list", arguments) => match arguments.first().map(|count| parse_number(count)) {
+                Some(None) => Err(format!("invalid count {}", arguments[0])),
+                count => {
+                    self.list(machine, count.flatten().unwrap_or(8) as usize, &mut output);
+                    Ok(())
+                },
+            },
+
+            (command, arguments) if command == "x" || command.starts_with("x/") => {
+                self.examine(machine, command.strip_prefix("x/").unwrap_or(""), arguments.first().copied(), &mut output)
+            },
+
+            _ => Err(format!("unknown command {}, try help", line)),
+        };
+
+        if let Err(message) = result { writeln!(output, "{}", message).unwrap(); }
+        Some(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::get_labels;
+
+    // mov cx, 3 / label_0: dec cx / mov [0x100], cx / jne label_0
+    const PROGRAM: [u8; 10] = [ 0xb9, 0x03, 0x00, 0x49, 0x89, 0x0e, 0x00, 0x01, 0x75, 0xf9 ];
+
+    fn start() -> (Debugger, Machine) {
+        let mut machine = Machine::new();
+        machine.load_program(&PROGRAM);
+        (Debugger::new(get_labels(&PROGRAM)), machine)
+    }
+
+    #[test]
+    fn breakpoints_by_label_stop_every_iteration() {
+        let (mut debugger, mut machine) = start();
+        assert_eq!(debugger.run_command(&mut machine, "b label_0").unwrap(), "breakpoint 1 at 0x00003\n");
+        assert_eq!(debugger.run_command(&mut machine, "c").unwrap(), "breakpoint 1\n=> 0000:0003 <label_0>  dec cx\n");
+        // an empty line repeats the last command
+        debugger.run_command(&mut machine, "");
+        assert_eq!(machine.registers.get_register_value(1, &RegisterAccess::Full), 2);
+
+        debugger.run_command(&mut machine, "delete 1");
+        assert_eq!(debugger.run_command(&mut machine, "continue").unwrap(), "program stopped: halted\n=> 0000:000a  hlt\n");
+    }
+
+    #[test]
+    fn watchpoints_and_inspection() {
+        let (mut debugger, mut machine) = start();
+        debugger.run_command(&mut machine, "watch 0x100 2");
+        assert_eq!(
+            debugger.run_command(&mut machine, "c").unwrap(),
+            "watchpoint 1 0000:0100 (2 bytes): 00 00 -> 02 00\n=> 0000:0008  jne label_0\n"
+        );
+        assert_eq!(debugger.run_command(&mut machine, "x/2xw 0x100").unwrap(), "0000:0100  0002 0000\n");
+
+        debugger.run_command(&mut machine, "set cl 0x7f");
+        debugger.run_command(&mut machine, "w cx");
+        assert_eq!(debugger.run_command(&mut machine, "next").unwrap(), "0000:0008  jne $-5 ; Clocks: +16 = 37 | ip:0x8->0x3\n");
+        assert!(debugger.run_command(&mut machine, "s").unwrap().ends_with("watchpoint 2 cx: 0x007f -> 0x007e\n"));
+
+        assert_eq!(debugger.run_command(&mut machine, "list 2").unwrap(), concat!(
+            "   0000:0004  mov [256], cx\n",
+            "   0000:0008  jne label_0\n",
+            "label_0:\n",
+            "   0000:0003  dec cx\n",
+            "=> 0000:0004  mov [256], cx\n",
+            "   0000:0008  jne label_0\n",
+        ));
+    }
+}
@@ -1,5 +1,8 @@
 use std::fmt;
 
+pub(crate) mod table;
+use table::*;
+
 const REGISTER_NAMES: [[&str; 2]; 8] = [
     ["al", "ax"],
     ["cl", "cx"],
@@ -15,6 +18,19 @@ pub fn get_register_name(reg: u8, wide: bool) -> Option<&'static str> {
     if reg > 7 { None } else { Some(REGISTER_NAMES[reg as usize][wide as usize]) }
 }
 
+const SEGMENT_REGISTER_NAMES: [&str; 4] = [ "es", "cs", "ss", "ds" ];
+
+// segment register encodings
+pub const ES: u8 = 0b00;
+pub const CS: u8 = 0b01;
+pub const SS: u8 = 0b10;
+pub const DS: u8 = 0b11;
+
+pub fn get_segment_register_name(reg: u8) -> Option<&'static str> {
+    if reg > 3 { None } else { Some(SEGMENT_REGISTER_NAMES[reg as usize]) }
+}
+
+#[derive(Clone, Copy, PartialEq)]
 pub enum RegisterAccess { Low, High, Full, }
 
 impl RegisterAccess {
@@ -30,6 +46,7 @@ impl RegisterAccess {
 }
 
 #[allow(non_camel_case_types)]
+#[derive(Clone, Copy)]
 pub enum EffectiveAddressBase {
     BX_SI,
     BX_DI,
@@ -74,15 +91,17 @@ impl fmt::Display for EffectiveAddressBase {
 
 // TODO maybe instead of having an effective address base we just store the two registers we're
 // using since we can pull the encodings right from them?
+#[derive(Clone, Copy)]
 pub enum EffectiveAddress {
-    Direct(u16),
-    Calculated { base: EffectiveAddressBase, displacement: u16 },
+    Direct { address: u16, segment_override: Option<u8> },
+    // displacement_size is the number of displacement bytes encoded, nasm uses the fewest that fit
+    Calculated { base: EffectiveAddressBase, displacement: u16, displacement_size: u8, segment_override: Option<u8> },
 }
 
 impl EffectiveAddress {
     fn new(mode: u8, encoding: u8, displacement: u16) -> Self {
         if mode == 0 && encoding == 0b110 {
-            Self::Direct(displacement)
+            Self::Direct { address: displacement, segment_override: None }
         } else {
             let base = match encoding {
                 0b000 => EffectiveAddressBase::BX_SI,
@@ -96,16 +115,38 @@ impl EffectiveAddress {
                 _ => panic!("Invalid effective address encoding: {:#b}", encoding)
             };
 
-            Self::Calculated { base, displacement }
+            // mod 00, 01 and 10 have 0, 1 and 2 displacement bytes
+            Self::Calculated { base, displacement, displacement_size: mode, segment_override: None }
+        }
+    }
+
+    pub fn get_segment_override(&self) -> Option<u8> {
+        match self {
+            Self::Direct { segment_override, .. } | Self::Calculated { segment_override, .. } => *segment_override,
+        }
+    }
+
+    fn set_segment_override(&mut self, segment: u8) {
+        match self {
+            Self::Direct { segment_override, .. } | Self::Calculated { segment_override, .. } => *segment_override = Some(segment),
         }
     }
 
-    fn get_clocks_estimate(&self) -> u16 {
-        // if implemented, add 2 clocks when a segment override is present
+    // segment register the address is relative to, bp based addressing defaults to the stack segment
+    pub fn get_segment(&self) -> u8 {
+        if let Some(segment) = self.get_segment_override() { return segment; }
+
         match self {
-            Self::Direct(_) => 6,
-            // TODO need to add 4 clocks for each 16-bit word transfer w/ odd address (see manual)
-            Self::Calculated { base, displacement } => match base {
+            Self::Calculated { base: EffectiveAddressBase::BP | EffectiveAddressBase::BP_SI | EffectiveAddressBase::BP_DI, .. } => SS,
+            _ => DS,
+        }
+    }
+
+    fn get_clocks_estimate(&self) -> u32 {
+        let segment_override_clocks = if self.get_segment_override().is_some() { 2 } else { 0 };
+        segment_override_clocks + match self {
+            Self::Direct { .. } => 6,
+            Self::Calculated { base, displacement, .. } => match base {
                 EffectiveAddressBase::BX
                 | EffectiveAddressBase::BP
                 | EffectiveAddressBase::SI
@@ -126,23 +167,35 @@ impl EffectiveAddress {
 
 impl fmt::Display for EffectiveAddress {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let segment_prefix = match self.get_segment_override() {
+            Some(segment) => format!("{}:", get_segment_register_name(segment).unwrap()),
+            None => String::new(),
+        };
+
         match self {
-            Self::Direct(address) => write!(formatter, "[{}]", address),
-            Self::Calculated { base, displacement } => {
-                let displacement = *displacement;
-                if displacement == 0 { return write!(formatter, "[{}]", base); }
-
-                let [disp_hi, disp_lo] = displacement.to_be_bytes();
-                let is_wide = disp_hi != 0;
-                let is_disp_negative = if is_wide { disp_hi.rotate_left(1) & 1 == 1 } else { disp_lo.rotate_left(1) & 1 == 1 };
-                let disp_sign = if is_disp_negative { "-" } else { "+" };
-                let disp_display_val = if is_disp_negative {
-                    if is_wide { displacement.wrapping_neg() } else { disp_lo.wrapping_neg() as u16 }
+            Self::Direct { address, .. } => write!(formatter, "[{}{}]", segment_prefix, address),
+            Self::Calculated { base, displacement, displacement_size, .. } => {
+                let displacement = *displacement as i16;
+                // [bp] has no form without a displacement
+                let fewest_bytes = if displacement == 0 && !matches!(base, EffectiveAddressBase::BP) {
+                    0
+                } else if (-128..=127).contains(&displacement) {
+                    1
                 } else {
-                    displacement
+                    2
+                };
+                // a displacement that's longer than it has to be is forced with nasm's byte or word
+                let size_specifier = match (*displacement_size == fewest_bytes, displacement_size) {
+                    (true, _) => "",
+                    (false, 1) => "byte ",
+                    (false, _) => "word ",
                 };
+                if displacement == 0 && size_specifier.is_empty() { return write!(formatter, "[{}{}]", segment_prefix, base); }
 
-                write!(formatter, "[{} {} {}]", base, disp_sign, disp_display_val)
+                let disp_sign = if displacement < 0 { "-" } else { "+" };
+                let disp_display_val = displacement.unsigned_abs();
+
+                write!(formatter, "[{}{}{} {} {}]", size_specifier, segment_prefix, base, disp_sign, disp_display_val)
             }
         }
     }
@@ -156,9 +209,11 @@ impl fmt::Display for EffectiveAddress {
 // invariants to say that SP, BP, SI, DI can only ever be full access.
 pub enum Operand {
     Register(u8, RegisterAccess),
+    SegmentRegister(u8),
     Memory(EffectiveAddress),
     ImmediateData(u16),
-    LabelOffset(i8), // instruction pointer increment
+    LabelOffset(i16), // instruction pointer increment
+    FarAddress { segment: u16, offset: u16 }, // absolute cs:ip of an intersegment jmp or call
 }
 
 impl Operand {
@@ -181,9 +236,11 @@ impl fmt::Display for Operand {
                 RegisterAccess::High => get_register_name(*encoding, false).unwrap(),
                 RegisterAccess::Low => get_register_name(*encoding, false).unwrap(),
             }),
+            Operand::SegmentRegister(encoding) => write!(formatter, "{}", get_segment_register_name(*encoding).unwrap()),
             Operand::Memory(effective_address) => write!(formatter, "{}", effective_address),
             Operand::ImmediateData(data) => write!(formatter, "{}", data),
             Operand::LabelOffset(offset) => write!(formatter, "{}", offset),
+            Operand::FarAddress { segment, offset } => write!(formatter, "{}:{}", segment, offset),
         }
     }
 }
@@ -195,17 +252,35 @@ pub struct InstructionFlags {
     pub wide: bool,
     pub destination: bool,
     pub v: bool, // false - shift/rotate count is 1, true - specified in CL reg
-    pub repeat_on_zero: bool,
+    pub repeat: bool, // string instruction has a rep prefix
+    pub repeat_on_zero: bool, // z bit of the rep prefix, only meaningful for cmps and scas
+    pub segment_override: Option<u8>, // segment register encoding of a segment override prefix
+    pub lock: bool, // lock prefix, the bus is locked for the instruction
 }
 
 #[allow(non_camel_case_types)]
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum Operation {
     Mov_RegMem_ToFrom_Reg,
     Mov_Imm_To_RegMem,
     Mov_Imm_To_Reg,
     Mov_Mem_To_Acc,
     Mov_Acc_To_Mem,
+    Mov_RegMem_To_SegReg,
+    Mov_SegReg_To_RegMem,
+    Xchg_RegMem_With_Reg,
+    Xchg_Reg_With_Acc,
+    Nop, // xchg ax, ax
+    Load_EA_To_Reg, // lea
+    Load_Pointer_To_DS, // lds
+    Load_Pointer_To_ES, // les
+    Translate, // xlatb
+    Load_AH_With_Flags, // lahf
+    Store_AH_Into_Flags, // sahf
+    In_Fixed_Port,
+    In_Variable_Port, // port in dx
+    Out_Fixed_Port,
+    Out_Variable_Port, // port in dx
 
     Add_RegMem_With_Reg_To_Either,
     Add_Imm_To_RegMem,
@@ -219,6 +294,100 @@ pub enum Operation {
     Cmp_Imm_With_RegMem,
     Cmp_Imm_With_Acc,
 
+    Adc_RegMem_With_Reg_To_Either,
+    Adc_Imm_To_RegMem,
+    Adc_Imm_To_Acc,
+
+    Sbb_RegMem_And_Reg_From_Either,
+    Sbb_Imm_From_RegMem,
+    Sbb_Imm_From_Acc,
+
+    And_RegMem_With_Reg_To_Either,
+    And_Imm_To_RegMem,
+    And_Imm_To_Acc,
+
+    Or_RegMem_And_Reg_To_Either,
+    Or_Imm_To_RegMem,
+    Or_Imm_To_Acc,
+
+    Xor_RegMem_And_Reg_To_Either,
+    Xor_Imm_To_RegMem,
+    Xor_Imm_To_Acc,
+
+    Test_RegMem_And_Reg,
+    Test_Imm_And_RegMem,
+    Test_Imm_And_Acc,
+
+    Inc_RegMem,
+    Inc_Reg,
+    Dec_RegMem,
+    Dec_Reg,
+    Neg, // change sign
+    Not, // invert
+
+    Mul, // multiply (unsigned)
+    Imul, // integer multiply (signed)
+    Div, // divide (unsigned)
+    Idiv, // integer divide (signed)
+    Cbw, // convert byte to word
+    Cwd, // convert word to double word
+    Aaa, // ascii adjust for add
+    Aas, // ascii adjust for subtract
+    Aam, // ascii adjust for multiply
+    Aad, // ascii adjust for divide
+    Daa, // decimal adjust for add
+    Das, // decimal adjust for subtract
+
+    Shl, // shift logical left, same as sal
+    Shr, // shift logical right
+    Sar, // shift arithmetic right
+    Rol, // rotate left
+    Ror, // rotate right
+    Rcl, // rotate through carry left
+    Rcr, // rotate through carry right
+
+    Movs, // movsb, movsw
+    Cmps, // cmpsb, cmpsw
+    Scas, // scasb, scasw
+    Lods, // lodsb, lodsw
+    Stos, // stosb, stosw
+
+    Clear_Direction, // cld
+    Set_Direction, // std
+    Clear_Carry, // clc
+    Set_Carry, // stc
+    Complement_Carry, // cmc
+    Clear_Interrupt, // cli
+    Set_Interrupt, // sti
+
+    Push_RegMem,
+    Push_Reg,
+    Push_SegReg,
+    Pop_RegMem,
+    Pop_Reg,
+    Pop_SegReg,
+    Push_Flags, // pushf
+    Pop_Flags, // popf
+
+    Call_Direct_Within_Segment,
+    Call_Indirect_Within_Segment,
+    Ret_Within_Segment,
+    Ret_Within_Segment_Imm, // ret adding immediate to sp
+
+    Jmp_Direct_Within_Segment_Short,
+    Jmp_Direct_Within_Segment,
+    Jmp_Indirect_Within_Segment,
+    Jmp_Direct_Intersegment,
+    Jmp_Indirect_Intersegment,
+    Call_Direct_Intersegment,
+    Call_Indirect_Intersegment,
+    Ret_Intersegment,
+    Ret_Intersegment_Imm, // retf adding immediate to sp
+
+    Interrupt, // int
+    Interrupt_Type_3, // int3, the one byte breakpoint
+    Interrupt_Return, // iret
+
     Jmp_On_Equal, // je
     Jmp_On_Less, // jl
     Jmp_On_Less_Or_Equal, // jle
@@ -244,6 +413,24 @@ pub enum Operation {
     Halt, // hlt
 }
 
+impl Operation {
+    pub fn is_string(&self) -> bool {
+        matches!(self, Self::Movs | Self::Cmps | Self::Scas | Self::Lods | Self::Stos)
+    }
+
+    // indirect intersegment transfers read a 32 bit offset:segment pointer from memory
+    pub fn is_indirect_intersegment(&self) -> bool {
+        matches!(self, Self::Jmp_Indirect_Intersegment | Self::Call_Indirect_Intersegment)
+    }
+
+    pub fn is_shift_or_rotate(&self) -> bool {
+        matches!(
+            self,
+            Self::Shl | Self::Shr | Self::Sar | Self::Rol | Self::Ror | Self::Rcl | Self::Rcr
+        )
+    }
+}
+
 pub struct Instruction {
     pub operation: Operation,
     pub operands: [Option<Operand>; 2], // e.g. opcode operand_1, operand_2 (max 2 operands)
@@ -251,158 +438,226 @@ pub struct Instruction {
     pub size: u8,
 }
 
-type ClockEstimate = u16;
+// Information only known while executing that some clock estimates depend on
+#[derive(Default)]
+pub struct ClockContext {
+    pub shift_count: u16, // value of CL for shifts and rotates with v set
+    pub repetitions: u32, // number of times a string instruction with a rep prefix was repeated
+    pub jump_taken: bool, // whether a conditional jump or loop jumped
+    pub penalized_transfers: u32, // word transfers that took an extra bus cycle, 4 clocks each
+}
+
+// a rep string instruction can repeat 65535 times, far more clocks than fit in a u16
+type ClockEstimate = u32;
 type ClockExplanation = String;
-fn get_ea_clocks_and_explanation(base_clocks: u16, ea: &EffectiveAddress) -> (ClockEstimate, Option<ClockExplanation>) {
+fn get_ea_clocks_and_explanation(base_clocks: u32, ea: &EffectiveAddress) -> (ClockEstimate, Option<ClockExplanation>) {
     let ea_clocks = ea.get_clocks_estimate();
     let clocks = base_clocks + ea_clocks;
     (clocks, Some(format!("{} + {}ea", base_clocks, ea_clocks)))
 }
 
+// The manual only gives a range for instructions whose timing depends on the data. The estimate is
+// the best case, the explanation shows the whole range.
+fn get_clock_range_and_explanation(min_clocks: u32, max_clocks: u32, ea: Option<&EffectiveAddress>) -> (ClockEstimate, Option<ClockExplanation>) {
+    match ea {
+        Some(ea) => {
+            let ea_clocks = ea.get_clocks_estimate();
+            (min_clocks + ea_clocks, Some(format!("{}-{} + {}ea", min_clocks, max_clocks, ea_clocks)))
+        },
+        None => (min_clocks, Some(format!("{}-{}", min_clocks, max_clocks))),
+    }
+}
+
 impl Instruction {
-    pub fn get_clocks_estimate(&self) -> (ClockEstimate, Option<ClockExplanation>) {
-        match self.operation {
-            Operation::Mov_Acc_To_Mem | Operation::Mov_Mem_To_Acc => (10, None),
-            Operation::Mov_Imm_To_Reg => (4, None),
-            Operation::Mov_Imm_To_RegMem => match &self.operands[0] {
-                Some(Operand::Register(..)) => (4, None),
-                Some(Operand::Memory(ea)) => get_ea_clocks_and_explanation(10, ea),
-                _ => panic!("other operand combos not supported for this operation"),
+    pub fn get_clocks_estimate(&self, context: &ClockContext) -> (ClockEstimate, Option<ClockExplanation>) {
+        let (clocks, explanation) = self.get_base_clocks_estimate(context);
+        if context.penalized_transfers == 0 { return (clocks, explanation); }
+
+        let penalty_clocks = 4 * context.penalized_transfers;
+        let explanation = format!("{} + {}p", explanation.unwrap_or_else(|| clocks.to_string()), penalty_clocks);
+        (clocks + penalty_clocks, Some(explanation))
+    }
+
+    fn get_base_clocks_estimate(&self, context: &ClockContext) -> (ClockEstimate, Option<ClockExplanation>) {
+        // an instruction has at most one memory operand
+        let memory_operand = self.operands.iter().enumerate().find_map(|(index, operand)| match operand {
+            Some(Operand::Memory(ea)) => Some((index, ea)),
+            _ => None,
+        });
+
+        match get_encoding(&self.operation).clocks {
+            ClockFormula::Fixed(clocks) => (clocks, None),
+            ClockFormula::RegMem { register, memory } => match memory_operand {
+                Some((_, ea)) => get_ea_clocks_and_explanation(memory, ea),
+                None => (register, None),
             },
-            Operation::Mov_RegMem_ToFrom_Reg => match &self.operands {
-                [ Some(Operand::Register(..)), Some(Operand::Register(..)) ] => (2, None),
-                [ Some(Operand::Register(..)), Some(Operand::Memory(ea)) ] => get_ea_clocks_and_explanation(8, ea),
-                [ Some(Operand::Memory(ea)), Some(Operand::Register(..)) ] => get_ea_clocks_and_explanation(9, ea),
-                _ => panic!("other operand combos not supported for this operation"),
+            ClockFormula::RegMemByWidth { byte_register, word_register, memory } => match memory_operand {
+                Some((_, ea)) => get_ea_clocks_and_explanation(memory, ea),
+                None => (if self.flags.wide { word_register } else { byte_register }, None),
             },
-
-            Operation::Add_Imm_To_Acc => (4, None),
-            Operation::Add_Imm_To_RegMem => {
-                let [ dst_op, _ ] = &self.operands;
-                match dst_op {
-                    Some(Operand::Register(..)) => (4, None),
-                    Some(Operand::Memory(ea)) => get_ea_clocks_and_explanation(17, ea),
-                    _ => panic!("other ops not supported for this operation. how did this happen?")
-                }
+            ClockFormula::RegMemReg { register, from_memory, to_memory } => match memory_operand {
+                Some((0, ea)) => get_ea_clocks_and_explanation(to_memory, ea),
+                Some((_, ea)) => get_ea_clocks_and_explanation(from_memory, ea),
+                None => (register, None),
+            },
+            ClockFormula::MemoryOnly(memory) => match memory_operand {
+                Some((_, ea)) => get_ea_clocks_and_explanation(memory, ea),
+                None => panic!("other operand combos not supported for this operation"),
             },
-            Operation::Add_RegMem_With_Reg_To_Either => {
-                match &self.operands {
-                    [ Some(Operand::Register(..)), Some(Operand::Register(..)) ] => (3, None),
-                    [ Some(Operand::Register(..)), Some(Operand::Memory(ea)) ] => get_ea_clocks_and_explanation(9, ea),
-                    [ Some(Operand::Memory(ea)), Some(Operand::Register(..)) ] => get_ea_clocks_and_explanation(16, ea),
-                    _ => panic!("other operand combos not supported for this operation")
+            ClockFormula::Range { register, memory } => {
+                let width = self.flags.wide as usize;
+                match memory_operand {
+                    Some((_, ea)) => get_clock_range_and_explanation(memory[width][0], memory[width][1], Some(ea)),
+                    None => get_clock_range_and_explanation(register[width][0], register[width][1], None),
                 }
             },
-
-            Operation::Sub_Imm_From_Acc => (4, None),
-            Operation::Sub_Imm_From_RegMem => match &self.operands[0] {
-                Some(Operand::Register(..)) => (4, None),
-                Some(Operand::Memory(ea)) => get_ea_clocks_and_explanation(17, ea),
-                _ => panic!("other operand combos not supported for this operation"),
+            ClockFormula::Shift { register, memory, register_by_cl, memory_by_cl } => {
+                let bit_clocks = 4 * u32::from(context.shift_count);
+                match (memory_operand, self.flags.v) {
+                    (None, false) => (register, None),
+                    (Some((_, ea)), false) => get_ea_clocks_and_explanation(memory, ea),
+                    (None, true) => (
+                        register_by_cl + bit_clocks,
+                        Some(format!("{} + 4*{}bit", register_by_cl, context.shift_count))
+                    ),
+                    (Some((_, ea)), true) => {
+                        let ea_clocks = ea.get_clocks_estimate();
+                        (
+                            memory_by_cl + ea_clocks + bit_clocks,
+                            Some(format!("{} + {}ea + 4*{}bit", memory_by_cl, ea_clocks, context.shift_count))
+                        )
+                    },
+                }
             },
-            Operation::Sub_RegMem_And_Reg_From_Either => match &self.operands {
-                [ Some(Operand::Register(..)), Some(Operand::Register(..)) ] => (3, None),
-                [ Some(Operand::Register(..)), Some(Operand::Memory(ea)) ] => get_ea_clocks_and_explanation(9, ea),
-                [ Some(Operand::Memory(ea)), Some(Operand::Register(..)) ] => get_ea_clocks_and_explanation(16, ea),
-                _ => panic!("other operand combos not supported for this operation"),
+            ClockFormula::Branch { taken, not_taken } => (if context.jump_taken { taken } else { not_taken }, None),
+            ClockFormula::String { single, per_repetition } => if self.flags.repeat {
+                (
+                    9 + per_repetition * context.repetitions,
+                    Some(format!("9 + {}*{}rep", per_repetition, context.repetitions))
+                )
+            } else {
+                (single, None)
             },
+        }
+    }
+}
 
-            Operation::Cmp_Imm_With_Acc => (4, None),
-            Operation::Cmp_Imm_With_RegMem => match &self.operands[0] {
-                Some(Operand::Register(..)) => (4, None),
-                Some(Operand::Memory(ea)) => get_ea_clocks_and_explanation(10, ea),
-                _ => panic!("other operand combos not supported for this operation"),
-            },
-            Operation::Cmp_RegMem_And_Reg => match &self.operands {
-                [ Some(Operand::Register(..)), Some(Operand::Register(..)) ] => (3, None),
-                [ Some(Operand::Register(..)), Some(Operand::Memory(ea)) ] => get_ea_clocks_and_explanation(9, ea),
-                [ Some(Operand::Memory(ea)), Some(Operand::Register(..)) ] => get_ea_clocks_and_explanation(9, ea),
-                _ => panic!("other operand combos not supported for this operation"),
+impl Instruction {
+    // Clocks as shown in a listing, parts that depend on values only known while executing are left
+    // as a count per unit
+    pub fn get_clocks_description(&self) -> String {
+        let ea_clocks = self.operands.iter().find_map(|operand| match operand {
+            Some(Operand::Memory(ea)) => Some(ea.get_clocks_estimate()),
+            _ => None,
+        });
+
+        match (&get_encoding(&self.operation).clocks, ea_clocks) {
+            (ClockFormula::Branch { taken, not_taken }, _) => format!("{}/{}", taken, not_taken),
+            (ClockFormula::Shift { register_by_cl, .. }, None) if self.flags.v => format!("{} + 4/bit", register_by_cl),
+            (ClockFormula::Shift { memory_by_cl, .. }, Some(ea_clocks)) if self.flags.v => format!("{} + {}ea + 4/bit", memory_by_cl, ea_clocks),
+            (ClockFormula::String { per_repetition, .. }, _) if self.flags.repeat => format!("9 + {}/rep", per_repetition),
+            (clocks, _) => match self.get_clocks_estimate(&ClockContext::default()) {
+                (_, Some(range)) if matches!(clocks, ClockFormula::Range { .. }) => range,
+                (clocks, Some(explanation)) => format!("{} ({})", clocks, explanation),
+                (clocks, None) => clocks.to_string(),
             },
-
-
-            Operation::Jmp_On_Equal
-            | Operation::Jmp_On_Less
-            | Operation::Jmp_On_Less_Or_Equal
-            | Operation::Jmp_On_Below
-            | Operation::Jmp_On_Below_Or_Equal
-            | Operation::Jmp_On_Greater
-            | Operation::Jmp_On_Above
-            | Operation::Jmp_On_Parity
-            | Operation::Jmp_On_Overflow
-            | Operation::Jmp_On_Sign
-            | Operation::Jmp_On_Not_Equal
-            | Operation::Jmp_On_Not_Less
-            | Operation::Jmp_On_Not_Below
-            | Operation::Jmp_On_Not_Parity
-            | Operation::Jmp_On_Not_Overflow
-            | Operation::Jmp_On_Not_Sign
-            | Operation::Jmp_On_CX_Zero
-            => (4, None),
-
-            Operation::Loop => (5, None),
-            Operation::Loop_While_Zero => (6, None),
-            Operation::Loop_While_Not_Zero => (5, None),
-            
-            Operation::Halt => (2, None),
         }
     }
 }
 
 impl fmt::Display for Instruction {
-    #[allow(unused_variables)]
-    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        let op_name = match self.operation {
-            Operation::Mov_RegMem_ToFrom_Reg
-            | Operation::Mov_Imm_To_RegMem
-            | Operation::Mov_Imm_To_Reg
-            | Operation::Mov_Mem_To_Acc
-            | Operation::Mov_Acc_To_Mem
-                => "mov",
-
-            Operation::Add_RegMem_With_Reg_To_Either
-            | Operation::Add_Imm_To_RegMem
-            | Operation::Add_Imm_To_Acc
-                => "add",
-
-            Operation::Sub_RegMem_And_Reg_From_Either
-            | Operation::Sub_Imm_From_RegMem
-            | Operation::Sub_Imm_From_Acc
-                => "sub",
-
-            Operation::Cmp_RegMem_And_Reg
-            | Operation::Cmp_Imm_With_RegMem
-            | Operation::Cmp_Imm_With_Acc
-                => "cmp",
-            
-            Operation::Jmp_On_Equal => "je",
-            Operation::Jmp_On_Less => "jl",
-            Operation::Jmp_On_Less_Or_Equal => "jle",
-            Operation::Jmp_On_Below =>  "jb",
-            Operation::Jmp_On_Below_Or_Equal =>  "jbe",
-            Operation::Jmp_On_Greater =>  "jg",
-            Operation::Jmp_On_Above =>  "ja",
-            Operation::Jmp_On_Parity =>  "jp",
-            Operation::Jmp_On_Overflow =>  "jo",
-            Operation::Jmp_On_Sign =>  "js",
-            Operation::Jmp_On_Not_Equal =>  "jne",
-            Operation::Jmp_On_Not_Less =>  "jnl",
-            Operation::Jmp_On_Not_Below =>  "jnb",
-            Operation::Jmp_On_Not_Parity =>  "jnp",
-            Operation::Jmp_On_Not_Overflow =>  "jno",
-            Operation::Jmp_On_Not_Sign =>  "jns",
-            Operation::Jmp_On_CX_Zero =>  "jcxz",
-
-            Operation::Loop =>  "loop",
-            Operation::Loop_While_Zero =>  "loopz",
-            Operation::Loop_While_Not_Zero =>  "loopnz",
-
-            Operation::Halt => "hlt",
-        };
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result { self.write(formatter, None) }
+}
+
+// Displays an instruction with the target of its relative jump replaced by a label
+pub struct LabelledInstruction<'a> {
+    pub instruction: &'a Instruction,
+    pub label: &'a str,
+}
+
+impl fmt::Display for LabelledInstruction<'_> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result { self.instruction.write(formatter, Some(self.label)) }
+}
+
+impl Instruction {
+    // string instructions get a b or w suffix for their size
+    pub fn get_mnemonic(&self) -> String {
+        let mnemonic = get_encoding(&self.operation).mnemonic;
+        match (self.operation.is_string(), self.flags.wide) {
+            (true, true) => format!("{}w", mnemonic),
+            (true, false) => format!("{}b", mnemonic),
+            (false, _) => mnemonic.to_string(),
+        }
+    }
+
+    // whether an encoding of the same instruction takes a sign extended byte immediate
+    fn has_sign_extended_form(&self) -> bool {
+        let mnemonic = get_encoding(&self.operation).mnemonic;
+        INSTRUCTION_TABLE.iter()
+            .any(|encoding| encoding.mnemonic == mnemonic && encoding.bits.iter().any(|field| matches!(field, BitField::S)))
+    }
+
+    fn write(&self, formatter: &mut fmt::Formatter, label: Option<&str>) -> fmt::Result {
+        let op_name = self.get_mnemonic();
+
+        if self.flags.lock { write!(formatter, "lock ")?; }
+        if self.flags.repeat {
+            let is_comparison = matches!(self.operation, Operation::Cmps | Operation::Scas);
+            let prefix = match (self.flags.repeat_on_zero, is_comparison) {
+                (true, true) => "repe",
+                (true, false) => "rep",
+                (false, _) => "repne",
+            };
+            write!(formatter, "{} ", prefix)?;
+        }
+
+        // segment overrides are shown inside the brackets of a memory operand, string instructions
+        // have no operands to show them on so they get the prefix instead
+        let has_memory_operand = self.operands.iter().any(|operand| matches!(operand, Some(Operand::Memory(_))));
+        if let (Some(segment), false) = (self.flags.segment_override, has_memory_operand) {
+            write!(formatter, "{} ", get_segment_register_name(segment).unwrap())?;
+        }
 
         match &self.operands {
-            [ None, None ] => todo!("printing for 0-operand instructions not implemented"),
+            [ None, None ] => write!(formatter, "{}", op_name),
+            [ Some(operand @ Operand::Memory(_)), None ] => {
+                let size_specifier = if self.operation.is_indirect_intersegment() {
+                    "far"
+                } else if self.flags.wide {
+                    "word"
+                } else {
+                    "byte"
+                };
+                write!(formatter, "{} {} {}", op_name, size_specifier, operand)
+            },
+            // nasm picks the short form of a jmp it can, near keeps a 16-bit displacement reassembling
+            // to the same bytes
+            [ Some(Operand::LabelOffset(offset)), None ] => {
+                let distance = if self.operation == Operation::Jmp_Direct_Within_Segment { "near " } else { "" };
+                match label {
+                    Some(label) => write!(formatter, "{} {}{}", op_name, distance, label),
+                    // relative to the start of the instruction like nasm's $
+                    None => write!(formatter, "{} {}${:+}", op_name, distance, *offset as i32 + self.size as i32),
+                }
+            },
             [ Some(operand), None ] => write!(formatter, "{} {}", op_name, operand),
+            // the count doesn't say anything about the size of the shifted value
+            [ Some(dst @ Operand::Memory(_)), Some(src) ] if self.operation.is_shift_or_rotate() => {
+                let size_specifier = if self.flags.wide { "word" } else { "byte" };
+                write!(formatter, "{} {} {}, {}", op_name, size_specifier, dst, src)
+            },
+            // a sign extended byte immediate is shown as the signed byte it's encoded as, the
+            // memory operand gets the size instead
+            [ Some(dst), Some(Operand::ImmediateData(data)) ] if self.flags.sign_extend && self.flags.wide => {
+                let size_specifier = if matches!(dst, Operand::Memory(_)) { "word " } else { "" };
+                write!(formatter, "{} {}{}, byte {}", op_name, size_specifier, dst, *data as i16)
+            },
+            // a word immediate that fits in a sign extended byte is only kept a word by nasm with strict
+            [ Some(dst), Some(Operand::ImmediateData(data)) ]
+                if self.flags.wide && (-128..=127).contains(&(*data as i16)) && self.has_sign_extended_form() =>
+            {
+                write!(formatter, "{} {}, strict word {}", op_name, dst, data)
+            },
             [ Some(dst @ Operand::Memory(_)), Some(src @ Operand::ImmediateData(_)) ] => {
                 let size_specifier = if self.flags.wide { "word" } else { "byte" };
                 write!(formatter, "{} {}, {} {}", op_name, dst, size_specifier, src)
@@ -420,271 +675,450 @@ pub fn read_word(bytes: &[u8], at: usize) -> u16 {
     (hi as u16) << 8 | lo as u16
 }
 
-fn read_displacement(instruction_stream: &[u8], displacement_index: usize, mode: u8, reg_or_mem: u8) -> (u16, u8) {
-    if mode == 0b10 || mode == 0b00 && reg_or_mem == 0b110 {
-        (read_word(instruction_stream, displacement_index), 2)
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    // the instruction starting at `at` needs at least `needed` bytes but the stream ends before that
+    Truncated { at: usize, needed: usize },
+    UnknownOpcode { at: usize, byte: u8 },
+    // the mod reg r/m byte at `at` selects an operation or operand the opcode doesn't have
+    InvalidModRm { at: usize, byte: u8 },
+    // a rep prefix not followed by a string instruction, or a prefix that's been repeated
+    InvalidPrefix { at: usize, byte: u8 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Truncated { at, needed } => write!(formatter, "instruction at byte {:#x} is truncated, it needs at least {} bytes", at, needed),
+            Self::UnknownOpcode { at, byte } => write!(formatter, "unknown opcode {:#04x} at byte {:#x}", byte, at),
+            Self::InvalidModRm { at, byte } => write!(formatter, "invalid mod reg r/m byte {:#04x} at byte {:#x}", byte, at),
+            Self::InvalidPrefix { at, byte } => write!(formatter, "prefix {:#04x} at byte {:#x} doesn't apply to the instruction after it", byte, at),
+        }
+    }
+}
+
+// Bounds checked access to the bytes of the instruction starting at `start`, indices are relative
+// to the start of the instruction
+struct InstructionBytes<'a> { stream: &'a [u8], start: usize }
+impl InstructionBytes<'_> {
+    fn byte(&self, index: usize) -> Result<u8, DecodeError> {
+        self.stream.get(self.start + index)
+            .copied()
+            .ok_or(DecodeError::Truncated { at: self.start, needed: index + 1 })
+    }
+
+    fn word(&self, index: usize) -> Result<u16, DecodeError> {
+        let lo = self.byte(index)?;
+        let hi = self.byte(index + 1)?;
+        Ok((hi as u16) << 8 | lo as u16)
+    }
+
+    // only call once the mod reg r/m byte has been read
+    fn invalid_mod_rm(&self) -> DecodeError {
+        DecodeError::InvalidModRm { at: self.start + 1, byte: self.stream[self.start + 1] }
+    }
+}
+
+fn read_displacement(bytes: &InstructionBytes, displacement_index: usize, mode: u8, reg_or_mem: u8) -> Result<(u16, u8), DecodeError> {
+    Ok(if mode == 0b10 || mode == 0b00 && reg_or_mem == 0b110 {
+        (bytes.word(displacement_index)?, 2)
     } else if mode == 0b01 {
-        (instruction_stream[displacement_index] as u16, 1)
+        // an 8-bit displacement is sign extended
+        (bytes.byte(displacement_index)? as i8 as i16 as u16, 1)
     } else {
         (0, 0)
-    }
+    })
 }
 
-fn read_data(instruction_stream: &[u8], data_index: usize, is_word: bool) -> (u16, u8) {
-    if is_word {
-        (read_word(instruction_stream, data_index), 2)
+fn read_data(bytes: &InstructionBytes, data_index: usize, is_word: bool) -> Result<(u16, u8), DecodeError> {
+    Ok(if is_word {
+        (bytes.word(data_index)?, 2)
     } else {
-        (instruction_stream[data_index] as u16, 1)
-    }
-}
-
-pub fn decode_instruction(instruction_stream: &[u8], instruction_pointer: usize) -> Option<Instruction> {
-    let maybe_opcode = instruction_stream[instruction_pointer];
-    let operation: Option<Operation> = match maybe_opcode >> 4 {
-        0b1011 => Some(Operation::Mov_Imm_To_Reg),
-        _ => match maybe_opcode >> 2 {
-            0b100010 => Some(Operation::Mov_RegMem_ToFrom_Reg),
-            0b000000 => Some(Operation::Add_RegMem_With_Reg_To_Either),
-            0b001010 => Some(Operation::Sub_RegMem_And_Reg_From_Either),
-            0b001110 => Some(Operation::Cmp_RegMem_And_Reg),
-            0b100000 => match (instruction_stream[instruction_pointer + 1] & 0b111000) >> 3 {
-                0b000 => Some(Operation::Add_Imm_To_RegMem),
-                0b101 => Some(Operation::Sub_Imm_From_RegMem),
-                0b111 => Some(Operation::Cmp_Imm_With_RegMem),
-                _ => None
-            },
-            _ => match maybe_opcode >> 1 {
-                0b1100011 => Some(Operation::Mov_Imm_To_RegMem),
-                0b1010000 => Some(Operation::Mov_Mem_To_Acc),
-                0b1010001 => Some(Operation::Mov_Acc_To_Mem),
-                0b0000010 => Some(Operation::Add_Imm_To_Acc),
-                0b0010110 => Some(Operation::Sub_Imm_From_Acc),
-                0b0011110 => Some(Operation::Cmp_Imm_With_Acc),
-                _ => match maybe_opcode {
-                    0b01110100 => Some(Operation::Jmp_On_Equal),
-                    0b01111100 => Some(Operation::Jmp_On_Less),
-                    0b01111110 => Some(Operation::Jmp_On_Less_Or_Equal),
-                    0b01110010 => Some(Operation::Jmp_On_Below),
-                    0b01110110 => Some(Operation::Jmp_On_Below_Or_Equal),
-                    0b01111111 => Some(Operation::Jmp_On_Greater),
-                    0b01110111 => Some(Operation::Jmp_On_Above),
-                    0b01111010 => Some(Operation::Jmp_On_Parity),
-                    0b01110000 => Some(Operation::Jmp_On_Overflow),
-                    0b01111000 => Some(Operation::Jmp_On_Sign),
-                    0b01110101 => Some(Operation::Jmp_On_Not_Equal),
-                    0b01111101 => Some(Operation::Jmp_On_Not_Less),
-                    0b01110011 => Some(Operation::Jmp_On_Not_Below),
-                    0b01111011 => Some(Operation::Jmp_On_Not_Parity),
-                    0b01110001 => Some(Operation::Jmp_On_Not_Overflow),
-                    0b01111001 => Some(Operation::Jmp_On_Not_Sign),
-                    0b11100011 => Some(Operation::Jmp_On_CX_Zero),
-                    0b11100010 => Some(Operation::Loop),
-                    0b11100001 => Some(Operation::Loop_While_Zero),
-                    0b11100000 => Some(Operation::Loop_While_Not_Zero),
-
-                    0b11110100 => Some(Operation::Halt),
-
-                    _ => None
-                }
-            }
+        (bytes.byte(data_index)? as u16, 1)
+    })
+}
+
+pub fn decode_instruction(instruction_stream: &[u8], instruction_pointer: usize) -> Result<Instruction, DecodeError> {
+    // prefixes come before the opcode, at most one lock prefix (11110000), one rep prefix (1111001z)
+    // and one segment override prefix (001sr110). Positions are kept to report a prefix that doesn't
+    // apply.
+    let mut lock_prefix: Option<(usize, u8)> = None;
+    let mut rep_prefix: Option<(usize, u8)> = None;
+    let mut segment_override_prefix: Option<(usize, u8)> = None;
+    let mut opcode_index = instruction_pointer;
+    loop {
+        let byte = *instruction_stream.get(opcode_index)
+            .ok_or(DecodeError::Truncated { at: instruction_pointer, needed: opcode_index - instruction_pointer + 1 })?;
+        let prefix = if byte == 0b11110000 {
+            &mut lock_prefix
+        } else if byte >> 1 == 0b1111001 {
+            &mut rep_prefix
+        } else if byte & 0b11100111 == 0b00100110 {
+            &mut segment_override_prefix
+        } else {
+            break;
+        };
+        if let Some((at, byte)) = *prefix { return Err(DecodeError::InvalidPrefix { at, byte }); }
+        *prefix = Some((opcode_index, byte));
+        opcode_index += 1;
+    }
+
+    // truncation is reported relative to the first prefix
+    let prefix_count = opcode_index - instruction_pointer;
+    let mut instruction = decode_unprefixed_instruction(instruction_stream, opcode_index).map_err(|error| match error {
+        DecodeError::Truncated { needed, .. } => DecodeError::Truncated { at: instruction_pointer, needed: needed + prefix_count },
+        error => error,
+    })?;
+    instruction.size += prefix_count as u8;
+    instruction.flags.lock = lock_prefix.is_some();
+
+    // the rep prefix is only valid in front of a string instruction
+    if let Some((at, byte)) = rep_prefix {
+        if !instruction.operation.is_string() { return Err(DecodeError::InvalidPrefix { at, byte }); }
+        instruction.flags.repeat = true;
+        instruction.flags.repeat_on_zero = byte & 1 == 1;
+    }
+
+    // the segment override applies to the memory operand of the instruction
+    if let Some((_, byte)) = segment_override_prefix {
+        let segment = (byte >> 3) & 0b11;
+        for operand in instruction.operands.iter_mut().flatten() {
+            if let Operand::Memory(effective_address) = operand { effective_address.set_segment_override(segment); }
         }
-    };
+        instruction.flags.segment_override = Some(segment);
+    }
 
-    #[allow(clippy::question_mark)]
-    if operation.is_none() { return None; }
-    let operation = operation.unwrap();
+    Ok(instruction)
+}
 
-    match operation {
-        Operation::Mov_Imm_To_Reg => {
-            const BASE_INSTRUCTION_LENGTH: u8 = 1;
+fn decode_unprefixed_instruction(instruction_stream: &[u8], instruction_pointer: usize) -> Result<Instruction, DecodeError> {
+    let bytes = InstructionBytes { stream: instruction_stream, start: instruction_pointer };
+    let maybe_opcode = bytes.byte(0)?;
 
-            let params = instruction_stream[instruction_pointer];
+    // an opcode that matched some encoding whose later bits didn't has a mod reg r/m byte the
+    // opcode doesn't allow
+    let mut opcode_matched = false;
+    for encoding in INSTRUCTION_TABLE {
+        if let Some(instruction) = decode_encoding(encoding, &bytes, &mut opcode_matched)? {
+            return Ok(instruction);
+        }
+    }
 
-            let wide = (params >> 3) & 1 == 1;
-            let reg = params & 0b111;
-            let (data, data_length) = read_data(instruction_stream, instruction_pointer + 1, wide);
+    Err(if opcode_matched {
+        bytes.invalid_mod_rm()
+    } else {
+        DecodeError::UnknownOpcode { at: instruction_pointer, byte: maybe_opcode }
+    })
+}
 
-            let destination_operand = Operand::Register(reg, RegisterAccess::new(reg, wide));
-            let source_operand = Operand::ImmediateData(data);
-            let operands = [ Some(destination_operand), Some(source_operand) ];
-            let flags = InstructionFlags { wide, ..Default::default() };
+fn get_encoding(operation: &Operation) -> &'static InstructionEncoding {
+    INSTRUCTION_TABLE.iter()
+        .find(|encoding| encoding.operation == *operation)
+        .expect("every operation has an encoding in the instruction table")
+}
 
-            Some(Instruction { operation, operands, flags, size: BASE_INSTRUCTION_LENGTH + data_length })
-        },
+// Decodes the instruction as the given encoding, None if the instruction's bits don't match it
+fn decode_encoding(encoding: &InstructionEncoding, bytes: &InstructionBytes, opcode_matched: &mut bool) -> Result<Option<Instruction>, DecodeError> {
+    let mut flags = InstructionFlags::default();
+    let mut swap_operands = false;
+    // without a mod field there is no displacement to read
+    let mut mode = 0b11;
+    let mut reg = 0;
+    let mut reg_or_mem = 0;
+    let mut segment_register = 0;
+
+    let mut bit_index = 0;
+    for field in encoding.bits {
+        let bit_count = field.get_bit_count();
+        if bit_count == 0 {
+            flags.wide = true;
+            continue;
+        }
 
-        Operation::Mov_RegMem_ToFrom_Reg
-        | Operation::Add_RegMem_With_Reg_To_Either
-        | Operation::Sub_RegMem_And_Reg_From_Either
-        | Operation::Cmp_RegMem_And_Reg
-        => {
-            const BASE_INSTRUCTION_LENGTH: u8 = 2;
-
-            let params = instruction_stream[instruction_pointer];
-            let operands = instruction_stream[instruction_pointer + 1];
-
-            let dest = params & 0b10 > 0;
-            let wide = params & 1 == 1;
-            let mode = operands >> 6;
-            let reg = (operands & 0b111000) >> 3;
-            let reg_or_mem = operands & 0b111;
-            let (displacement, displacement_length) = read_displacement(
-                instruction_stream,
-                instruction_pointer + 2,
-                mode,
-                reg_or_mem
-            );
-
-            let flags = InstructionFlags { wide, destination: dest, ..Default::default() };
-
-            let mut destination_operand = Operand::Register(reg, RegisterAccess::new(reg, wide));
-            let mut source_operand = Operand::register_or_memory(mode, reg_or_mem, displacement, wide);
-
-            if !dest { std::mem::swap(&mut destination_operand, &mut source_operand); }
-            let operands = [ Some(destination_operand), Some(source_operand) ];
-
-            Some(Instruction { operation, operands, flags, size: BASE_INSTRUCTION_LENGTH + displacement_length })
-        },
+        let byte_index = bit_index / 8;
+        if byte_index > 0 { *opcode_matched = true; }
+        let shift = 8 - bit_index % 8 - bit_count as usize;
+        let value = (bytes.byte(byte_index)? >> shift) & (0xff >> (8 - bit_count));
+        match field {
+            BitField::Literal(_, expected) => if value != *expected { return Ok(None); },
+            BitField::D => {
+                flags.destination = value == 1;
+                swap_operands = value == 0;
+            },
+            BitField::W => flags.wide = value == 1,
+            BitField::S => flags.sign_extend = value == 1,
+            BitField::V => flags.v = value == 1,
+            BitField::Mod => mode = value,
+            BitField::Reg => reg = value,
+            BitField::Rm => reg_or_mem = value,
+            BitField::Sr => segment_register = value,
+            BitField::WideImplied => unreachable!(),
+        }
 
-        Operation::Add_Imm_To_RegMem
-        | Operation::Sub_Imm_From_RegMem
-        | Operation::Cmp_Imm_With_RegMem
-        => {
-            const BASE_INSTRUCTION_LENGTH: u8 = 2;
-
-            let params = instruction_stream[instruction_pointer];
-            let operands = instruction_stream[instruction_pointer + 1];
-
-            let sign_extend = params & 0b10 > 0;
-            let wide = params & 1 == 1;
-            let mode = operands >> 6;
-            let reg_or_mem = operands & 0b111;
-            let (displacement, displacement_length) = read_displacement(
-                instruction_stream,
-                instruction_pointer + 2,
-                mode,
-                reg_or_mem
-            );
-            let (data, data_length) = read_data(
-                instruction_stream,
-                instruction_pointer + 2 + displacement_length as usize,
-                !sign_extend && wide
-            );
-
-            let flags = InstructionFlags { sign_extend, wide, ..Default::default() };
-
-            let destination_operand = Operand::register_or_memory(mode, reg_or_mem, displacement, wide);
-            let source_operand = Operand::ImmediateData(data);
-            let operands = [ Some(destination_operand), Some(source_operand) ];
-
-            Some(Instruction { operation, operands, flags, size: BASE_INSTRUCTION_LENGTH + displacement_length + data_length })
-        },
+        bit_index += bit_count as usize;
+    }
 
-        Operation::Mov_Imm_To_RegMem => {
-            const BASE_INSTRUCTION_LENGTH: u8 = 2;
-
-            let params = instruction_stream[instruction_pointer];
-            let operands = instruction_stream[instruction_pointer + 1];
-
-            let wide = params & 1 == 1;
-            let mode = operands >> 6;
-            let reg_or_mem = operands & 0b111;
-            let (displacement, displacement_length) = read_displacement(
-                instruction_stream,
-                instruction_pointer + 2,
-                mode,
-                reg_or_mem
-            );
-            let (data, data_length) = read_data(
-                instruction_stream,
-                instruction_pointer + 2 + displacement_length as usize,
-                wide
-            );
-
-            let flags = InstructionFlags { wide, ..Default::default() };
-
-            let destination_operand = Operand::register_or_memory(mode, reg_or_mem, displacement, wide);
-            let source_operand = Operand::ImmediateData(data);
-            let operands = [ Some(destination_operand), Some(source_operand) ];
-
-            Some(Instruction { operation, operands, flags, size: BASE_INSTRUCTION_LENGTH + displacement_length + data_length })
-        },
+    // displacement comes right after the opcode and mod reg r/m bytes, then any data
+    let mut length = bit_index / 8;
+    let (displacement, displacement_length) = read_displacement(bytes, length, mode, reg_or_mem)?;
+    length += displacement_length as usize;
+
+    let wide = flags.wide;
+    let mut operands = [ None, None ];
+    for (operand, kind) in operands.iter_mut().zip(encoding.operands) {
+        *operand = Some(match kind {
+            OperandKind::Reg => Operand::Register(reg, RegisterAccess::new(reg, wide)),
+            OperandKind::RegMem => Operand::register_or_memory(mode, reg_or_mem, displacement, wide),
+            OperandKind::Memory => {
+                if mode == 0b11 { return Err(bytes.invalid_mod_rm()); }
+                Operand::register_or_memory(mode, reg_or_mem, displacement, wide)
+            },
+            OperandKind::SegReg => Operand::SegmentRegister(segment_register),
+            OperandKind::Acc => Operand::register_acc(wide),
+            OperandKind::Dx => Operand::Register(0b010, RegisterAccess::Full),
+            OperandKind::Imm => {
+                let (data, data_length) = read_data(bytes, length, !flags.sign_extend && wide)?;
+                length += data_length as usize;
+                // a single data byte is sign extended to 16 bits for word operations
+                Operand::ImmediateData(if flags.sign_extend && wide { data as u8 as i8 as i16 as u16 } else { data })
+            },
+            OperandKind::ImmByte | OperandKind::ImmWord => {
+                let (data, data_length) = read_data(bytes, length, matches!(kind, OperandKind::ImmWord))?;
+                length += data_length as usize;
+                Operand::ImmediateData(data)
+            },
+            OperandKind::Direct => {
+                let address = bytes.word(length)?;
+                length += 2;
+                Operand::Memory(EffectiveAddress::Direct { address, segment_override: None })
+            },
+            OperandKind::ShiftCount => if flags.v { Operand::Register(0b001, RegisterAccess::Low) } else { Operand::ImmediateData(1) },
+            OperandKind::Rel8 => {
+                let offset = bytes.byte(length)? as i8 as i16;
+                length += 1;
+                Operand::LabelOffset(offset)
+            },
+            OperandKind::Rel16 => {
+                let offset = bytes.word(length)? as i16;
+                length += 2;
+                Operand::LabelOffset(offset)
+            },
+            OperandKind::Far => {
+                let offset = bytes.word(length)?;
+                let segment = bytes.word(length + 2)?;
+                length += 4;
+                Operand::FarAddress { segment, offset }
+            },
+        });
+    }
+    if swap_operands { operands.swap(0, 1); }
 
-        Operation::Mov_Mem_To_Acc
-        | Operation::Mov_Acc_To_Mem
-        => {
-            const BASE_INSTRUCTION_LENGTH: u8 = 3;
+    Ok(Some(Instruction { operation: encoding.operation, operands, flags, size: length as u8 }))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disassemble(bytes: &[u8]) -> String { decode_instruction(bytes, 0).unwrap().to_string() }
+
+    #[test]
+    fn arithmetic_logic_group() {
+        assert_eq!(disassemble(&[ 0x80, 0xc3, 0x05 ]), "add bl, 5");
+        assert_eq!(disassemble(&[ 0x81, 0xc3, 0x34, 0x12 ]), "add bx, 4660");
+        assert_eq!(disassemble(&[ 0x82, 0xe9, 0x02 ]), "sub cl, 2");
+        // 0x83 sign extends its byte immediate
+        assert_eq!(disassemble(&[ 0x83, 0xf0, 0xff ]), "xor ax, byte -1");
+        assert_eq!(disassemble(&[ 0x83, 0x47, 0x02, 0x05 ]), "add word [bx + 2], byte 5");
+        assert_eq!(disassemble(&[ 0x80, 0x16, 0x00, 0x01, 0xfe ]), "adc [256], byte 254");
+        assert_eq!(disassemble(&[ 0x04, 0x07 ]), "add al, 7");
+        assert_eq!(disassemble(&[ 0x15, 0x34, 0x12 ]), "adc ax, 4660");
+        assert_eq!(disassemble(&[ 0x20, 0x07 ]), "and [bx], al");
+        assert_eq!(disassemble(&[ 0x84, 0xd8 ]), "test al, bl");
+        assert_eq!(disassemble(&[ 0xa9, 0x01, 0x00 ]), "test ax, 1");
+        assert_eq!(disassemble(&[ 0xf6, 0x06, 0x00, 0x01, 0x81 ]), "test [256], byte 129");
+        assert_eq!(disassemble(&[ 0xf6, 0xd3 ]), "not bl");
+        assert_eq!(disassemble(&[ 0xf7, 0x1f ]), "neg word [bx]");
+        assert_eq!(disassemble(&[ 0xfe, 0xc0 ]), "inc al");
+        assert_eq!(disassemble(&[ 0xff, 0x0f ]), "dec word [bx]");
+        assert_eq!(disassemble(&[ 0x48 ]), "dec ax");
+    }
 
-            let params = instruction_stream[instruction_pointer];
-            let wide = params & 1 == 1;
-            let address = read_word(instruction_stream, instruction_pointer + 1);
+    #[test]
+    fn shifts_and_rotates() {
+        // v picks a count of 1 or cl
+        assert_eq!(disassemble(&[ 0xd0, 0xe0 ]), "shl al, 1");
+        assert_eq!(disassemble(&[ 0xd3, 0xf8 ]), "sar ax, cl");
+        assert_eq!(disassemble(&[ 0xd2, 0x4f, 0x02 ]), "ror byte [bx + 2], cl");
+        assert_eq!(disassemble(&[ 0xd1, 0x16, 0x00, 0x01 ]), "rcl word [256], 1");
+        // 110 isn't a shift on the 8086
+        assert_eq!(decode_instruction(&[ 0xd0, 0xf0 ], 0).err(), Some(DecodeError::InvalidModRm { at: 1, byte: 0xf0 }));
+    }
 
-            // assuming mem -> acc
-            let mut destination_operand = Operand::register_acc(wide);
-            let mut source_operand = Operand::Memory(EffectiveAddress::Direct(address));
-            // swap destination and source if acc -> mem
-            if operation == Operation::Mov_Acc_To_Mem { std::mem::swap(&mut destination_operand, &mut source_operand); }
-            let operands = [ Some(destination_operand), Some(source_operand) ];
+    #[test]
+    fn string_instructions() {
+        assert_eq!(disassemble(&[ 0xac ]), "lodsb");
+        assert_eq!(disassemble(&[ 0xab ]), "stosw");
+        // z only matters to cmps and scas
+        assert_eq!(disassemble(&[ 0xf3, 0xa4 ]), "rep movsb");
+        assert_eq!(disassemble(&[ 0xf3, 0xa6 ]), "repe cmpsb");
+        assert_eq!(disassemble(&[ 0xf2, 0xae ]), "repne scasb");
+        let instruction = decode_instruction(&[ 0xf2, 0xa7 ], 0).unwrap();
+        assert!(instruction.flags.repeat && !instruction.flags.repeat_on_zero);
+        assert_eq!(instruction.size, 2);
+    }
 
-            let flags = InstructionFlags { wide, ..Default::default() };
+    #[test]
+    fn stack_and_procedures() {
+        assert_eq!(disassemble(&[ 0x50 ]), "push ax");
+        assert_eq!(disassemble(&[ 0x8f, 0x06, 0x00, 0x02 ]), "pop word [512]");
+        assert_eq!(disassemble(&[ 0x1e ]), "push ds");
+        assert_eq!(disassemble(&[ 0x9c ]), "pushf");
+        assert_eq!(disassemble(&[ 0x9d ]), "popf");
+        assert_eq!(disassemble(&[ 0xc2, 0x02, 0x00 ]), "ret 2");
+        assert_eq!(disassemble(&[ 0xc3 ]), "ret");
+        // the 8086 can still pop cs, later cpus use 0x0f as an escape
+        assert_eq!(disassemble(&[ 0x0f ]), "pop cs");
+    }
 
-            Some(Instruction { operation, operands, flags, size: BASE_INSTRUCTION_LENGTH })
-        },
+    #[test]
+    fn jumps_and_far_transfers() {
+        assert_eq!(disassemble(&[ 0x9a, 0x00, 0x00, 0x20, 0x00 ]), "call 32:0");
+        assert_eq!(disassemble(&[ 0xea, 0x00, 0x00, 0x30, 0x00 ]), "jmp 48:0");
+        assert_eq!(disassemble(&[ 0xff, 0xe3 ]), "jmp bx");
+        assert_eq!(disassemble(&[ 0xff, 0x1e, 0x80, 0x02 ]), "call far [640]");
+        assert_eq!(disassemble(&[ 0xff, 0x2f ]), "jmp far [bx]");
+        assert_eq!(disassemble(&[ 0xca, 0x02, 0x00 ]), "retf 2");
+        assert_eq!(disassemble(&[ 0xcb ]), "retf");
+    }
 
-        Operation::Add_Imm_To_Acc
-        | Operation::Sub_Imm_From_Acc
-        | Operation::Cmp_Imm_With_Acc
-        => {
-            const BASE_INSTRUCTION_LENGTH: u8 = 1;
+    #[test]
+    fn multiply_divide_and_adjusts() {
+        assert_eq!(disassemble(&[ 0xf6, 0xf3 ]), "div bl");
+        assert_eq!(disassemble(&[ 0xf7, 0x2f ]), "imul word [bx]");
+        // aam and aad carry their base, 10 unless hand assembled
+        assert_eq!(disassemble(&[ 0xd4, 0x0a ]), "aam 10");
+        assert_eq!(disassemble(&[ 0xd5, 0x10 ]), "aad 16");
+        assert_eq!(disassemble(&[ 0x27 ]), "daa");
+        assert_eq!(disassemble(&[ 0x3f ]), "aas");
+        assert_eq!(disassemble(&[ 0x98 ]), "cbw");
+        assert_eq!(disassemble(&[ 0x99 ]), "cwd");
+    }
 
-            let params = instruction_stream[instruction_pointer];
-            let wide = params & 1 == 1;
-            let (data, data_length) = read_data(instruction_stream, instruction_pointer + 1, wide);
+    #[test]
+    fn segment_registers_and_overrides() {
+        assert_eq!(disassemble(&[ 0x8e, 0xd8 ]), "mov ds, ax");
+        assert_eq!(disassemble(&[ 0x8c, 0xc0 ]), "mov ax, es");
+        assert_eq!(disassemble(&[ 0x07 ]), "pop es");
+        // an override goes on the memory operand, even when it names the default segment
+        assert_eq!(disassemble(&[ 0x26, 0x8b, 0x07 ]), "mov ax, [es:bx]");
+        assert_eq!(disassemble(&[ 0x3e, 0x89, 0x46, 0x02 ]), "mov [ds:bp + 2], ax");
+        let instruction = decode_instruction(&[ 0x2e, 0xff, 0x27 ], 0).unwrap();
+        assert_eq!((instruction.to_string(), instruction.size), ("jmp word [cs:bx]".to_string(), 3));
+    }
 
-            let destination_operand = Operand::register_acc(wide);
-            let source_operand = Operand::ImmediateData(data);
-            let operands = [ Some(destination_operand), Some(source_operand) ];
+    fn get_relative_jump(bytes: &[u8]) -> (String, i16) {
+        let instruction = decode_instruction(bytes, 0).unwrap();
+        let [ Some(Operand::LabelOffset(offset)), None ] = instruction.operands else { panic!("{} isn't a relative jump", instruction) };
+        (instruction.get_mnemonic(), offset)
+    }
 
-            let flags = InstructionFlags { wide, ..Default::default() };
+    #[test]
+    fn conditional_jumps_and_loops() {
+        // targets are kept as the signed increment from the next instruction
+        assert_eq!(get_relative_jump(&[ 0x72, 0xfe ]), ("jb".to_string(), -2));
+        assert_eq!(get_relative_jump(&[ 0x7f, 0x05 ]), ("jg".to_string(), 5));
+        assert_eq!(get_relative_jump(&[ 0xe2, 0xfe ]), ("loop".to_string(), -2));
+        assert_eq!(get_relative_jump(&[ 0xe1, 0x00 ]), ("loopz".to_string(), 0));
+        assert_eq!(get_relative_jump(&[ 0xe0, 0x10 ]), ("loopnz".to_string(), 16));
+        assert_eq!(get_relative_jump(&[ 0xe3, 0x80 ]), ("jcxz".to_string(), -128));
+    }
 
-            Some(Instruction { operation, operands, flags, size: BASE_INSTRUCTION_LENGTH + data_length })
-        },
+    #[test]
+    fn forced_displacement_and_immediate_sizes() {
+        assert_eq!(disassemble(&[ 0x89, 0x47, 0x00 ]), "mov [byte bx + 0], ax");
+        assert_eq!(disassemble(&[ 0x89, 0x87, 0x04, 0x00 ]), "mov [word bx + 4], ax");
+        assert_eq!(disassemble(&[ 0x89, 0x86, 0x00, 0x00 ]), "mov [word bp + 0], ax");
+        assert_eq!(disassemble(&[ 0x26, 0x89, 0x47, 0x00 ]), "mov [byte es:bx + 0], ax");
+        // bp always has a displacement, a byte is the fewest
+        assert_eq!(disassemble(&[ 0x89, 0x46, 0x00 ]), "mov [bp], ax");
+        assert_eq!(disassemble(&[ 0x89, 0x87, 0x00, 0x01 ]), "mov [bx + 256], ax");
+
+        assert_eq!(disassemble(&[ 0x81, 0xc3, 0x05, 0x00 ]), "add bx, strict word 5");
+        assert_eq!(disassemble(&[ 0x05, 0xff, 0xff ]), "add ax, strict word 65535");
+        assert_eq!(disassemble(&[ 0x81, 0x07, 0x00, 0x01 ]), "add [bx], word 256");
+        // mov has no sign extended form to avoid
+        assert_eq!(disassemble(&[ 0xc7, 0x07, 0x05, 0x00 ]), "mov [bx], word 5");
+    }
 
-        Operation::Jmp_On_Equal
-        | Operation::Jmp_On_Less
-        | Operation::Jmp_On_Less_Or_Equal
-        | Operation::Jmp_On_Below
-        | Operation::Jmp_On_Below_Or_Equal
-        | Operation::Jmp_On_Greater
-        | Operation::Jmp_On_Above
-        | Operation::Jmp_On_Parity
-        | Operation::Jmp_On_Overflow
-        | Operation::Jmp_On_Sign
-        | Operation::Jmp_On_Not_Equal
-        | Operation::Jmp_On_Not_Less
-        | Operation::Jmp_On_Not_Below
-        | Operation::Jmp_On_Not_Parity
-        | Operation::Jmp_On_Not_Overflow
-        | Operation::Jmp_On_Not_Sign
-        | Operation::Jmp_On_CX_Zero
-        | Operation::Loop
-        | Operation::Loop_While_Zero
-        | Operation::Loop_While_Not_Zero
-        => {
-            const BASE_INSTRUCTION_LENGTH: u8 = 2;
-
-            let operands = [ Some(Operand::LabelOffset(instruction_stream[instruction_pointer + 1] as i8)), None ];
-            let flags = InstructionFlags::default();
-
-            Some(Instruction { operation, operands, flags, size: BASE_INSTRUCTION_LENGTH })
-        }
+    #[test]
+    fn exchanges_loads_and_ports() {
+        assert_eq!(disassemble(&[ 0x86, 0xc3 ]), "xchg al, bl");
+        assert_eq!(disassemble(&[ 0x87, 0x08 ]), "xchg cx, [bx + si]");
+        assert_eq!(disassemble(&[ 0x92 ]), "xchg ax, dx");
+        // xchg ax, ax
+        assert_eq!(disassemble(&[ 0x90 ]), "nop");
+        assert_eq!(disassemble(&[ 0x8d, 0x73, 0x04 ]), "lea si, [bp + di + 4]");
+        assert_eq!(disassemble(&[ 0xc5, 0x1e, 0x00, 0x02 ]), "lds bx, [512]");
+        assert_eq!(disassemble(&[ 0xc4, 0x3f ]), "les di, [bx]");
+        assert_eq!(disassemble(&[ 0x26, 0xd7 ]), "es xlatb");
+        assert_eq!(disassemble(&[ 0x9f ]), "lahf");
+        assert_eq!(disassemble(&[ 0x9e ]), "sahf");
+        assert_eq!(disassemble(&[ 0xe4, 0x60 ]), "in al, 96");
+        assert_eq!(disassemble(&[ 0xed ]), "in ax, dx");
+        assert_eq!(disassemble(&[ 0xe6, 0x20 ]), "out 32, al");
+        assert_eq!(disassemble(&[ 0xef ]), "out dx, ax");
+        assert_eq!(disassemble(&[ 0xf0, 0xff, 0x07 ]), "lock inc word [bx]");
+        // addresses only, there's no register to load from
+        assert_eq!(decode_instruction(&[ 0x8d, 0xc0 ], 0).err(), Some(DecodeError::InvalidModRm { at: 1, byte: 0xc0 }));
+        assert_eq!(decode_instruction(&[ 0xf0, 0xf0, 0x90 ], 0).err(), Some(DecodeError::InvalidPrefix { at: 0, byte: 0xf0 }));
+    }
 
-        Operation::Halt
-        => {
-            const BASE_INSTRUCTION_LENGTH: u8 = 1;
-            let operands = [ None, None ];
-            let flags = InstructionFlags::default();
+    #[test]
+    fn truncated_instruction() {
+        // mov ax, imm16 missing its high data byte
+        assert_eq!(decode_instruction(&[ 0xb8, 0x01 ], 0).err(), Some(DecodeError::Truncated { at: 0, needed: 3 }));
+        // add [disp16], imm missing displacement and data
+        assert_eq!(decode_instruction(&[ 0x90, 0x81, 0x06 ], 1).err(), Some(DecodeError::Truncated { at: 1, needed: 3 }));
+        // truncation after a prefix is reported from the prefix
+        assert_eq!(decode_instruction(&[ 0x26, 0xc7 ], 0).err(), Some(DecodeError::Truncated { at: 0, needed: 3 }));
+        assert_eq!(decode_instruction(&[], 0).err(), Some(DecodeError::Truncated { at: 0, needed: 1 }));
+    }
 
-            Some(Instruction { operation, operands, flags, size: BASE_INSTRUCTION_LENGTH })
-        },
+    #[test]
+    fn unknown_opcode() {
+        assert_eq!(decode_instruction(&[ 0xf4, 0xd6 ], 1).err(), Some(DecodeError::UnknownOpcode { at: 1, byte: 0xd6 }));
+    }
+
+    #[test]
+    fn invalid_mod_rm() {
+        // 0xFE only has inc and dec forms
+        assert_eq!(decode_instruction(&[ 0xfe, 0xd0 ], 0).err(), Some(DecodeError::InvalidModRm { at: 1, byte: 0xd0 }));
+        // far pointers can't come from a register
+        assert_eq!(decode_instruction(&[ 0xff, 0xd8 ], 0).err(), Some(DecodeError::InvalidModRm { at: 1, byte: 0xd8 }));
+    }
+
+    #[test]
+    fn table_fields_fit_in_bytes() {
+        for encoding in INSTRUCTION_TABLE {
+            let mut bit_index = 0;
+            for field in encoding.bits {
+                let bit_count = field.get_bit_count() as usize;
+                assert!(bit_index % 8 + bit_count <= 8, "{} has a field straddling a byte", encoding.mnemonic);
+                bit_index += bit_count;
+            }
+            assert!(bit_index % 8 == 0, "{} doesn't end on a byte boundary", encoding.mnemonic);
+        }
+    }
+
+    #[test]
+    fn invalid_prefix() {
+        // rep add ax, ax
+        assert_eq!(decode_instruction(&[ 0xf3, 0x01, 0xc0 ], 0).err(), Some(DecodeError::InvalidPrefix { at: 0, byte: 0xf3 }));
+        // es es mov ax, [bx]
+        assert_eq!(decode_instruction(&[ 0x26, 0x26, 0x8b, 0x07 ], 0).err(), Some(DecodeError::InvalidPrefix { at: 0, byte: 0x26 }));
+        // prefixes are read without recursing, however many there are
+        assert_eq!(decode_instruction(&[ 0x26; 300_000 ], 0).err(), Some(DecodeError::InvalidPrefix { at: 0, byte: 0x26 }));
+        // es rep with nothing after them
+        assert_eq!(decode_instruction(&[ 0x26, 0xf3 ], 0).err(), Some(DecodeError::Truncated { at: 0, needed: 3 }));
     }
 }
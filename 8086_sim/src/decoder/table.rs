@@ -0,0 +1,234 @@
+use super::Operation;
+
+// Bit fields of an encoding, listed high bit first. Fields never straddle a byte boundary.
+pub enum BitField {
+    Literal(u8, u8), // bit count and the value those bits must have
+    D,
+    W,
+    S,
+    V,
+    Mod,
+    Reg,
+    Rm,
+    Sr, // 2-bit segment register
+    WideImplied, // takes no bits, the instruction always operates on words
+}
+
+impl BitField {
+    pub fn get_bit_count(&self) -> u8 {
+        match self {
+            Self::Literal(count, _) => *count,
+            Self::D | Self::W | Self::S | Self::V => 1,
+            Self::Mod | Self::Sr => 2,
+            Self::Reg | Self::Rm => 3,
+            Self::WideImplied => 0,
+        }
+    }
+}
+
+pub enum OperandKind {
+    Reg, // reg field, sized by w
+    RegMem, // mod and r/m fields
+    Memory, // mod and r/m fields, register forms are invalid
+    SegReg,
+    Acc, // al or ax depending on w
+    Dx, // dx holding an i/o port, whatever w is
+    Imm, // data sized by w, a single sign extended byte when s is set
+    ImmByte, // a data byte regardless of w
+    ImmWord, // a data word regardless of w
+    Direct, // memory operand at a 16-bit address following the opcode
+    ShiftCount, // cl when v is set, otherwise 1
+    Rel8,
+    Rel16,
+    Far, // offset word then segment word
+}
+
+// Clocks from the 8086 manual. Memory forms add the clocks for calculating the effective address.
+pub enum ClockFormula {
+    Fixed(u32),
+    RegMem { register: u32, memory: u32 },
+    RegMemByWidth { byte_register: u32, word_register: u32, memory: u32 },
+    // instructions with a reg/mem and a reg operand, to_memory when reg/mem is the destination
+    RegMemReg { register: u32, from_memory: u32, to_memory: u32 },
+    MemoryOnly(u32),
+    // data dependent min and max clocks for byte and word operands
+    Range { register: [[u32; 2]; 2], memory: [[u32; 2]; 2] },
+    // shifts by cl add 4 clocks per bit
+    Shift { register: u32, memory: u32, register_by_cl: u32, memory_by_cl: u32 },
+    Branch { taken: u32, not_taken: u32 },
+    String { single: u32, per_repetition: u32 },
+}
+
+pub struct InstructionEncoding {
+    pub operation: Operation,
+    pub mnemonic: &'static str,
+    pub bits: &'static [BitField],
+    pub operands: &'static [OperandKind], // destination first when d is set or the encoding has no d bit
+    pub clocks: ClockFormula,
+}
+
+// Field, operand kind and clock formula names are given without their enum, they overlap
+macro_rules! encoding {
+    (
+        $operation:ident, $mnemonic:literal,
+        [ $($field:ident $(($($field_args:expr),*))?),* ],
+        [ $($operand:ident),* ],
+        $clocks:ident $($clocks_args:tt)?
+    ) => {
+        InstructionEncoding {
+            operation: Operation::$operation,
+            mnemonic: $mnemonic,
+            bits: &[ $(BitField::$field $(($($field_args),*))?),* ],
+            operands: &[ $(OperandKind::$operand),* ],
+            clocks: ClockFormula::$clocks $($clocks_args)?,
+        }
+    };
+}
+
+// The first encoding whose literal bits all match is the one used, so the order only matters if
+// encodings overlap (only nop and xchg do).
+pub const INSTRUCTION_TABLE: &[InstructionEncoding] = &[
+    encoding!(Mov_RegMem_ToFrom_Reg, "mov", [ Literal(6, 0b100010), D, W, Mod, Reg, Rm ], [ Reg, RegMem ], RegMemReg { register: 2, from_memory: 8, to_memory: 9 }),
+    encoding!(Mov_Imm_To_RegMem, "mov", [ Literal(7, 0b1100011), W, Mod, Literal(3, 0b000), Rm ], [ RegMem, Imm ], RegMem { register: 4, memory: 10 }),
+    encoding!(Mov_Imm_To_Reg, "mov", [ Literal(4, 0b1011), W, Reg ], [ Reg, Imm ], Fixed(4)),
+    encoding!(Mov_Mem_To_Acc, "mov", [ Literal(7, 0b1010000), W ], [ Acc, Direct ], Fixed(10)),
+    encoding!(Mov_Acc_To_Mem, "mov", [ Literal(7, 0b1010001), W ], [ Direct, Acc ], Fixed(10)),
+    encoding!(Mov_RegMem_To_SegReg, "mov", [ Literal(8, 0b10001110), WideImplied, Mod, Literal(1, 0), Sr, Rm ], [ SegReg, RegMem ], RegMem { register: 2, memory: 8 }),
+    encoding!(Mov_SegReg_To_RegMem, "mov", [ Literal(8, 0b10001100), WideImplied, Mod, Literal(1, 0), Sr, Rm ], [ RegMem, SegReg ], RegMem { register: 2, memory: 9 }),
+    encoding!(Xchg_RegMem_With_Reg, "xchg", [ Literal(7, 0b1000011), W, Mod, Reg, Rm ], [ Reg, RegMem ], RegMem { register: 4, memory: 17 }),
+    // xchg ax, ax does nothing, it's listed first so it's shown as nop
+    encoding!(Nop, "nop", [ Literal(8, 0b10010000) ], [], Fixed(3)),
+    encoding!(Xchg_Reg_With_Acc, "xchg", [ Literal(5, 0b10010), WideImplied, Reg ], [ Acc, Reg ], Fixed(3)),
+    encoding!(In_Fixed_Port, "in", [ Literal(7, 0b1110010), W ], [ Acc, ImmByte ], Fixed(10)),
+    encoding!(In_Variable_Port, "in", [ Literal(7, 0b1110110), W ], [ Acc, Dx ], Fixed(8)),
+    encoding!(Out_Fixed_Port, "out", [ Literal(7, 0b1110011), W ], [ ImmByte, Acc ], Fixed(10)),
+    encoding!(Out_Variable_Port, "out", [ Literal(7, 0b1110111), W ], [ Dx, Acc ], Fixed(8)),
+    encoding!(Translate, "xlatb", [ Literal(8, 0b11010111) ], [], Fixed(11)),
+    encoding!(Load_EA_To_Reg, "lea", [ Literal(8, 0b10001101), WideImplied, Mod, Reg, Rm ], [ Reg, Memory ], MemoryOnly(2)),
+    encoding!(Load_Pointer_To_DS, "lds", [ Literal(8, 0b11000101), WideImplied, Mod, Reg, Rm ], [ Reg, Memory ], MemoryOnly(16)),
+    encoding!(Load_Pointer_To_ES, "les", [ Literal(8, 0b11000100), WideImplied, Mod, Reg, Rm ], [ Reg, Memory ], MemoryOnly(16)),
+    encoding!(Load_AH_With_Flags, "lahf", [ Literal(8, 0b10011111) ], [], Fixed(4)),
+    encoding!(Store_AH_Into_Flags, "sahf", [ Literal(8, 0b10011110) ], [], Fixed(4)),
+
+    encoding!(Add_RegMem_With_Reg_To_Either, "add", [ Literal(6, 0b000000), D, W, Mod, Reg, Rm ], [ Reg, RegMem ], RegMemReg { register: 3, from_memory: 9, to_memory: 16 }),
+    encoding!(Add_Imm_To_RegMem, "add", [ Literal(6, 0b100000), S, W, Mod, Literal(3, 0b000), Rm ], [ RegMem, Imm ], RegMem { register: 4, memory: 17 }),
+    encoding!(Add_Imm_To_Acc, "add", [ Literal(7, 0b0000010), W ], [ Acc, Imm ], Fixed(4)),
+    encoding!(Or_RegMem_And_Reg_To_Either, "or", [ Literal(6, 0b000010), D, W, Mod, Reg, Rm ], [ Reg, RegMem ], RegMemReg { register: 3, from_memory: 9, to_memory: 16 }),
+    encoding!(Or_Imm_To_RegMem, "or", [ Literal(6, 0b100000), S, W, Mod, Literal(3, 0b001), Rm ], [ RegMem, Imm ], RegMem { register: 4, memory: 17 }),
+    encoding!(Or_Imm_To_Acc, "or", [ Literal(7, 0b0000110), W ], [ Acc, Imm ], Fixed(4)),
+    encoding!(Adc_RegMem_With_Reg_To_Either, "adc", [ Literal(6, 0b000100), D, W, Mod, Reg, Rm ], [ Reg, RegMem ], RegMemReg { register: 3, from_memory: 9, to_memory: 16 }),
+    encoding!(Adc_Imm_To_RegMem, "adc", [ Literal(6, 0b100000), S, W, Mod, Literal(3, 0b010), Rm ], [ RegMem, Imm ], RegMem { register: 4, memory: 17 }),
+    encoding!(Adc_Imm_To_Acc, "adc", [ Literal(7, 0b0001010), W ], [ Acc, Imm ], Fixed(4)),
+    encoding!(Sbb_RegMem_And_Reg_From_Either, "sbb", [ Literal(6, 0b000110), D, W, Mod, Reg, Rm ], [ Reg, RegMem ], RegMemReg { register: 3, from_memory: 9, to_memory: 16 }),
+    encoding!(Sbb_Imm_From_RegMem, "sbb", [ Literal(6, 0b100000), S, W, Mod, Literal(3, 0b011), Rm ], [ RegMem, Imm ], RegMem { register: 4, memory: 17 }),
+    encoding!(Sbb_Imm_From_Acc, "sbb", [ Literal(7, 0b0001110), W ], [ Acc, Imm ], Fixed(4)),
+    encoding!(And_RegMem_With_Reg_To_Either, "and", [ Literal(6, 0b001000), D, W, Mod, Reg, Rm ], [ Reg, RegMem ], RegMemReg { register: 3, from_memory: 9, to_memory: 16 }),
+    encoding!(And_Imm_To_RegMem, "and", [ Literal(6, 0b100000), S, W, Mod, Literal(3, 0b100), Rm ], [ RegMem, Imm ], RegMem { register: 4, memory: 17 }),
+    encoding!(And_Imm_To_Acc, "and", [ Literal(7, 0b0010010), W ], [ Acc, Imm ], Fixed(4)),
+    encoding!(Sub_RegMem_And_Reg_From_Either, "sub", [ Literal(6, 0b001010), D, W, Mod, Reg, Rm ], [ Reg, RegMem ], RegMemReg { register: 3, from_memory: 9, to_memory: 16 }),
+    encoding!(Sub_Imm_From_RegMem, "sub", [ Literal(6, 0b100000), S, W, Mod, Literal(3, 0b101), Rm ], [ RegMem, Imm ], RegMem { register: 4, memory: 17 }),
+    encoding!(Sub_Imm_From_Acc, "sub", [ Literal(7, 0b0010110), W ], [ Acc, Imm ], Fixed(4)),
+    encoding!(Xor_RegMem_And_Reg_To_Either, "xor", [ Literal(6, 0b001100), D, W, Mod, Reg, Rm ], [ Reg, RegMem ], RegMemReg { register: 3, from_memory: 9, to_memory: 16 }),
+    encoding!(Xor_Imm_To_RegMem, "xor", [ Literal(6, 0b100000), S, W, Mod, Literal(3, 0b110), Rm ], [ RegMem, Imm ], RegMem { register: 4, memory: 17 }),
+    encoding!(Xor_Imm_To_Acc, "xor", [ Literal(7, 0b0011010), W ], [ Acc, Imm ], Fixed(4)),
+    encoding!(Cmp_RegMem_And_Reg, "cmp", [ Literal(6, 0b001110), D, W, Mod, Reg, Rm ], [ Reg, RegMem ], RegMemReg { register: 3, from_memory: 9, to_memory: 9 }),
+    encoding!(Cmp_Imm_With_RegMem, "cmp", [ Literal(6, 0b100000), S, W, Mod, Literal(3, 0b111), Rm ], [ RegMem, Imm ], RegMem { register: 4, memory: 10 }),
+    encoding!(Cmp_Imm_With_Acc, "cmp", [ Literal(7, 0b0011110), W ], [ Acc, Imm ], Fixed(4)),
+    encoding!(Test_RegMem_And_Reg, "test", [ Literal(7, 0b1000010), W, Mod, Reg, Rm ], [ RegMem, Reg ], RegMemReg { register: 3, from_memory: 9, to_memory: 9 }),
+    encoding!(Test_Imm_And_RegMem, "test", [ Literal(7, 0b1111011), W, Mod, Literal(3, 0b000), Rm ], [ RegMem, Imm ], RegMem { register: 5, memory: 11 }),
+    encoding!(Test_Imm_And_Acc, "test", [ Literal(7, 0b1010100), W ], [ Acc, Imm ], Fixed(4)),
+
+    encoding!(Inc_RegMem, "inc", [ Literal(7, 0b1111111), W, Mod, Literal(3, 0b000), Rm ], [ RegMem ], RegMemByWidth { byte_register: 3, word_register: 2, memory: 15 }),
+    encoding!(Inc_Reg, "inc", [ Literal(5, 0b01000), WideImplied, Reg ], [ Reg ], Fixed(2)),
+    encoding!(Dec_RegMem, "dec", [ Literal(7, 0b1111111), W, Mod, Literal(3, 0b001), Rm ], [ RegMem ], RegMemByWidth { byte_register: 3, word_register: 2, memory: 15 }),
+    encoding!(Dec_Reg, "dec", [ Literal(5, 0b01001), WideImplied, Reg ], [ Reg ], Fixed(2)),
+    encoding!(Not, "not", [ Literal(7, 0b1111011), W, Mod, Literal(3, 0b010), Rm ], [ RegMem ], RegMem { register: 3, memory: 16 }),
+    encoding!(Neg, "neg", [ Literal(7, 0b1111011), W, Mod, Literal(3, 0b011), Rm ], [ RegMem ], RegMem { register: 3, memory: 16 }),
+
+    encoding!(Mul, "mul", [ Literal(7, 0b1111011), W, Mod, Literal(3, 0b100), Rm ], [ RegMem ], Range { register: [ [ 70, 77 ], [ 118, 133 ] ], memory: [ [ 76, 83 ], [ 124, 139 ] ] }),
+    encoding!(Imul, "imul", [ Literal(7, 0b1111011), W, Mod, Literal(3, 0b101), Rm ], [ RegMem ], Range { register: [ [ 80, 98 ], [ 128, 154 ] ], memory: [ [ 86, 104 ], [ 134, 160 ] ] }),
+    encoding!(Div, "div", [ Literal(7, 0b1111011), W, Mod, Literal(3, 0b110), Rm ], [ RegMem ], Range { register: [ [ 80, 90 ], [ 144, 162 ] ], memory: [ [ 86, 96 ], [ 150, 168 ] ] }),
+    encoding!(Idiv, "idiv", [ Literal(7, 0b1111011), W, Mod, Literal(3, 0b111), Rm ], [ RegMem ], Range { register: [ [ 101, 112 ], [ 165, 184 ] ], memory: [ [ 107, 118 ], [ 171, 190 ] ] }),
+    encoding!(Cbw, "cbw", [ Literal(8, 0b10011000) ], [], Fixed(2)),
+    encoding!(Cwd, "cwd", [ Literal(8, 0b10011001) ], [], Fixed(5)),
+    encoding!(Aaa, "aaa", [ Literal(8, 0b00110111) ], [], Fixed(4)),
+    encoding!(Aas, "aas", [ Literal(8, 0b00111111) ], [], Fixed(4)),
+    // the byte following aam and aad is the base, always 10 as emitted by assemblers, but the 8086 accepts any
+    encoding!(Aam, "aam", [ Literal(8, 0b11010100) ], [ ImmByte ], Fixed(83)),
+    encoding!(Aad, "aad", [ Literal(8, 0b11010101) ], [ ImmByte ], Fixed(60)),
+    encoding!(Daa, "daa", [ Literal(8, 0b00100111) ], [], Fixed(4)),
+    encoding!(Das, "das", [ Literal(8, 0b00101111) ], [], Fixed(4)),
+
+    encoding!(Rol, "rol", [ Literal(6, 0b110100), V, W, Mod, Literal(3, 0b000), Rm ], [ RegMem, ShiftCount ], Shift { register: 2, memory: 15, register_by_cl: 8, memory_by_cl: 20 }),
+    encoding!(Ror, "ror", [ Literal(6, 0b110100), V, W, Mod, Literal(3, 0b001), Rm ], [ RegMem, ShiftCount ], Shift { register: 2, memory: 15, register_by_cl: 8, memory_by_cl: 20 }),
+    encoding!(Rcl, "rcl", [ Literal(6, 0b110100), V, W, Mod, Literal(3, 0b010), Rm ], [ RegMem, ShiftCount ], Shift { register: 2, memory: 15, register_by_cl: 8, memory_by_cl: 20 }),
+    encoding!(Rcr, "rcr", [ Literal(6, 0b110100), V, W, Mod, Literal(3, 0b011), Rm ], [ RegMem, ShiftCount ], Shift { register: 2, memory: 15, register_by_cl: 8, memory_by_cl: 20 }),
+    encoding!(Shl, "shl", [ Literal(6, 0b110100), V, W, Mod, Literal(3, 0b100), Rm ], [ RegMem, ShiftCount ], Shift { register: 2, memory: 15, register_by_cl: 8, memory_by_cl: 20 }),
+    encoding!(Shr, "shr", [ Literal(6, 0b110100), V, W, Mod, Literal(3, 0b101), Rm ], [ RegMem, ShiftCount ], Shift { register: 2, memory: 15, register_by_cl: 8, memory_by_cl: 20 }),
+    encoding!(Sar, "sar", [ Literal(6, 0b110100), V, W, Mod, Literal(3, 0b111), Rm ], [ RegMem, ShiftCount ], Shift { register: 2, memory: 15, register_by_cl: 8, memory_by_cl: 20 }),
+
+    // string mnemonics get a b or w suffix from w
+    encoding!(Movs, "movs", [ Literal(7, 0b1010010), W ], [], String { single: 18, per_repetition: 17 }),
+    encoding!(Cmps, "cmps", [ Literal(7, 0b1010011), W ], [], String { single: 22, per_repetition: 22 }),
+    encoding!(Scas, "scas", [ Literal(7, 0b1010111), W ], [], String { single: 15, per_repetition: 15 }),
+    encoding!(Lods, "lods", [ Literal(7, 0b1010110), W ], [], String { single: 12, per_repetition: 13 }),
+    encoding!(Stos, "stos", [ Literal(7, 0b1010101), W ], [], String { single: 11, per_repetition: 10 }),
+
+    encoding!(Clear_Carry, "clc", [ Literal(8, 0b11111000) ], [], Fixed(2)),
+    encoding!(Complement_Carry, "cmc", [ Literal(8, 0b11110101) ], [], Fixed(2)),
+    encoding!(Set_Carry, "stc", [ Literal(8, 0b11111001) ], [], Fixed(2)),
+    encoding!(Clear_Direction, "cld", [ Literal(8, 0b11111100) ], [], Fixed(2)),
+    encoding!(Set_Direction, "std", [ Literal(8, 0b11111101) ], [], Fixed(2)),
+    encoding!(Clear_Interrupt, "cli", [ Literal(8, 0b11111010) ], [], Fixed(2)),
+    encoding!(Set_Interrupt, "sti", [ Literal(8, 0b11111011) ], [], Fixed(2)),
+
+    encoding!(Push_RegMem, "push", [ Literal(8, 0b11111111), WideImplied, Mod, Literal(3, 0b110), Rm ], [ RegMem ], RegMem { register: 11, memory: 16 }),
+    encoding!(Push_Reg, "push", [ Literal(5, 0b01010), WideImplied, Reg ], [ Reg ], Fixed(11)),
+    encoding!(Push_SegReg, "push", [ Literal(3, 0b000), WideImplied, Sr, Literal(3, 0b110) ], [ SegReg ], Fixed(10)),
+    encoding!(Pop_RegMem, "pop", [ Literal(8, 0b10001111), WideImplied, Mod, Literal(3, 0b000), Rm ], [ RegMem ], RegMem { register: 8, memory: 17 }),
+    encoding!(Pop_Reg, "pop", [ Literal(5, 0b01011), WideImplied, Reg ], [ Reg ], Fixed(8)),
+    encoding!(Pop_SegReg, "pop", [ Literal(3, 0b000), WideImplied, Sr, Literal(3, 0b111) ], [ SegReg ], Fixed(8)),
+    encoding!(Push_Flags, "pushf", [ Literal(8, 0b10011100) ], [], Fixed(10)),
+    encoding!(Pop_Flags, "popf", [ Literal(8, 0b10011101) ], [], Fixed(8)),
+
+    encoding!(Call_Direct_Within_Segment, "call", [ Literal(8, 0b11101000) ], [ Rel16 ], Fixed(19)),
+    encoding!(Call_Indirect_Within_Segment, "call", [ Literal(8, 0b11111111), WideImplied, Mod, Literal(3, 0b010), Rm ], [ RegMem ], RegMem { register: 16, memory: 21 }),
+    encoding!(Call_Direct_Intersegment, "call", [ Literal(8, 0b10011010) ], [ Far ], Fixed(28)),
+    encoding!(Call_Indirect_Intersegment, "call", [ Literal(8, 0b11111111), WideImplied, Mod, Literal(3, 0b011), Rm ], [ Memory ], MemoryOnly(37)),
+    encoding!(Jmp_Direct_Within_Segment, "jmp", [ Literal(8, 0b11101001) ], [ Rel16 ], Fixed(15)),
+    encoding!(Jmp_Direct_Within_Segment_Short, "jmp", [ Literal(8, 0b11101011) ], [ Rel8 ], Fixed(15)),
+    encoding!(Jmp_Indirect_Within_Segment, "jmp", [ Literal(8, 0b11111111), WideImplied, Mod, Literal(3, 0b100), Rm ], [ RegMem ], RegMem { register: 11, memory: 18 }),
+    encoding!(Jmp_Direct_Intersegment, "jmp", [ Literal(8, 0b11101010) ], [ Far ], Fixed(15)),
+    encoding!(Jmp_Indirect_Intersegment, "jmp", [ Literal(8, 0b11111111), WideImplied, Mod, Literal(3, 0b101), Rm ], [ Memory ], MemoryOnly(24)),
+    encoding!(Ret_Within_Segment, "ret", [ Literal(8, 0b11000011) ], [], Fixed(8)),
+    encoding!(Ret_Within_Segment_Imm, "ret", [ Literal(8, 0b11000010), WideImplied ], [ ImmWord ], Fixed(12)),
+    encoding!(Ret_Intersegment, "retf", [ Literal(8, 0b11001011) ], [], Fixed(18)),
+    encoding!(Ret_Intersegment_Imm, "retf", [ Literal(8, 0b11001010), WideImplied ], [ ImmWord ], Fixed(17)),
+
+    encoding!(Interrupt, "int", [ Literal(8, 0b11001101) ], [ ImmByte ], Fixed(51)),
+    encoding!(Interrupt_Type_3, "int3", [ Literal(8, 0b11001100) ], [], Fixed(52)),
+    encoding!(Interrupt_Return, "iret", [ Literal(8, 0b11001111) ], [], Fixed(24)),
+
+    encoding!(Jmp_On_Equal, "je", [ Literal(8, 0b01110100) ], [ Rel8 ], Branch { taken: 16, not_taken: 4 }),
+    encoding!(Jmp_On_Less, "jl", [ Literal(8, 0b01111100) ], [ Rel8 ], Branch { taken: 16, not_taken: 4 }),
+    encoding!(Jmp_On_Less_Or_Equal, "jle", [ Literal(8, 0b01111110) ], [ Rel8 ], Branch { taken: 16, not_taken: 4 }),
+    encoding!(Jmp_On_Below, "jb", [ Literal(8, 0b01110010) ], [ Rel8 ], Branch { taken: 16, not_taken: 4 }),
+    encoding!(Jmp_On_Below_Or_Equal, "jbe", [ Literal(8, 0b01110110) ], [ Rel8 ], Branch { taken: 16, not_taken: 4 }),
+    encoding!(Jmp_On_Greater, "jg", [ Literal(8, 0b01111111) ], [ Rel8 ], Branch { taken: 16, not_taken: 4 }),
+    encoding!(Jmp_On_Above, "ja", [ Literal(8, 0b01110111) ], [ Rel8 ], Branch { taken: 16, not_taken: 4 }),
+    encoding!(Jmp_On_Parity, "jp", [ Literal(8, 0b01111010) ], [ Rel8 ], Branch { taken: 16, not_taken: 4 }),
+    encoding!(Jmp_On_Overflow, "jo", [ Literal(8, 0b01110000) ], [ Rel8 ], Branch { taken: 16, not_taken: 4 }),
+    encoding!(Jmp_On_Sign, "js", [ Literal(8, 0b01111000) ], [ Rel8 ], Branch { taken: 16, not_taken: 4 }),
+    encoding!(Jmp_On_Not_Equal, "jne", [ Literal(8, 0b01110101) ], [ Rel8 ], Branch { taken: 16, not_taken: 4 }),
+    encoding!(Jmp_On_Not_Less, "jnl", [ Literal(8, 0b01111101) ], [ Rel8 ], Branch { taken: 16, not_taken: 4 }),
+    encoding!(Jmp_On_Not_Below, "jnb", [ Literal(8, 0b01110011) ], [ Rel8 ], Branch { taken: 16, not_taken: 4 }),
+    encoding!(Jmp_On_Not_Parity, "jnp", [ Literal(8, 0b01111011) ], [ Rel8 ], Branch { taken: 16, not_taken: 4 }),
+    encoding!(Jmp_On_Not_Overflow, "jno", [ Literal(8, 0b01110001) ], [ Rel8 ], Branch { taken: 16, not_taken: 4 }),
+    encoding!(Jmp_On_Not_Sign, "jns", [ Literal(8, 0b01111001) ], [ Rel8 ], Branch { taken: 16, not_taken: 4 }),
+    encoding!(Jmp_On_CX_Zero, "jcxz", [ Literal(8, 0b11100011) ], [ Rel8 ], Branch { taken: 18, not_taken: 6 }),
+    encoding!(Loop, "loop", [ Literal(8, 0b11100010) ], [ Rel8 ], Branch { taken: 17, not_taken: 5 }),
+    encoding!(Loop_While_Zero, "loopz", [ Literal(8, 0b11100001) ], [ Rel8 ], Branch { taken: 18, not_taken: 6 }),
+    encoding!(Loop_While_Not_Zero, "loopnz", [ Literal(8, 0b11100000) ], [ Rel8 ], Branch { taken: 19, not_taken: 5 }),
+
+    encoding!(Halt, "hlt", [ Literal(8, 0b11110100) ], [], Fixed(2)),
+];
@@ -0,0 +1,261 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+};
+
+use crate::{
+    assembler::assemble,
+    decoder::*,
+};
+
+// Address a relative jump or loop lands on, None for anything else or a target before the start
+fn get_jump_target(instruction: &Instruction, address: usize) -> Option<usize> {
+    match instruction.operands {
+        [ Some(Operand::LabelOffset(offset)), None ] => {
+            address.checked_add_signed(instruction.size as isize + offset as isize)
+        },
+        _ => None,
+    }
+}
+
+struct Disassembly {
+    instructions: Vec<(usize, Instruction)>,
+    labels: BTreeMap<usize, String>,
+    end: usize, // address after the last decoded instruction
+    error: Option<DecodeError>,
+}
+
+// The first pass decodes every instruction and collects jump targets so the listing can put a label
+// in front of every instruction that's jumped to. If decoding fails the disassembly covers
+// everything before the failing instruction.
+fn decode_stream(instruction_stream: &[u8]) -> Disassembly {
+    let mut instructions: Vec<(usize, Instruction)> = vec![];
+    let mut error = None;
+    let mut address = 0;
+    while address < instruction_stream.len() {
+        match decode_instruction(instruction_stream, address) {
+            Ok(instruction) => {
+                let size = instruction.size as usize;
+                instructions.push((address, instruction));
+                address += size;
+            },
+            Err(decode_error) => {
+                error = Some(decode_error);
+                break;
+            },
+        }
+    }
+    let end = address;
+
+    // only targets on an instruction boundary (or the end of the listing) can be labelled, anything
+    // else keeps its $ relative form
+    let mut labels: BTreeMap<usize, String> = BTreeMap::new();
+    for (address, instruction) in &instructions {
+        if let Some(target) = get_jump_target(instruction, *address) {
+            let is_boundary = target == end || instructions.binary_search_by_key(&target, |(address, _)| *address).is_ok();
+            if is_boundary { labels.insert(target, String::new()); }
+        }
+    }
+    for (index, label) in labels.values_mut().enumerate() {
+        *label = format!("label_{}", index);
+    }
+
+    Disassembly { instructions, labels, end, error }
+}
+
+impl Disassembly {
+    fn format_instruction(&self, address: usize, instruction: &Instruction) -> String {
+        match get_jump_target(instruction, address).and_then(|target| self.labels.get(&target)) {
+            Some(label) => LabelledInstruction { instruction, label }.to_string(),
+            None => instruction.to_string(),
+        }
+    }
+}
+
+// Labels the disassembly gives to jump targets in the stream, by address
+pub fn get_labels(instruction_stream: &[u8]) -> BTreeMap<usize, String> {
+    decode_stream(instruction_stream).labels
+}
+
+// Disassembles the whole stream into nasm source that reassembles to the same bytes. Encodings nasm
+// never picks for the instruction they hold (add al, 5 through 80 c0 05 rather than 04 05, push bx
+// through ff f3, prefixes out of order, ...) are written as db with the instruction in a comment.
+pub fn disassemble(instruction_stream: &[u8]) -> (String, Option<DecodeError>) {
+    let disassembly = decode_stream(instruction_stream);
+
+    let mut listing = String::from("bits 16\n\n");
+    for (address, instruction) in &disassembly.instructions {
+        if let Some(label) = disassembly.labels.get(address) {
+            writeln!(listing, "{}:", label).unwrap();
+        }
+        // the built-in assembler picks encodings the way nasm does, jumps are relative to $ so the
+        // instruction assembles the same on its own
+        let bytes = &instruction_stream[*address..*address + instruction.size as usize];
+        let text = disassembly.format_instruction(*address, instruction);
+        if assemble(&instruction.to_string()).is_ok_and(|assembled| assembled == bytes) {
+            writeln!(listing, "{}", text).unwrap();
+        } else {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:#04x}", byte)).collect();
+            writeln!(listing, "db {} ; {}", bytes.join(", "), text).unwrap();
+        }
+    }
+    if let Some(label) = disassembly.labels.get(&disassembly.end) {
+        writeln!(listing, "{}:", label).unwrap();
+    }
+
+    (listing, disassembly.error)
+}
+
+// Disassembles the whole stream into an assembler style listing with the address, encoded bytes,
+// instruction and clocks of each instruction in aligned columns
+pub fn disassemble_listing(instruction_stream: &[u8]) -> (String, Option<DecodeError>) {
+    let disassembly = decode_stream(instruction_stream);
+
+    let rows: Vec<(usize, String, String, String)> = disassembly.instructions.iter()
+        .map(|(address, instruction)| {
+            let bytes = instruction_stream[*address..*address + instruction.size as usize].iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<Vec<_>>()
+                .join(" ");
+            (*address, bytes, disassembly.format_instruction(*address, instruction), instruction.get_clocks_description())
+        })
+        .collect();
+    let bytes_width = rows.iter().map(|(_, bytes, ..)| bytes.len()).max().unwrap_or(0);
+    let instruction_width = rows.iter().map(|(_, _, instruction, _)| instruction.len()).max().unwrap_or(0);
+    // labels sit in the instruction column so they line up with the code
+    let label_indent = 4 + 2 + bytes_width + 2;
+
+    let mut listing = String::new();
+    for (address, bytes, instruction, clocks) in &rows {
+        if let Some(label) = disassembly.labels.get(address) {
+            writeln!(listing, "{:label_indent$}{}:", "", label).unwrap();
+        }
+        writeln!(listing, "{:04x}  {:bytes_width$}  {:instruction_width$}  {}", address, bytes, instruction, clocks).unwrap();
+    }
+    if let Some(label) = disassembly.labels.get(&disassembly.end) {
+        writeln!(listing, "{:label_indent$}{}:", "", label).unwrap();
+    }
+
+    (listing, disassembly.error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::HashSet,
+        process::Command,
+    };
+
+    // Every instruction the decoder accepts from each opcode and mod/rm byte, followed by zero,
+    // small, negative and large displacements and data, and behind each kind of prefix
+    fn get_encodings() -> Vec<Vec<u8>> {
+        let tails: [&[u8]; 4] = [ &[ 0x00, 0x00, 0x00, 0x00 ], &[ 0x05, 0x00, 0x05, 0x00 ], &[ 0xfe, 0xff, 0x80, 0xff ], &[ 0x34, 0x12, 0x78, 0x56 ] ];
+        let mut seen: HashSet<Vec<u8>> = HashSet::new();
+        let mut encodings = vec![];
+        for prefix in [ None, Some(0x26), Some(0x2e), Some(0xf0), Some(0xf2), Some(0xf3) ] {
+            for opcode in 0..=0xff {
+                // behind a prefix a few mod/rm bytes are enough to cover register and memory forms
+                let mod_rms: Vec<u8> = if prefix.is_some() { vec![ 0x07, 0x47, 0x87, 0xc1 ] } else { (0..=0xff).collect() };
+                for mod_rm in mod_rms {
+                    for tail in tails {
+                        let bytes: Vec<u8> = prefix.into_iter().chain([ opcode, mod_rm ]).chain(tail.iter().copied()).collect();
+                        if let Ok(instruction) = decode_instruction(&bytes, 0) {
+                            let encoding = bytes[..instruction.size as usize].to_vec();
+                            if seen.insert(encoding.clone()) { encodings.push(encoding); }
+                        }
+                    }
+                }
+            }
+        }
+
+        encodings
+    }
+
+    #[test]
+    fn labels_jump_targets() {
+        // mov cx, 3 / dec cx / jne -3 / jmp +0 / hlt
+        let (listing, error) = disassemble(&[ 0xb9, 0x03, 0x00, 0x49, 0x75, 0xfd, 0xe9, 0x00, 0x00, 0xf4 ]);
+        assert!(error.is_none());
+        assert_eq!(listing, "bits 16\n\nmov cx, 3\nlabel_0:\ndec cx\njne label_0\njmp near label_1\nlabel_1:\nhlt\n");
+    }
+
+    #[test]
+    fn listing_columns() {
+        // mov cx, 3 / dec cx / jne -3 / add [bx + si + 4], ax
+        let (listing, _) = disassemble_listing(&[ 0xb9, 0x03, 0x00, 0x49, 0x75, 0xfd, 0x01, 0x40, 0x04 ]);
+        assert_eq!(listing, concat!(
+            "0000  b9 03 00  mov cx, 3              4\n",
+            "                label_0:\n",
+            "0003  49        dec cx                 2\n",
+            "0004  75 fd     jne label_0            16/4\n",
+            "0006  01 40 04  add [bx + si + 4], ax  27 (16 + 11ea)\n",
+        ));
+    }
+
+    #[test]
+    fn unaligned_target_keeps_relative_offset() {
+        // jmp short into the middle of mov ax, imm16
+        let (listing, _) = disassemble(&[ 0xeb, 0x01, 0xb8, 0x00, 0x00 ]);
+        assert_eq!(listing, "bits 16\n\njmp $+3\nmov ax, 0\n");
+    }
+
+    #[test]
+    fn writes_encodings_nasm_doesnt_pick_as_db() {
+        let (listing, _) = disassemble(&[ 0x80, 0xc0, 0x05, 0x04, 0x05, 0xff, 0xf3, 0x81, 0xc3, 0x05, 0x00, 0x89, 0x47, 0x00 ]);
+        assert_eq!(listing, concat!(
+            "bits 16\n\n",
+            "db 0x80, 0xc0, 0x05 ; add al, 5\n",
+            "add al, 5\n",
+            "db 0xff, 0xf3 ; push bx\n",
+            "add bx, strict word 5\n",
+            "mov [byte bx + 0], ax\n",
+        ));
+    }
+
+    #[test]
+    fn every_encoding_reassembles() {
+        for bytes in get_encodings() {
+            let (listing, error) = disassemble(&bytes);
+            assert!(error.is_none());
+            assert_eq!(assemble(&listing).as_ref(), Ok(&bytes), "{}", listing);
+        }
+    }
+
+    // nasm is the reference the built-in assembler copies, this checks the disassembly of every
+    // encoding and listing against it when it's installed
+    #[test]
+    fn nasm_reassembles_every_encoding() {
+        if Command::new("nasm").arg("-v").output().is_err() {
+            eprintln!("nasm isn't on the PATH, skipping");
+            return;
+        }
+
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/listings");
+        let mut streams: Vec<(String, Vec<u8>)> = std::fs::read_dir(directory).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "bin"))
+            .map(|path| (path.display().to_string(), std::fs::read(&path).unwrap()))
+            .collect();
+        // the encodings go in chunks that keep every address within a segment
+        let mut chunk: Vec<u8> = vec![];
+        for bytes in get_encodings() {
+            chunk.extend(bytes);
+            if chunk.len() > 0x8000 { streams.push((format!("encodings {}", streams.len()), std::mem::take(&mut chunk))); }
+        }
+        streams.push((format!("encodings {}", streams.len()), chunk));
+
+        let source_path = std::env::temp_dir().join(format!("8086_sim_nasm_{}.asm", std::process::id()));
+        let output_path = source_path.with_extension("bin");
+        for (name, bytes) in streams {
+            let (listing, error) = disassemble(&bytes);
+            assert!(error.is_none(), "{} failed to decode", name);
+            std::fs::write(&source_path, &listing).unwrap();
+            let output = Command::new("nasm").arg("-f").arg("bin").arg("-o").arg(&output_path).arg(&source_path).output().unwrap();
+            assert!(output.status.success(), "nasm failed on {}: {}", name, String::from_utf8_lossy(&output.stderr));
+            assert!(std::fs::read(&output_path).unwrap() == bytes, "{} didn't reassemble to the same bytes with nasm", name);
+        }
+        let _ = std::fs::remove_file(&source_path);
+        let _ = std::fs::remove_file(&output_path);
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    fmt,
+    io::Read,
+};
+
+use crate::{
+    decoder::*,
+    machine::*,
+};
+
+// Stand-in for the few DOS services small .com and .exe programs need. Interrupts 20h and 21h are handled
+// here instead of going through the interrupt vector table, int 3 is reported as a breakpoint, int 0
+// (the divide error) ends the program like DOS's divide overflow handler and every other interrupt
+// stops execution since there's no BIOS or DOS behind the vectors.
+
+// segment the program segment prefix (PSP) and the program are loaded at, DOS would pick the first
+// free segment so any segment past the interrupt vectors does
+pub const COM_SEGMENT: u16 = 0x1000;
+// the program and its stack share a single segment after the 256 byte PSP
+pub const MAX_COM_SIZE: usize = 0xFF00;
+// an .exe is loaded right after its PSP, the PSP takes 16 paragraphs
+pub const EXE_LOAD_SEGMENT: u16 = COM_SEGMENT + 0x10;
+// programs get the conventional memory below the video memory at a000:0000
+const MEMORY_END_SEGMENT: u16 = 0xa000;
+
+const AX: u8 = 0;
+const DX: u8 = 2;
+const SP: u8 = 4;
+
+// The $ terminated string at segment:offset, None when a whole segment goes by without a $. The
+// offset wraps around within the segment like it would for a real string instruction.
+fn get_dollar_string(memory: &[u8], segment: u16, offset: u16) -> Option<Vec<u8>> {
+    let mut text = vec![];
+    for index in 0..=u16::MAX {
+        let character = memory[get_physical_address(segment, offset.wrapping_add(index))];
+        if character == b'$' { return Some(text); }
+        text.push(character);
+    }
+    None
+}
+
+pub struct Dos {
+    pub input: Box<dyn Read>,
+    pub exit_code: Option<u8>, // set once the program terminated
+    pub divide_overflow: bool, // set once a divide error ended the program
+}
+
+impl Dos {
+    pub fn new(input: Box<dyn Read>) -> Self { Self { input, exit_code: None, divide_overflow: false } }
+
+    // Whether the interrupt is one of the services handled here, ah selects the int 21h service
+    pub fn is_supported(&self, interrupt_type: u8, ah: u8) -> bool {
+        match interrupt_type {
+            0x00 | 0x03 | 0x20 => true,
+            0x21 => matches!(ah, 0x00 | 0x01 | 0x02 | 0x09 | 0x4c),
+            _ => false,
+        }
+    }
+
+    // Stops before executing an interrupt that isn't handled here or that can't complete, like
+    // printing a string without a $ at the end
+    pub fn check_interrupt(&self, interrupt_type: u8, at: usize, registers: &RegisterSet, memory: &[u8]) -> Result<(), StopReason> {
+        let ah = registers.get_register_value(AX, &RegisterAccess::High) as u8;
+        if !self.is_supported(interrupt_type, ah) {
+            return Err(StopReason::UnsupportedInterrupt { at, interrupt_type, ah });
+        }
+
+        if (interrupt_type, ah) == (0x21, 0x09) {
+            let segment = registers.segment_registers[DS as usize];
+            let offset = registers.get_register_value(DX, &RegisterAccess::Full);
+            if get_dollar_string(memory, segment, offset).is_none() {
+                return Err(StopReason::UnterminatedString { at: get_physical_address(segment, offset) });
+            }
+        }
+
+        Ok(())
+    }
+
+    // Runs a supported interrupt in place of the real handler, after check_interrupt passed
+    pub fn handle_interrupt(&mut self, interrupt_type: u8, registers: &mut RegisterSet, memory: &[u8], events: &mut Vec<StepEvent>) {
+        let ah = registers.get_register_value(AX, &RegisterAccess::High);
+        match (interrupt_type, ah) {
+            // DOS prints "Divide overflow" and ends the program
+            (0x00, _) => self.divide_overflow = true,
+
+            (0x03, _) => events.push(StepEvent::Breakpoint),
+
+            // terminate
+            (0x20, _) | (0x21, 0x00) => self.exit_code = Some(0),
+
+            // read a character with echo, end of input reads as ctrl-z like it would from a file
+            (0x21, 0x01) => {
+                let mut character = [ 0x1a ];
+                if self.input.read(&mut character).unwrap_or(0) == 1 {
+                    events.push(StepEvent::Output(character.to_vec()));
+                }
+                registers.set_register_value(AX, &RegisterAccess::Low, character[0] as u16);
+            },
+
+            // print the character in dl
+            (0x21, 0x02) => {
+                let character = registers.get_register_value(DX, &RegisterAccess::Low);
+                events.push(StepEvent::Output(vec![ character as u8 ]));
+                registers.set_register_value(AX, &RegisterAccess::Low, character);
+            },
+
+            // print the $ terminated string at ds:dx
+            (0x21, 0x09) => {
+                let segment = registers.segment_registers[DS as usize];
+                let offset = registers.get_register_value(DX, &RegisterAccess::Full);
+                let text = get_dollar_string(memory, segment, offset).expect("strings without a $ stop before executing");
+                events.push(StepEvent::Output(text));
+                registers.set_register_value(AX, &RegisterAccess::Low, b'$' as u16);
+            },
+
+            // terminate with the exit code in al
+            (0x21, 0x4c) => self.exit_code = Some(registers.get_register_value(AX, &RegisterAccess::Low) as u8),
+
+            _ => panic!("unsupported interrupts stop before executing"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExeError {
+    // the file is shorter than the fixed part of the header
+    Truncated { size: usize },
+    BadSignature { signature: u16 },
+    // the header size, page count or bytes in the last page don't describe a load module in the file
+    BadImageSize { header_size: usize, image_end: usize, file_size: usize },
+    // the relocation table or a relocation points outside the file or the load module
+    BadRelocation { index: usize },
+    // the load module and the minimum extra allocation don't fit below MEMORY_END_SEGMENT, or the PSP
+    // doesn't fit before the load segment
+    NotEnoughMemory { needed: usize, available: usize },
+}
+
+impl fmt::Display for ExeError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Truncated { size } => write!(formatter, "exe file is {} bytes, too short for an MZ header", size),
+            Self::BadSignature { signature } => write!(formatter, "exe file starts with {:#06x} instead of the MZ signature", signature),
+            Self::BadImageSize { header_size, image_end, file_size } => write!(
+                formatter,
+                "exe header says the load module is bytes {:#x} to {:#x} but the file is {:#x} bytes", header_size, image_end, file_size
+            ),
+            Self::BadRelocation { index } => write!(formatter, "exe relocation {} is outside the file", index),
+            Self::NotEnoughMemory { needed, available } => write!(formatter, "exe needs {} bytes of memory but only {} are available", needed, available),
+        }
+    }
+}
+
+// The fields of an MZ header the loader uses, sizes converted to bytes
+#[derive(Debug, PartialEq)]
+pub struct ExeHeader {
+    pub header_size: usize,
+    pub image_end: usize, // end of the load module in the file
+    pub relocation_count: usize,
+    pub relocation_table: usize,
+    pub min_allocation: usize, // extra memory needed past the load module
+    pub stack_segment: u16, // relative to the load segment
+    pub stack_pointer: u16,
+    pub code_segment: u16, // relative to the load segment
+    pub instruction_pointer: u16,
+}
+
+const EXE_HEADER_SIZE: usize = 0x1c;
+
+fn read_file_word(file: &[u8], offset: usize) -> u16 { u16::from_le_bytes([ file[offset], file[offset + 1] ]) }
+
+pub fn parse_exe_header(file: &[u8]) -> Result<ExeHeader, ExeError> {
+    if file.len() < EXE_HEADER_SIZE { return Err(ExeError::Truncated { size: file.len() }); }
+    // some linkers wrote the signature the other way around, DOS accepts both
+    let signature = read_file_word(file, 0x00);
+    if signature != u16::from_le_bytes(*b"MZ") && signature != u16::from_le_bytes(*b"ZM") {
+        return Err(ExeError::BadSignature { signature });
+    }
+
+    let bytes_in_last_page = read_file_word(file, 0x02) as usize;
+    let pages = read_file_word(file, 0x04) as usize;
+    let header_size = read_file_word(file, 0x08) as usize * 16;
+    // the last page is full when bytes_in_last_page is 0
+    let image_end = match bytes_in_last_page {
+        0 => pages * 512,
+        bytes => pages.saturating_sub(1) * 512 + bytes,
+    };
+    if bytes_in_last_page > 512 || header_size < EXE_HEADER_SIZE || header_size > image_end || image_end > file.len() {
+        return Err(ExeError::BadImageSize { header_size, image_end, file_size: file.len() });
+    }
+
+    Ok(ExeHeader {
+        header_size,
+        image_end,
+        relocation_count: read_file_word(file, 0x06) as usize,
+        relocation_table: read_file_word(file, 0x18) as usize,
+        min_allocation: read_file_word(file, 0x0a) as usize * 16,
+        stack_segment: read_file_word(file, 0x0e),
+        stack_pointer: read_file_word(file, 0x10),
+        code_segment: read_file_word(file, 0x16),
+        instruction_pointer: read_file_word(file, 0x14),
+    })
+}
+
+// Writes the parts of a PSP programs look at
+fn write_psp(memory: &mut [u8], psp_segment: u16) {
+    let psp = get_physical_address(psp_segment, 0);
+    // int 20h
+    memory[psp..psp + 2].copy_from_slice(&[ 0xcd, 0x20 ]);
+    // first segment past the memory available to the program
+    memory[psp + 2..psp + 4].copy_from_slice(&MEMORY_END_SEGMENT.to_le_bytes());
+    // int 21h / retf, the far call entry into DOS
+    memory[psp + 0x50..psp + 0x53].copy_from_slice(&[ 0xcd, 0x21, 0xcb ]);
+    // empty command line
+    memory[psp + 0x80..psp + 0x82].copy_from_slice(&[ 0x00, 0x0d ]);
+}
+
+impl Machine {
+    // Loads a .com program at COM_SEGMENT:0100 after a PSP and starts it like DOS would. Every
+    // segment register points at the PSP and sp at the top of the segment, where a 0 is pushed so a
+    // near ret from the program returns to the int 20h at the start of the PSP.
+    pub fn load_com(&mut self, program: &[u8], input: Box<dyn Read>) {
+        write_psp(&mut self.memory, COM_SEGMENT);
+
+        let start = get_physical_address(COM_SEGMENT, 0x100);
+        self.memory[start..start + program.len()].copy_from_slice(program);
+
+        self.registers.segment_registers = [ COM_SEGMENT; 4 ];
+        self.registers.set_register_value(SP, &RegisterAccess::Full, 0xfffe);
+        let stack_top = get_physical_address(COM_SEGMENT, 0xfffe);
+        self.memory[stack_top..stack_top + 2].copy_from_slice(&[ 0x00, 0x00 ]);
+        self.instruction_pointer = 0x100;
+        self.dos = Some(Dos::new(input));
+    }
+
+    // Loads the load module of an MZ .exe at load_segment with its PSP in the 16 paragraphs before
+    // it, adds load_segment to every relocated segment and starts at the entry point from the header.
+    // ds and es point at the PSP like DOS leaves them.
+    pub fn load_exe(&mut self, file: &[u8], load_segment: u16, input: Box<dyn Read>) -> Result<(), ExeError> {
+        let header = parse_exe_header(file)?;
+        let image = &file[header.header_size..header.image_end];
+
+        let start = get_physical_address(load_segment, 0);
+        let needed = image.len() + header.min_allocation;
+        let available = (MEMORY_END_SEGMENT as usize * 16).saturating_sub(start);
+        if load_segment < 0x10 || needed > available { return Err(ExeError::NotEnoughMemory { needed, available }); }
+
+        // every relocation is checked before anything is loaded
+        let relocations = (0..header.relocation_count)
+            .map(|index| {
+                let entry = header.relocation_table + 4 * index;
+                if entry + 4 > file.len() { return Err(ExeError::BadRelocation { index }); }
+                let address = read_file_word(file, entry + 2) as usize * 16 + read_file_word(file, entry) as usize;
+                if address + 2 > image.len() { return Err(ExeError::BadRelocation { index }); }
+                Ok(address)
+            })
+            .collect::<Result<Vec<usize>, ExeError>>()?;
+
+        let psp_segment = load_segment - 0x10;
+        write_psp(&mut self.memory, psp_segment);
+        self.memory[start..start + image.len()].copy_from_slice(image);
+        for address in relocations {
+            let relocated = read_file_word(image, address).wrapping_add(load_segment);
+            self.memory[start + address..start + address + 2].copy_from_slice(&relocated.to_le_bytes());
+        }
+
+        self.registers.segment_registers[ES as usize] = psp_segment;
+        self.registers.segment_registers[DS as usize] = psp_segment;
+        self.registers.segment_registers[CS as usize] = header.code_segment.wrapping_add(load_segment);
+        self.registers.segment_registers[SS as usize] = header.stack_segment.wrapping_add(load_segment);
+        self.registers.set_register_value(SP, &RegisterAccess::Full, header.stack_pointer);
+        self.instruction_pointer = header.instruction_pointer;
+        self.dos = Some(Dos::new(input));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn run(mut machine: Machine) -> (StopReason, Vec<u8>) {
+        let mut output = vec![];
+        let stop_reason = loop {
+            match machine.step() {
+                Ok(step) => for event in step.events {
+                    if let StepEvent::Output(bytes) = event { output.extend(bytes); }
+                },
+                Err(stop_reason) => break stop_reason,
+            }
+        };
+        (stop_reason, output)
+    }
+
+    fn run_com(program: &[u8], input: &[u8]) -> (StopReason, Vec<u8>) {
+        let mut machine = Machine::new();
+        machine.load_com(program, Box::new(Cursor::new(input.to_vec())));
+        run(machine)
+    }
+
+    // A small model exe with a code, data and stack segment, the data segment is loaded with a
+    // relocated mov
+    fn build_exe() -> Vec<u8> {
+        let mut file = vec![ 0u8; 0x20 ];
+        let code = [
+            0xb8, 0x02, 0x00, // mov ax, seg data
+            0x8e, 0xd8,       // mov ds, ax
+            0xb4, 0x09,       // mov ah, 9
+            0xba, 0x00, 0x00, // mov dx, 0
+            0xcd, 0x21,       // int 21h
+            0xb8, 0x00, 0x4c, // mov ax, 4c00h
+            0xcd, 0x21,       // int 21h
+        ];
+        file.extend(code);
+        file.resize(0x40, 0);
+        file.extend(b"ok$");
+
+        let header: [(usize, u16); 10] = [
+            (0x00, u16::from_le_bytes(*b"MZ")),
+            (0x02, file.len() as u16), // bytes in the last page
+            (0x04, 1), // pages
+            (0x06, 1), // relocations
+            (0x08, 2), // header paragraphs
+            (0x0a, 0x10), // min allocation, the stack
+            (0x0e, 3), // ss
+            (0x10, 0x100), // sp
+            (0x18, 0x1c), // relocation table
+            (0x1c, 0x0001), // relocation at 0000:0001
+        ];
+        for (offset, value) in header {
+            file[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+        }
+        file
+    }
+
+    #[test]
+    fn loads_exe_with_relocations() {
+        let mut machine = Machine::new();
+        machine.load_exe(&build_exe(), EXE_LOAD_SEGMENT, Box::new(std::io::empty())).unwrap();
+        assert_eq!(machine.registers.segment_registers, [ COM_SEGMENT, EXE_LOAD_SEGMENT, EXE_LOAD_SEGMENT + 3, COM_SEGMENT ]);
+        assert_eq!(machine.memory[get_physical_address(EXE_LOAD_SEGMENT, 1)], (EXE_LOAD_SEGMENT + 2) as u8);
+
+        let (stop_reason, output) = run(machine);
+        assert_eq!(stop_reason, StopReason::Exited(0));
+        assert_eq!(output, b"ok");
+    }
+
+    #[test]
+    fn rejects_malformed_exe() {
+        let mut file = build_exe();
+        assert_eq!(parse_exe_header(&file[..0x10]), Err(ExeError::Truncated { size: 0x10 }));
+
+        file[0x04] = 2; // two pages when the file has less than one
+        assert!(matches!(parse_exe_header(&file), Err(ExeError::BadImageSize { .. })));
+
+        let mut file = build_exe();
+        file[0x1e] = 3; // relocation in segment 3, past the end of the load module
+        let mut machine = Machine::new();
+        assert_eq!(machine.load_exe(&file, EXE_LOAD_SEGMENT, Box::new(std::io::empty())), Err(ExeError::BadRelocation { index: 0 }));
+
+        file[0x00] = b'X';
+        assert!(matches!(parse_exe_header(&file), Err(ExeError::BadSignature { .. })));
+    }
+
+    #[test]
+    fn prints_and_exits_with_code() {
+        let program = [
+            0xb4, 0x09,             // mov ah, 9
+            0xba, 0x0f, 0x01,       // mov dx, message
+            0xcd, 0x21,             // int 21h
+            0xb4, 0x01,             // mov ah, 1
+            0xcd, 0x21,             // int 21h
+            0xb4, 0x4c,             // mov ah, 4ch
+            0xcd, 0x21,             // int 21h
+            b'h', b'i', b' ', b'$', // message: db "hi $"
+        ];
+        let (stop_reason, output) = run_com(&program, b"*");
+        assert_eq!(stop_reason, StopReason::Exited(b'*'));
+        assert_eq!(output, b"hi *");
+    }
+
+    #[test]
+    fn ret_returns_to_the_psp() {
+        let (stop_reason, output) = run_com(&[ 0xb2, 0x21, 0xb4, 0x02, 0xcd, 0x21, 0xc3 ], b""); // print '!' / ret
+        assert_eq!(stop_reason, StopReason::Exited(0));
+        assert_eq!(output, b"!");
+    }
+
+    #[test]
+    fn divide_error_ends_the_program() {
+        // mov bl, 0 / div bl / int 20h
+        let mut machine = Machine::new();
+        machine.load_com(&[ 0xb3, 0x00, 0xf6, 0xf3, 0xcd, 0x20 ], Box::new(std::io::empty()));
+        assert_eq!(machine.run_until(10), StopReason::DivideOverflow);
+        // it never went through the empty interrupt vector table
+        assert_eq!((machine.registers.segment_registers[CS as usize], machine.instruction_pointer), (COM_SEGMENT, 0x104));
+    }
+
+    #[test]
+    fn ret_returns_to_the_psp_whatever_was_in_memory() {
+        let mut machine = Machine::new();
+        let stack_top = get_physical_address(COM_SEGMENT, 0xfffe);
+        machine.memory[stack_top..stack_top + 2].copy_from_slice(&[ 0x34, 0x12 ]);
+        machine.load_com(&[ 0xc3 ], Box::new(std::io::empty())); // ret
+        assert_eq!(machine.run_until(10), StopReason::Exited(0));
+    }
+
+    #[test]
+    fn unsupported_interrupt_stops() {
+        let (stop_reason, _) = run_com(&[ 0xb4, 0x0e, 0xcd, 0x10 ], b""); // mov ah, 0eh / int 10h
+        assert_eq!(stop_reason, StopReason::UnsupportedInterrupt { at: 0x10102, interrupt_type: 0x10, ah: 0x0e });
+    }
+
+    #[test]
+    fn string_without_dollar_stops() {
+        let program = [
+            0xb8, 0x00, 0x50, // mov ax, 5000h
+            0x8e, 0xd8,       // mov ds, ax
+            0xb4, 0x09,       // mov ah, 9
+            0xba, 0x00, 0x00, // mov dx, 0
+            0xcd, 0x21,       // int 21h
+        ];
+        let (stop_reason, output) = run_com(&program, b"");
+        assert_eq!(stop_reason, StopReason::UnterminatedString { at: 0x50000 });
+        assert!(output.is_empty());
+    }
+}
@@ -0,0 +1,323 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, Read, Write},
+    net::TcpStream,
+};
+
+use crate::{
+    decoder::*,
+    machine::*,
+};
+
+// Stub for the gdb remote serial protocol, enough for gdb with `set architecture i8086` and
+// `target remote` to step, continue, set breakpoints and look at registers and memory.
+//
+// Registers use the i386 layout gdb expects, 16 32-bit registers with the 8086 registers in their
+// low halves: eax ecx edx ebx esp ebp esi edi eip eflags cs ss ds es fs gs. There's no fs or gs so
+// they read as 0 and ignore writes. Memory and breakpoint addresses are physical addresses since gdb
+// doesn't know about segments, so breaking at cs:ip takes cs * 16 + ip.
+
+const REGISTER_COUNT: usize = 16;
+const EIP: usize = 8;
+const EFLAGS: usize = 9;
+// gdb's segment register order after eflags, by encoding
+const SEGMENT_REGISTERS: [u8; 4] = [ CS, SS, DS, ES ];
+// a running program checks for an interrupt from gdb this often
+const INTERRUPT_POLL_STEPS: usize = 1024;
+
+// signals in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) { return None; }
+    (0..text.len()).step_by(2).map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok()).collect()
+}
+
+fn parse_hex_number(text: &str) -> Option<usize> { usize::from_str_radix(text, 16).ok() }
+
+// addr,length as in m and M packets
+fn parse_memory_range(text: &str, memory_size: usize) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+    let (address, length) = (parse_hex_number(address)?, parse_hex_number(length)?);
+    if address.checked_add(length)? > memory_size { return None; }
+    Some((address, length))
+}
+
+fn get_checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |checksum, byte| checksum.wrapping_add(byte))
+}
+
+enum Response {
+    Reply(Vec<String>), // console output packets come before a stop reply
+    Detach,
+    Kill,
+}
+
+#[derive(Default)]
+pub struct GdbStub {
+    breakpoints: BTreeSet<usize>, // physical addresses
+}
+
+impl GdbStub {
+    pub fn new() -> Self { Self::default() }
+
+    fn read_register(machine: &Machine, number: usize) -> Option<u16> {
+        match number {
+            0..=7 => Some(machine.registers.get_register_value(number as u8, &RegisterAccess::Full)),
+            EIP => Some(machine.instruction_pointer),
+            EFLAGS => Some(machine.flags.to_word()),
+            10..=13 => Some(machine.registers.segment_registers[SEGMENT_REGISTERS[number - 10] as usize]),
+            14 | 15 => Some(0),
+            _ => None,
+        }
+    }
+
+    fn write_register(machine: &mut Machine, number: usize, value: u16) -> bool {
+        match number {
+            0..=7 => machine.registers.set_register_value(number as u8, &RegisterAccess::Full, value),
+            EIP => machine.instruction_pointer = value,
+            EFLAGS => machine.flags = Flags::from_word(value),
+            10..=13 => machine.registers.segment_registers[SEGMENT_REGISTERS[number - 10] as usize] = value,
+            14 | 15 => {},
+            _ => return false,
+        }
+        true
+    }
+
+    // Steps once or until a breakpoint, an int 3, the end of the program or an interrupt from gdb,
+    // with the program's output as console output packets before the stop reply
+    fn resume(&self, machine: &mut Machine, is_single_step: bool, is_interrupted: &mut dyn FnMut() -> bool) -> Vec<String> {
+        let mut packets = vec![];
+        let mut steps: usize = 0;
+        let stop_reply = loop {
+            let step = match machine.step() {
+                Ok(step) => step,
+                Err(StopReason::Halted) => break String::from("W00"),
+                Err(StopReason::Exited(exit_code)) => break format!("W{:02x}", exit_code),
+                Err(_) => break format!("S{:02x}", SIGILL),
+            };
+
+            let mut is_trapped = is_single_step;
+            for event in &step.events {
+                match event {
+                    StepEvent::Output(bytes) => packets.push(format!("O{}", to_hex(bytes))),
+                    StepEvent::Breakpoint => is_trapped = true,
+                    _ => {},
+                }
+            }
+            let address = get_physical_address(machine.registers.segment_registers[CS as usize], machine.instruction_pointer);
+            if is_trapped || self.breakpoints.contains(&address) { break format!("S{:02x}", SIGTRAP); }
+
+            steps += 1;
+            if steps.is_multiple_of(INTERRUPT_POLL_STEPS) && is_interrupted() { break format!("S{:02x}", SIGINT); }
+        };
+
+        packets.push(stop_reply);
+        packets
+    }
+
+    fn handle_packet(&mut self, machine: &mut Machine, packet: &str, is_interrupted: &mut dyn FnMut() -> bool) -> Response {
+        let error = || String::from("E01");
+        let reply = match packet.split_at(packet.len().min(1)) {
+            ("?", _) => format!("S{:02x}", SIGTRAP),
+
+            ("g", _) => (0..REGISTER_COUNT)
+                .map(|number| to_hex(&(Self::read_register(machine, number).expect("every register is readable") as u32).to_le_bytes()))
+                .collect(),
+            ("G", registers) => match from_hex(registers) {
+                Some(bytes) if bytes.len() >= 4 * REGISTER_COUNT => {
+                    for (number, value) in bytes.chunks(4).take(REGISTER_COUNT).enumerate() {
+                        Self::write_register(machine, number, u16::from_le_bytes([ value[0], value[1] ]));
+                    }
+                    String::from("OK")
+                },
+                _ => error(),
+            },
+            ("p", number) => match parse_hex_number(number).and_then(|number| Self::read_register(machine, number)) {
+                Some(value) => to_hex(&(value as u32).to_le_bytes()),
+                None => error(),
+            },
+            ("P", assignment) => {
+                let register = assignment.split_once('=').and_then(|(number, value)| Some((parse_hex_number(number)?, from_hex(value)?)));
+                match register {
+                    Some((number, value)) if value.len() >= 2 && Self::write_register(machine, number, u16::from_le_bytes([ value[0], value[1] ])) => String::from("OK"),
+                    _ => error(),
+                }
+            },
+
+            ("m", range) => match parse_memory_range(range, machine.memory.len()) {
+                Some((address, length)) => to_hex(&machine.memory[address..address + length]),
+                None => error(),
+            },
+            ("M", write) => {
+                let write = write.split_once(':').and_then(|(range, data)| Some((parse_memory_range(range, machine.memory.len())?, from_hex(data)?)));
+                match write {
+                    Some(((address, length), data)) if data.len() == length => {
+                        machine.memory[address..address + length].copy_from_slice(&data);
+                        String::from("OK")
+                    },
+                    _ => error(),
+                }
+            },
+
+            // resuming at another address isn't supported, gdb sets eip itself for that
+            ("s", "") => return Response::Reply(self.resume(machine, true, is_interrupted)),
+            ("c", "") => return Response::Reply(self.resume(machine, false, is_interrupted)),
+
+            ("Z" | "z", arguments) if arguments.starts_with("0,") => {
+                match arguments[2..].split_once(',').and_then(|(address, _kind)| parse_hex_number(address)) {
+                    Some(address) => {
+                        if packet.starts_with('Z') { self.breakpoints.insert(address); } else { self.breakpoints.remove(&address); }
+                        String::from("OK")
+                    },
+                    None => error(),
+                }
+            },
+
+            ("H", _) => String::from("OK"),
+            ("q", "Attached") => String::from("1"),
+            ("D", _) => return Response::Detach,
+            ("k", _) => return Response::Kill,
+            // an empty reply tells gdb the packet isn't supported
+            _ => String::new(),
+        };
+
+        Response::Reply(vec![ reply ])
+    }
+}
+
+fn read_byte(stream: &mut TcpStream) -> io::Result<Option<u8>> {
+    let mut byte = [ 0 ];
+    match stream.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+// Reads the next packet and acknowledges it, None once gdb closed the connection. Acknowledgements
+// from gdb and interrupts while nothing runs are skipped.
+fn read_packet(stream: &mut TcpStream) -> io::Result<Option<String>> {
+    loop {
+        match read_byte(stream)? {
+            None => return Ok(None),
+            Some(b'$') => {},
+            Some(_) => continue,
+        }
+
+        let mut data = vec![];
+        loop {
+            match read_byte(stream)? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(byte) => data.push(byte),
+            }
+        }
+        let mut checksum = [ 0; 2 ];
+        stream.read_exact(&mut checksum)?;
+
+        let data = String::from_utf8_lossy(&data).into_owned();
+        let is_valid = std::str::from_utf8(&checksum).ok()
+            .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+            .is_some_and(|checksum| checksum == get_checksum(&data));
+        if is_valid {
+            stream.write_all(b"+")?;
+            return Ok(Some(data));
+        }
+        stream.write_all(b"-")?;
+    }
+}
+
+fn write_packet(stream: &mut TcpStream, data: &str) -> io::Result<()> {
+    stream.write_all(format!("${}#{:02x}", data, get_checksum(data)).as_bytes())
+}
+
+// Whether gdb sent an interrupt (a lone 0x03) while the program runs, without waiting for one
+fn is_interrupt_pending(stream: &mut TcpStream) -> bool {
+    let mut byte = [ 0 ];
+    let _ = stream.set_nonblocking(true);
+    let is_interrupt = matches!(stream.peek(&mut byte), Ok(1)) && byte[0] == 0x03;
+    let _ = stream.set_nonblocking(false);
+    if is_interrupt { let _ = stream.read(&mut byte); }
+    is_interrupt
+}
+
+// Serves one gdb connection until gdb detaches, kills the program or disconnects
+pub fn serve(machine: &mut Machine, mut stream: TcpStream) -> io::Result<()> {
+    // packets are small and answered one at a time, batching them only adds latency
+    stream.set_nodelay(true)?;
+    let mut stub = GdbStub::new();
+    let mut interrupt_stream = stream.try_clone()?;
+    while let Some(packet) = read_packet(&mut stream)? {
+        match stub.handle_packet(machine, &packet, &mut || is_interrupt_pending(&mut interrupt_stream)) {
+            Response::Reply(packets) => {
+                for packet in packets { write_packet(&mut stream, &packet)?; }
+            },
+            Response::Detach => {
+                write_packet(&mut stream, "OK")?;
+                break;
+            },
+            Response::Kill => break,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        thread,
+    };
+
+    use super::*;
+
+    // Sends each packet like gdb would and collects the replies up to the next stop or plain reply
+    fn run_client(port: u16, packets: &'static [&'static str]) -> thread::JoinHandle<Vec<String>> {
+        thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            stream.set_nodelay(true).unwrap();
+            let mut replies = vec![];
+            for packet in packets {
+                write_packet(&mut stream, packet).unwrap();
+                assert_eq!(read_byte(&mut stream).unwrap(), Some(b'+'));
+                if *packet == "k" { break; }
+                // console output comes in extra packets before the reply
+                loop {
+                    let reply = read_packet(&mut stream).unwrap().unwrap();
+                    let is_output = reply.starts_with('O') && reply != "OK";
+                    replies.push(reply);
+                    if !is_output { break; }
+                }
+            }
+            replies
+        })
+    }
+
+    #[test]
+    fn scripted_session() {
+        // mov cx, 3 / dec cx / jne -3 / hlt
+        let mut machine = Machine::new();
+        machine.load_program(&[ 0xb9, 0x03, 0x00, 0x49, 0x75, 0xfd, 0xf4 ]);
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let client = run_client(listener.local_addr().unwrap().port(), &[
+            "?", "Z0,3,1", "c", "c", "p1", "P1=01000000", "g", "m0,3", "M100,2:abcd", "m100,2", "z0,3,1", "s", "c", "k",
+        ]);
+        let (stream, _) = listener.accept().unwrap();
+        serve(&mut machine, stream).unwrap();
+
+        assert_eq!(client.join().unwrap(), [
+            "S05", "OK", "S05", "S05", "02000000", "OK",
+            "00000000010000000000000000000000000000000000000000000000000000000300000002f00000000000000000000000000000000000000000000000000000",
+            "b90300", "OK", "abcd", "OK", "S05", "W00",
+        ]);
+        assert_eq!(machine.registers.get_register_value(1, &RegisterAccess::Full), 0);
+    }
+}
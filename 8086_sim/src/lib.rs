@@ -0,0 +1,11 @@
+pub mod assembler;
+pub mod debugger;
+pub mod decoder;
+pub mod disassembler;
+pub mod dos;
+pub mod gdb;
+pub mod limits;
+pub mod machine;
+pub mod prefetch;
+pub mod profile;
+pub mod trace;
@@ -0,0 +1,128 @@
+use std::{
+    collections::HashSet,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use crate::{
+    decoder::*,
+    machine::*,
+};
+
+// Stops a run that goes on for too long, or that can't ever end because the machine came back to
+// exactly the state it was in before: same cs:ip, registers, flags and memory. Execution is
+// deterministic from there, so it would keep going around the same loop forever.
+//
+// Hashing all of memory every step would be far too slow, so memory is only hashed once the cpu
+// state alone repeats. That catches a loop one time around later than it could be caught, which
+// doesn't matter for something that never ends anyway.
+
+#[derive(Default)]
+pub struct RunLimits {
+    pub max_instructions: Option<u64>,
+    pub max_clocks: Option<u64>,
+    pub should_detect_loops: bool,
+    executed_instructions: u64,
+    seen_cpu_states: HashSet<u64>,
+    seen_states: HashSet<u64>, // cpu state and memory, of cpu states that were seen before
+}
+
+fn hash_cpu_state(machine: &Machine) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    machine.instruction_pointer.hash(&mut hasher);
+    machine.registers.registers.hash(&mut hasher);
+    machine.registers.segment_registers.hash(&mut hasher);
+    machine.flags.to_word().hash(&mut hasher);
+    hasher.finish()
+}
+
+// a megabyte through DefaultHasher is slow enough to notice, a multiply and rotate per word isn't
+fn hash_memory(memory: &[u8]) -> u64 {
+    memory.chunks_exact(8).fold(0, |hash: u64, word| {
+        (hash.rotate_left(5) ^ u64::from_le_bytes(word.try_into().unwrap())).wrapping_mul(0x517cc1b727220a95)
+    })
+}
+
+// Whether the step read a character with int 21h. Input makes the same state go somewhere else the
+// next time around, so the states seen before it don't count.
+fn did_read_input(machine: &Machine, step: &Step) -> bool {
+    const AX: u8 = 0;
+    machine.dos.is_some()
+        && step.instruction.operation == Operation::Interrupt
+        && matches!(step.instruction.operands[0], Some(Operand::ImmediateData(0x21)))
+        && machine.registers.get_register_value(AX, &RegisterAccess::High) == 0x01
+}
+
+impl RunLimits {
+    pub fn new() -> Self { Self::default() }
+
+    // Machine::step, except it stops once a limit is reached or the machine is in a state it was in
+    // before
+    pub fn step(&mut self, machine: &mut Machine) -> Result<Step, StopReason> {
+        if self.max_instructions.is_some_and(|max_instructions| self.executed_instructions >= max_instructions) {
+            return Err(StopReason::MaxSteps);
+        }
+        if self.max_clocks.is_some_and(|max_clocks| machine.total_clocks >= max_clocks) {
+            return Err(StopReason::MaxClocks);
+        }
+
+        if self.should_detect_loops {
+            let cpu_state = hash_cpu_state(machine);
+            if !self.seen_cpu_states.insert(cpu_state) {
+                let state = cpu_state ^ hash_memory(&machine.memory);
+                if !self.seen_states.insert(state) {
+                    return Err(StopReason::InfiniteLoop { instruction_pointer: machine.instruction_pointer });
+                }
+            }
+        }
+
+        let step = machine.step()?;
+        self.executed_instructions += 1;
+        if self.should_detect_loops && did_read_input(machine, &step) {
+            self.seen_cpu_states.clear();
+            self.seen_states.clear();
+        }
+        Ok(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(machine: &mut Machine, run_limits: &mut RunLimits) -> StopReason {
+        loop {
+            if let Err(stop_reason) = run_limits.step(machine) { return stop_reason; }
+        }
+    }
+
+    #[test]
+    fn instruction_and_clock_limits() {
+        // jmp -2
+        let mut machine = Machine::new();
+        machine.load_program(&[ 0xeb, 0xfe ]);
+        let mut run_limits = RunLimits { max_instructions: Some(10), ..RunLimits::new() };
+        assert_eq!(run(&mut machine, &mut run_limits), StopReason::MaxSteps);
+        assert_eq!(machine.total_clocks, 150);
+
+        // the instruction that crosses the limit still runs
+        let mut run_limits = RunLimits { max_clocks: Some(160), ..RunLimits::new() };
+        assert_eq!(run(&mut machine, &mut run_limits), StopReason::MaxClocks);
+        assert_eq!(machine.total_clocks, 165);
+    }
+
+    #[test]
+    fn loops_only_stop_on_an_exact_repeat() {
+        // mov cx, 3 / dec cx / jne -3 comes back to dec cx with a different cx every time
+        let mut machine = Machine::new();
+        machine.load_program(&[ 0xb9, 0x03, 0x00, 0x49, 0x75, 0xfd ]);
+        let mut run_limits = RunLimits { should_detect_loops: true, ..RunLimits::new() };
+        assert_eq!(run(&mut machine, &mut run_limits), StopReason::Halted);
+
+        // not byte [0x100] / jmp -6 changes only memory and repeats every second time around
+        let mut machine = Machine::new();
+        machine.load_program(&[ 0xf6, 0x16, 0x00, 0x01, 0xeb, 0xfa ]);
+        let mut run_limits = RunLimits { should_detect_loops: true, ..RunLimits::new() };
+        assert_eq!(run(&mut machine, &mut run_limits), StopReason::InfiniteLoop { instruction_pointer: 0 });
+        assert_eq!(run_limits.executed_instructions, 6);
+    }
+}
@@ -0,0 +1,2015 @@
+use std::fmt;
+
+use crate::{
+    decoder::*,
+    dos::*,
+    prefetch::*,
+};
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct Flags {
+    pub carry: bool,
+    pub parity: bool,
+    pub auxiliary_carry: bool,
+    pub zero: bool,
+    pub sign: bool,
+    pub trap: bool,
+    pub interrupt: bool,
+    pub direction: bool,
+    pub overflow: bool,
+}
+
+impl Flags {
+    pub fn new() -> Self {
+        Self {
+            carry: false,
+            parity: false,
+            auxiliary_carry: false,
+            zero: false,
+            sign: false,
+            trap: false,
+            interrupt: false,
+            direction: false,
+            overflow: false,
+        }
+    }
+
+    // bit layout of the flags register as pushed by pushf, unused bits read as 1
+    pub fn to_word(&self) -> u16 {
+        0b1111_0000_0000_0010
+            | self.carry as u16
+            | (self.parity as u16) << 2
+            | (self.auxiliary_carry as u16) << 4
+            | (self.zero as u16) << 6
+            | (self.sign as u16) << 7
+            | (self.trap as u16) << 8
+            | (self.interrupt as u16) << 9
+            | (self.direction as u16) << 10
+            | (self.overflow as u16) << 11
+    }
+
+    pub fn from_word(word: u16) -> Self {
+        Self {
+            carry: word & 1 != 0,
+            parity: (word >> 2) & 1 != 0,
+            auxiliary_carry: (word >> 4) & 1 != 0,
+            zero: (word >> 6) & 1 != 0,
+            sign: (word >> 7) & 1 != 0,
+            trap: (word >> 8) & 1 != 0,
+            interrupt: (word >> 9) & 1 != 0,
+            direction: (word >> 10) & 1 != 0,
+            overflow: (word >> 11) & 1 != 0,
+        }
+    }
+
+    // Whether the flags satisfy the condition of a conditional jump. jcxz tests cx, not the flags.
+    fn is_condition_met(&self, operation: &Operation) -> bool {
+        match operation {
+            Operation::Jmp_On_Overflow => self.overflow,
+            Operation::Jmp_On_Not_Overflow => !self.overflow,
+            Operation::Jmp_On_Below => self.carry,
+            Operation::Jmp_On_Not_Below => !self.carry,
+            Operation::Jmp_On_Equal => self.zero,
+            Operation::Jmp_On_Not_Equal => !self.zero,
+            Operation::Jmp_On_Below_Or_Equal => self.carry || self.zero,
+            Operation::Jmp_On_Above => !self.carry && !self.zero,
+            Operation::Jmp_On_Sign => self.sign,
+            Operation::Jmp_On_Not_Sign => !self.sign,
+            Operation::Jmp_On_Parity => self.parity,
+            Operation::Jmp_On_Not_Parity => !self.parity,
+            Operation::Jmp_On_Less => self.sign != self.overflow,
+            Operation::Jmp_On_Not_Less => self.sign == self.overflow,
+            Operation::Jmp_On_Less_Or_Equal => self.zero || self.sign != self.overflow,
+            Operation::Jmp_On_Greater => !self.zero && self.sign == self.overflow,
+            _ => panic!("not a conditional jump that tests flags"),
+        }
+    }
+
+    pub fn get_active_flags_string(&self) -> String {
+        let mut str = String::with_capacity(9); // should match number of flag fields
+        if self.carry { str += "C"; }
+        if self.parity { str += "P"; }
+        if self.auxiliary_carry { str += "A"; }
+        if self.sign { str += "S"; }
+        if self.zero { str += "Z"; }
+        if self.overflow { str += "O"; }
+        if self.direction { str += "D"; }
+        if self.interrupt { str += "I"; }
+        if self.trap { str += "T"; }
+
+        str
+    }
+}
+
+const AX: u8 = 0;
+const SP: u8 = 4;
+
+#[derive(Clone, Default)]
+pub struct RegisterSet { pub registers: [u16; 8], pub segment_registers: [u16; 4] }
+impl RegisterSet {
+    pub fn new() -> Self { Self { registers: [0u16; 8], segment_registers: [0u16; 4] } }
+
+    // 8-bit register encodings 0b100..0b111 (ah, ch, dh, bh) refer to the high halves of the first
+    // four registers rather than sp, bp, si, di
+    fn get_register_index(encoding: u8, access: &RegisterAccess) -> usize {
+        match access {
+            RegisterAccess::Full => encoding as usize,
+            RegisterAccess::Low | RegisterAccess::High => (encoding & 0b11) as usize,
+        }
+    }
+
+    pub fn get_register_value(&self, encoding: u8, access: &RegisterAccess) -> u16 {
+        let register = self.registers[Self::get_register_index(encoding, access)];
+        match access {
+            RegisterAccess::Low => register.to_le_bytes()[0] as u16,
+            RegisterAccess::High => register.to_le_bytes()[1] as u16,
+            RegisterAccess::Full => register,
+        }
+    }
+
+    pub fn set_register_value(&mut self, encoding: u8, access: &RegisterAccess, value: u16) {
+        let register = &mut self.registers[Self::get_register_index(encoding, access)];
+
+        match access {
+            RegisterAccess::Full => *register = value,
+            RegisterAccess::High => set_high_byte(register, value as u8),
+            RegisterAccess::Low => set_low_byte(register, value as u8),
+        };
+    }
+
+    fn calculate_effective_address(&self, base: &EffectiveAddressBase, displacement: u16) -> u16 {
+        base.get_register_encodings()
+            .iter()
+            .filter_map(|v| *v)
+            .fold(displacement, |acc, reg| acc.wrapping_add(self.get_register_value(reg, &RegisterAccess::Full)))
+    }
+
+    // segment and offset an effective address refers to
+    fn resolve_effective_address(&self, effective_address: &EffectiveAddress) -> (u16, u16) {
+        let offset = match effective_address {
+            EffectiveAddress::Direct { address, .. } => *address,
+            EffectiveAddress::Calculated { base, displacement, .. } => self.calculate_effective_address(base, *displacement),
+        };
+
+        (self.segment_registers[effective_address.get_segment() as usize], offset)
+    }
+}
+
+// 20-bit physical address of segment:offset, addresses past 1MB wrap around to 0 like on the 8086
+pub fn get_physical_address(segment: u16, offset: u16) -> usize {
+    (((segment as usize) << 4) + offset as usize) & 0xFFFFF
+}
+
+// The high byte of a word at offset 0xFFFF wraps around to the start of the segment.
+fn read_memory(memory: &[u8], segment: u16, offset: u16, wide: bool) -> u16 {
+    let low = memory[get_physical_address(segment, offset)] as u16;
+    if !wide { return low; }
+
+    let high = memory[get_physical_address(segment, offset.wrapping_add(1))] as u16;
+    high << 8 | low
+}
+
+fn write_memory(memory: &mut [u8], segment: u16, offset: u16, value: u16, wide: bool) {
+    let [ lo, hi ] = value.to_le_bytes();
+    memory[get_physical_address(segment, offset)] = lo;
+    if wide { memory[get_physical_address(segment, offset.wrapping_add(1))] = hi; }
+}
+
+// wide only matters for memory operands, registers already know their width from their access
+fn read_operand(operand: &Operand, wide: bool, register_set: &RegisterSet, memory: &[u8]) -> u16 {
+    match operand {
+        Operand::Register(encoding, access) => register_set.get_register_value(*encoding, access),
+        Operand::SegmentRegister(encoding) => register_set.segment_registers[*encoding as usize],
+        Operand::Memory(effective_address) => {
+            let (segment, offset) = register_set.resolve_effective_address(effective_address);
+            read_memory(memory, segment, offset, wide)
+        },
+        Operand::ImmediateData(data) => *data,
+        Operand::LabelOffset(_) | Operand::FarAddress { .. } => panic!("offset value cannot be read as an operand"),
+    }
+}
+
+fn write_operand(operand: &Operand, wide: bool, value: u16, register_set: &mut RegisterSet, memory: &mut [u8]) {
+    match operand {
+        Operand::Register(encoding, access) => register_set.set_register_value(*encoding, access, value),
+        Operand::SegmentRegister(encoding) => register_set.segment_registers[*encoding as usize] = value,
+        Operand::Memory(effective_address) => {
+            let (segment, offset) = register_set.resolve_effective_address(effective_address);
+            write_memory(memory, segment, offset, value, wide);
+        },
+        _ => panic!("cannot write into immediate or label offset"),
+    }
+}
+
+// Sets the flags that only depend on the result of an operation
+fn set_result_flags(flags: &mut Flags, result: u16, wide: bool) {
+    let result = if wide { result } else { result & 0xFF };
+    let sign_bit = if wide { 0x8000 } else { 0x80 };
+    flags.zero = result == 0;
+    flags.sign = result & sign_bit != 0;
+    // parity only looks at the low byte, even for word operations
+    flags.parity = (result & 0xFF).count_ones() % 2 == 0;
+}
+
+// Computes the result of an arithmetic or logical operation at the width of the instruction and
+// updates the flags it affects. Returns None for operations that only set flags (cmp, test).
+fn alu(operation: &Operation, destination: u16, source: u16, wide: bool, flags: &mut Flags) -> Option<u16> {
+    let mask: u32 = if wide { 0xFFFF } else { 0xFF };
+    let sign_bit: u32 = if wide { 0x8000 } else { 0x80 };
+    let destination = destination as u32 & mask;
+    let source = source as u32 & mask;
+    let carry_in = flags.carry as u32;
+
+    // overflow is set when the signed result doesn't fit, i.e. the sign of the result is wrong
+    let add_overflow = |result: u32| (destination ^ result) & (source ^ result) & sign_bit != 0;
+    let sub_overflow = |result: u32| (destination ^ source) & (destination ^ result) & sign_bit != 0;
+    // auxiliary carry is the carry out of (or borrow into) the low nibble
+    let auxiliary_carry = |lhs: u32, rhs: u32, result: u32| (lhs ^ rhs ^ result) & 0x10 != 0;
+
+    let (result, carry, overflow, auxiliary) = match operation {
+        Operation::Add_RegMem_With_Reg_To_Either
+        | Operation::Add_Imm_To_RegMem
+        | Operation::Add_Imm_To_Acc
+        => {
+            let sum = destination + source;
+            (sum, Some(sum > mask), add_overflow(sum), auxiliary_carry(destination, source, sum))
+        },
+
+        Operation::Adc_RegMem_With_Reg_To_Either
+        | Operation::Adc_Imm_To_RegMem
+        | Operation::Adc_Imm_To_Acc
+        => {
+            let sum = destination + source + carry_in;
+            (sum, Some(sum > mask), add_overflow(sum), auxiliary_carry(destination, source, sum))
+        },
+
+        Operation::Sub_RegMem_And_Reg_From_Either
+        | Operation::Sub_Imm_From_RegMem
+        | Operation::Sub_Imm_From_Acc
+        | Operation::Cmp_RegMem_And_Reg
+        | Operation::Cmp_Imm_With_RegMem
+        | Operation::Cmp_Imm_With_Acc
+        => {
+            let difference = destination.wrapping_sub(source);
+            (difference, Some(source > destination), sub_overflow(difference), auxiliary_carry(destination, source, difference))
+        },
+
+        Operation::Sbb_RegMem_And_Reg_From_Either
+        | Operation::Sbb_Imm_From_RegMem
+        | Operation::Sbb_Imm_From_Acc
+        => {
+            let difference = destination.wrapping_sub(source).wrapping_sub(carry_in);
+            (difference, Some(source + carry_in > destination), sub_overflow(difference), auxiliary_carry(destination, source, difference))
+        },
+
+        Operation::And_RegMem_With_Reg_To_Either
+        | Operation::And_Imm_To_RegMem
+        | Operation::And_Imm_To_Acc
+        | Operation::Test_RegMem_And_Reg
+        | Operation::Test_Imm_And_RegMem
+        | Operation::Test_Imm_And_Acc
+        => (destination & source, Some(false), false, false),
+
+        Operation::Or_RegMem_And_Reg_To_Either
+        | Operation::Or_Imm_To_RegMem
+        | Operation::Or_Imm_To_Acc
+        => (destination | source, Some(false), false, false),
+
+        Operation::Xor_RegMem_And_Reg_To_Either
+        | Operation::Xor_Imm_To_RegMem
+        | Operation::Xor_Imm_To_Acc
+        => (destination ^ source, Some(false), false, false),
+
+        // inc and dec leave the carry flag untouched
+        Operation::Inc_RegMem | Operation::Inc_Reg => {
+            let sum = destination + 1;
+            (sum, None, destination == sign_bit - 1, auxiliary_carry(destination, 1, sum))
+        },
+        Operation::Dec_RegMem | Operation::Dec_Reg => {
+            let difference = destination.wrapping_sub(1);
+            (difference, None, destination == sign_bit, auxiliary_carry(destination, 1, difference))
+        },
+        Operation::Neg => {
+            let difference = 0u32.wrapping_sub(destination);
+            (difference, Some(destination != 0), destination == sign_bit, auxiliary_carry(0, destination, difference))
+        },
+        // not doesn't affect any flags
+        Operation::Not => return Some((!destination & mask) as u16),
+
+        _ => panic!("not an arithmetic or logical operation"),
+    };
+
+    let result = result & mask;
+    if let Some(carry) = carry { flags.carry = carry; }
+    flags.overflow = overflow;
+    flags.auxiliary_carry = auxiliary;
+    set_result_flags(flags, result as u16, wide);
+
+    match operation {
+        Operation::Cmp_RegMem_And_Reg
+        | Operation::Cmp_Imm_With_RegMem
+        | Operation::Cmp_Imm_With_Acc
+        | Operation::Test_RegMem_And_Reg
+        | Operation::Test_Imm_And_RegMem
+        | Operation::Test_Imm_And_Acc
+        => None,
+
+        _ => Some(result as u16),
+    }
+}
+
+// Shifts or rotates a value count times at the width of the instruction, updating flags. A count of
+// zero leaves both the value and the flags untouched. The 8086 doesn't mask the count.
+fn shift_rotate(operation: &Operation, destination: u16, count: u16, wide: bool, flags: &mut Flags) -> u16 {
+    let mask: u32 = if wide { 0xFFFF } else { 0xFF };
+    let sign_bit: u32 = if wide { 0x8000 } else { 0x80 };
+    let mut value = destination as u32 & mask;
+    if count == 0 { return value as u16; }
+
+    let mut carry = flags.carry;
+    for _ in 0 .. count {
+        let high_bit = value & sign_bit != 0;
+        let low_bit = value & 1 == 1;
+        value = match operation {
+            Operation::Shl => (value << 1) & mask,
+            Operation::Shr => value >> 1,
+            Operation::Sar => (value >> 1) | (value & sign_bit),
+            Operation::Rol => ((value << 1) & mask) | high_bit as u32,
+            Operation::Ror => (value >> 1) | if low_bit { sign_bit } else { 0 },
+            Operation::Rcl => ((value << 1) & mask) | carry as u32,
+            Operation::Rcr => (value >> 1) | if carry { sign_bit } else { 0 },
+            _ => panic!("not a shift or rotate operation"),
+        };
+        carry = match operation {
+            Operation::Shl | Operation::Rol | Operation::Rcl => high_bit,
+            _ => low_bit,
+        };
+    }
+
+    let high_bit = value & sign_bit != 0;
+    let next_high_bit = value & (sign_bit >> 1) != 0;
+    flags.carry = carry;
+    // overflow is only defined for single bit shifts, but the 8086 applies the same rule for any count
+    flags.overflow = match operation {
+        Operation::Shl | Operation::Rol | Operation::Rcl => high_bit != carry,
+        Operation::Shr => destination as u32 & sign_bit != 0,
+        Operation::Sar => false,
+        _ => high_bit != next_high_bit,
+    };
+
+    // rotates only affect carry and overflow
+    if matches!(operation, Operation::Shl | Operation::Shr | Operation::Sar) {
+        set_result_flags(flags, value as u16, wide);
+    }
+
+    value as u16
+}
+
+// Executes a string instruction, repeating it while CX is nonzero if it has a rep prefix. cmps and
+// scas additionally stop repeating when the zero flag no longer matches the prefix's z bit. Returns
+// the number of times the instruction was repeated.
+fn execute_string_instruction(instruction: &Instruction, register_set: &mut RegisterSet, flags: &mut Flags, memory: &mut [u8]) -> u32 {
+    const AX: u8 = 0;
+    const CX: u8 = 1;
+    const SI: u8 = 6;
+    const DI: u8 = 7;
+
+    let wide = instruction.flags.wide;
+    let access = if wide { RegisterAccess::Full } else { RegisterAccess::Low };
+    let element_size: u16 = if wide { 2 } else { 1 };
+    let step = if flags.direction { element_size.wrapping_neg() } else { element_size };
+    // the source at ds:si can be given a segment override, the destination is always es:di
+    let source_segment = register_set.segment_registers[instruction.flags.segment_override.unwrap_or(DS) as usize];
+    let destination_segment = register_set.segment_registers[ES as usize];
+
+    let mut repetitions = 0;
+    loop {
+        if instruction.flags.repeat && register_set.get_register_value(CX, &RegisterAccess::Full) == 0 { break; }
+
+        let si = register_set.get_register_value(SI, &RegisterAccess::Full);
+        let di = register_set.get_register_value(DI, &RegisterAccess::Full);
+        let (uses_si, uses_di) = match instruction.operation {
+            Operation::Movs => {
+                let value = read_memory(memory, source_segment, si, wide);
+                write_memory(memory, destination_segment, di, value, wide);
+                (true, true)
+            },
+            Operation::Cmps => {
+                let source = read_memory(memory, source_segment, si, wide);
+                let destination = read_memory(memory, destination_segment, di, wide);
+                alu(&Operation::Cmp_RegMem_And_Reg, source, destination, wide, flags);
+                (true, true)
+            },
+            Operation::Scas => {
+                let accumulator = register_set.get_register_value(AX, &access);
+                alu(&Operation::Cmp_RegMem_And_Reg, accumulator, read_memory(memory, destination_segment, di, wide), wide, flags);
+                (false, true)
+            },
+            Operation::Lods => {
+                register_set.set_register_value(AX, &access, read_memory(memory, source_segment, si, wide));
+                (true, false)
+            },
+            Operation::Stos => {
+                write_memory(memory, destination_segment, di, register_set.get_register_value(AX, &access), wide);
+                (false, true)
+            },
+            _ => panic!("not a string operation"),
+        };
+
+        if uses_si { register_set.set_register_value(SI, &RegisterAccess::Full, si.wrapping_add(step)); }
+        if uses_di { register_set.set_register_value(DI, &RegisterAccess::Full, di.wrapping_add(step)); }
+
+        if !instruction.flags.repeat { break; }
+        repetitions += 1;
+
+        let cx = register_set.get_register_value(CX, &RegisterAccess::Full);
+        register_set.set_register_value(CX, &RegisterAccess::Full, cx - 1);
+
+        let is_comparison = matches!(instruction.operation, Operation::Cmps | Operation::Scas);
+        if is_comparison && flags.zero != instruction.flags.repeat_on_zero { break; }
+    }
+
+    repetitions
+}
+
+struct DivideError;
+
+// Executes mul, imul, div and idiv. Byte operations use AL (AX for division) and leave the result in
+// AX (AH:AL for division), word operations use AX (DX:AX for division) and leave the result in DX:AX.
+fn execute_multiply_divide(operation: &Operation, source: u16, wide: bool, register_set: &mut RegisterSet, flags: &mut Flags) -> Result<(), DivideError> {
+    const AX: u8 = 0;
+    const DX: u8 = 2;
+
+    let ax = register_set.get_register_value(AX, &RegisterAccess::Full);
+    let dx = register_set.get_register_value(DX, &RegisterAccess::Full);
+
+    match (operation, wide) {
+        (Operation::Mul, false) => {
+            let product = (ax & 0xFF) * (source & 0xFF);
+            register_set.set_register_value(AX, &RegisterAccess::Full, product);
+            flags.carry = product > 0xFF;
+            flags.overflow = flags.carry;
+        },
+        (Operation::Mul, true) => {
+            let product = ax as u32 * source as u32;
+            register_set.set_register_value(AX, &RegisterAccess::Full, product as u16);
+            register_set.set_register_value(DX, &RegisterAccess::Full, (product >> 16) as u16);
+            flags.carry = product > 0xFFFF;
+            flags.overflow = flags.carry;
+        },
+        (Operation::Imul, false) => {
+            let product = (ax as u8 as i8 as i16) * (source as u8 as i8 as i16);
+            register_set.set_register_value(AX, &RegisterAccess::Full, product as u16);
+            flags.carry = product != product as i8 as i16;
+            flags.overflow = flags.carry;
+        },
+        (Operation::Imul, true) => {
+            let product = (ax as i16 as i32) * (source as i16 as i32);
+            register_set.set_register_value(AX, &RegisterAccess::Full, product as u16);
+            register_set.set_register_value(DX, &RegisterAccess::Full, (product >> 16) as u16);
+            flags.carry = product != product as i16 as i32;
+            flags.overflow = flags.carry;
+        },
+        (Operation::Div, false) => {
+            let divisor = source & 0xFF;
+            if divisor == 0 || ax / divisor > 0xFF { return Err(DivideError); }
+            register_set.set_register_value(AX, &RegisterAccess::Low, ax / divisor);
+            register_set.set_register_value(AX, &RegisterAccess::High, ax % divisor);
+        },
+        (Operation::Div, true) => {
+            let dividend = (dx as u32) << 16 | ax as u32;
+            let divisor = source as u32;
+            if divisor == 0 || dividend / divisor > 0xFFFF { return Err(DivideError); }
+            register_set.set_register_value(AX, &RegisterAccess::Full, (dividend / divisor) as u16);
+            register_set.set_register_value(DX, &RegisterAccess::Full, (dividend % divisor) as u16);
+        },
+        // the 8086 raises a divide error for the most negative quotient, later cpus don't
+        (Operation::Idiv, false) => {
+            let dividend = ax as i16 as i32;
+            let divisor = source as u8 as i8 as i32;
+            if divisor == 0 || !(-127 ..= 127).contains(&(dividend / divisor)) { return Err(DivideError); }
+            register_set.set_register_value(AX, &RegisterAccess::Low, (dividend / divisor) as u16);
+            register_set.set_register_value(AX, &RegisterAccess::High, (dividend % divisor) as u16);
+        },
+        (Operation::Idiv, true) => {
+            let dividend = ((dx as u32) << 16 | ax as u32) as i32 as i64;
+            let divisor = source as i16 as i64;
+            if divisor == 0 || !(-32767 ..= 32767).contains(&(dividend / divisor)) { return Err(DivideError); }
+            register_set.set_register_value(AX, &RegisterAccess::Full, (dividend / divisor) as u16);
+            register_set.set_register_value(DX, &RegisterAccess::Full, (dividend % divisor) as u16);
+        },
+        _ => panic!("not a multiply or divide operation"),
+    };
+
+    Ok(())
+}
+
+// Executes the sign extensions and the ascii/decimal adjustments, which all operate on AL, AH or AX.
+// base is the immediate byte following aam and aad.
+fn execute_accumulator_adjust(operation: &Operation, base: u16, register_set: &mut RegisterSet, flags: &mut Flags) -> Result<(), DivideError> {
+    const AX: u8 = 0;
+    const DX: u8 = 2;
+
+    let al = register_set.get_register_value(AX, &RegisterAccess::Low);
+    let ah = register_set.get_register_value(AX, &RegisterAccess::High);
+    let set_al = |register_set: &mut RegisterSet, value: u16| register_set.set_register_value(AX, &RegisterAccess::Low, value);
+    let set_ah = |register_set: &mut RegisterSet, value: u16| register_set.set_register_value(AX, &RegisterAccess::High, value);
+
+    match operation {
+        Operation::Cbw => set_ah(register_set, if al & 0x80 != 0 { 0xFF } else { 0 }),
+        Operation::Cwd => {
+            let ax = register_set.get_register_value(AX, &RegisterAccess::Full);
+            register_set.set_register_value(DX, &RegisterAccess::Full, if ax & 0x8000 != 0 { 0xFFFF } else { 0 });
+        },
+
+        Operation::Aaa | Operation::Aas => {
+            let adjust = al & 0x0F > 9 || flags.auxiliary_carry;
+            if adjust {
+                if *operation == Operation::Aaa {
+                    set_al(register_set, al.wrapping_add(6));
+                    set_ah(register_set, ah.wrapping_add(1));
+                } else {
+                    set_al(register_set, al.wrapping_sub(6));
+                    set_ah(register_set, ah.wrapping_sub(1));
+                }
+            }
+            let al = register_set.get_register_value(AX, &RegisterAccess::Low);
+            set_al(register_set, al & 0x0F);
+            flags.auxiliary_carry = adjust;
+            flags.carry = adjust;
+        },
+
+        Operation::Daa | Operation::Das => {
+            let mut result = al;
+            let mut carry = flags.carry;
+            let is_add = *operation == Operation::Daa;
+            let adjust = |value: u16, by: u16| if is_add { value.wrapping_add(by) & 0xFF } else { value.wrapping_sub(by) & 0xFF };
+
+            let low_adjust = al & 0x0F > 9 || flags.auxiliary_carry;
+            if low_adjust {
+                // the low adjustment can carry/borrow out of the byte on its own
+                carry |= if is_add { al + 6 > 0xFF } else { al < 6 };
+                result = adjust(result, 6);
+            }
+            let high_adjust = al > 0x99 || flags.carry;
+            if high_adjust {
+                result = adjust(result, 0x60);
+                carry = true;
+            }
+
+            set_al(register_set, result);
+            flags.auxiliary_carry = low_adjust;
+            flags.carry = carry;
+            set_result_flags(flags, result, false);
+        },
+
+        Operation::Aam => {
+            if base & 0xFF == 0 { return Err(DivideError); }
+            set_ah(register_set, al / base);
+            set_al(register_set, al % base);
+            set_result_flags(flags, al % base, false);
+        },
+
+        Operation::Aad => {
+            let result = (ah * base + al) & 0xFF;
+            set_al(register_set, result);
+            set_ah(register_set, 0);
+            set_result_flags(flags, result, false);
+        },
+
+        _ => panic!("not an accumulator adjust operation"),
+    };
+
+    Ok(())
+}
+
+// Pushes flags, cs and ip, clears the interrupt and trap flags, then jumps through the vector at
+// 0000:(type * 4).
+fn interrupt(interrupt_type: u8, register_set: &mut RegisterSet, memory: &mut [u8], flags: &mut Flags, instruction_pointer: &mut u16) {
+    push(register_set, memory, flags.to_word());
+    flags.interrupt = false;
+    flags.trap = false;
+    let cs = register_set.segment_registers[CS as usize];
+    push(register_set, memory, cs);
+    push(register_set, memory, *instruction_pointer);
+
+    let vector_address = interrupt_type as usize * 4;
+    *instruction_pointer = read_word(memory, vector_address);
+    register_set.segment_registers[CS as usize] = read_word(memory, vector_address + 2);
+}
+
+fn push(register_set: &mut RegisterSet, memory: &mut [u8], value: u16) {
+    let sp = register_set.get_register_value(SP, &RegisterAccess::Full).wrapping_sub(2);
+    register_set.set_register_value(SP, &RegisterAccess::Full, sp);
+    write_memory(memory, register_set.segment_registers[SS as usize], sp, value, true);
+}
+
+fn pop(register_set: &mut RegisterSet, memory: &[u8]) -> u16 {
+    let sp = register_set.get_register_value(SP, &RegisterAccess::Full);
+    register_set.set_register_value(SP, &RegisterAccess::Full, sp.wrapping_add(2));
+    read_memory(memory, register_set.segment_registers[SS as usize], sp, true)
+}
+
+fn push_register_changes(events: &mut Vec<StepEvent>, before: &RegisterSet, after: &RegisterSet) {
+    for (register_index, (value_before, value_after)) in before.registers.iter().zip(after.registers.iter()).enumerate() {
+        if value_before != value_after {
+            events.push(StepEvent::Register {
+                encoding: register_index as u8,
+                access: RegisterAccess::Full,
+                before: *value_before,
+                after: *value_after,
+            });
+        }
+    }
+
+    for (register_index, (value_before, value_after)) in before.segment_registers.iter().zip(after.segment_registers.iter()).enumerate() {
+        if value_before != value_after {
+            events.push(StepEvent::SegmentRegister { encoding: register_index as u8, before: *value_before, after: *value_after });
+        }
+    }
+}
+
+fn set_high_byte(value: &mut u16, to: u8) {
+    let ptr: *mut u16 = value;
+    unsafe { *((ptr as *mut u8).offset(1)) = to };
+}
+
+fn set_low_byte(value: &mut u16, to: u8) {
+    let ptr: *mut u16 = value;
+    unsafe { *(ptr as *mut u8) = to };
+}
+
+// Something an executed instruction changed, in the order it happened. Register and memory
+// destinations of arithmetic instructions are always reported, even when the value didn't change.
+pub enum StepEvent {
+    Register { encoding: u8, access: RegisterAccess, before: u16, after: u16 },
+    SegmentRegister { encoding: u8, before: u16, after: u16 },
+    Memory { effective_address: EffectiveAddress, physical_address: usize, before: u16, after: u16 },
+    DivideError,
+    Flags { before: Flags, after: Flags },
+    Breakpoint, // int 3 with dos services
+    Output(Vec<u8>), // bytes a dos service printed
+}
+
+pub struct Step {
+    pub instruction: Instruction,
+    pub code_segment: u16, // cs the instruction was fetched from
+    pub instruction_pointer_before: u16,
+    pub instruction_pointer_after: u16,
+    pub clocks: u32,
+    pub clock_explanation: Option<String>,
+    pub cycles: Option<u32>, // clocks including prefetch stalls, only with a prefetch model
+    pub events: Vec<StepEvent>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StopReason {
+    Halted,
+    DecodeError(DecodeError),
+    MaxSteps,
+    MaxClocks,
+    InfiniteLoop { instruction_pointer: u16 }, // the machine came back to a state it was in before
+    Exited(u8), // a dos program terminated with an exit code
+    DivideOverflow, // a divide error ended a dos program
+    Breakpoint { at: usize }, // physical address of an int 3 in a dos program run without a debugger
+    UnsupportedInterrupt { at: usize, interrupt_type: u8, ah: u8 }, // physical address of the int
+    UnterminatedString { at: usize }, // physical address of a string to print that has no $ in its segment
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Halted => write!(formatter, "halted"),
+            Self::DecodeError(error) => write!(formatter, "{}", error),
+            Self::MaxSteps => write!(formatter, "reached the maximum number of steps"),
+            Self::MaxClocks => write!(formatter, "reached the maximum number of clocks"),
+            Self::InfiniteLoop { instruction_pointer } => write!(formatter, "infinite loop detected at ip {:#x}", instruction_pointer),
+            Self::Exited(exit_code) => write!(formatter, "exited with code {}", exit_code),
+            Self::DivideOverflow => write!(formatter, "divide overflow"),
+            Self::Breakpoint { at } => write!(formatter, "int 3 breakpoint at byte {:#x}", at),
+            Self::UnsupportedInterrupt { at, interrupt_type, ah } => write!(formatter, "unsupported interrupt {:#04x} with ah {:#04x} at byte {:#x}", interrupt_type, ah, at),
+            Self::UnterminatedString { at } => write!(formatter, "no $ within 64 KiB of the string at byte {:#x}", at),
+        }
+    }
+}
+
+// The 8086 moves a word over its 16-bit bus in one cycle unless the word is at an odd address. The
+// 8088 has an 8-bit bus and always takes two. Every extra bus cycle costs 4 clocks.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Cpu {
+    #[default]
+    I8086,
+    I8088,
+}
+
+impl Cpu {
+    fn is_transfer_penalized(&self, offset: u16) -> bool {
+        match self {
+            Cpu::I8086 => offset & 1 == 1,
+            Cpu::I8088 => true,
+        }
+    }
+}
+
+// Number of bus transfers an instruction makes and how many of them are word transfers that need an
+// extra bus cycle, following the transfer counts in the manual's timing tables. Memory operands are
+// transferred at their effective address, pushes and pops at sp, and string instructions at si and di
+// once per repetition. Has to be called with the registers from before the instruction executed.
+fn count_transfers(cpu: Cpu, instruction: &Instruction, registers: &RegisterSet) -> (u32, u32) {
+    const SI: u8 = 6;
+    const DI: u8 = 7;
+
+    let mut transfers = 0;
+    let mut penalized_transfers = 0;
+    let mut transfer = |offset: u16, count: u32, wide: bool| {
+        transfers += count;
+        if wide && cpu.is_transfer_penalized(offset) { penalized_transfers += count; }
+    };
+
+    let stack_transfers = match instruction.operation {
+        Operation::Push_RegMem
+        | Operation::Push_Reg
+        | Operation::Push_SegReg
+        | Operation::Pop_RegMem
+        | Operation::Pop_Reg
+        | Operation::Pop_SegReg
+        | Operation::Push_Flags
+        | Operation::Pop_Flags
+        | Operation::Call_Direct_Within_Segment
+        | Operation::Call_Indirect_Within_Segment
+        | Operation::Ret_Within_Segment
+        | Operation::Ret_Within_Segment_Imm => 1,
+
+        Operation::Call_Direct_Intersegment
+        | Operation::Call_Indirect_Intersegment
+        | Operation::Ret_Intersegment
+        | Operation::Ret_Intersegment_Imm => 2,
+
+        // flags, cs and ip
+        Operation::Interrupt
+        | Operation::Interrupt_Type_3
+        | Operation::Interrupt_Return => 3,
+
+        _ => 0,
+    };
+    // pushes and pops move sp by 2 so every transfer has the same alignment as sp
+    transfer(registers.get_register_value(SP, &RegisterAccess::Full), stack_transfers, true);
+    // interrupt vectors are always aligned
+    if matches!(instruction.operation, Operation::Interrupt | Operation::Interrupt_Type_3) { transfer(0, 2, true); }
+
+    let memory_operand = instruction.operands.iter().enumerate().find_map(|(index, operand)| match operand {
+        Some(Operand::Memory(effective_address)) => Some((index, registers.resolve_effective_address(effective_address).1)),
+        _ => None,
+    });
+    if let Some((index, offset)) = memory_operand {
+        let memory_transfers = match instruction.operation {
+            // far pointers are two words
+            Operation::Jmp_Indirect_Intersegment
+            | Operation::Call_Indirect_Intersegment
+            | Operation::Load_Pointer_To_DS
+            | Operation::Load_Pointer_To_ES => 2,
+            // only the address is used
+            Operation::Load_EA_To_Reg => 0,
+            // read and written
+            Operation::Xchg_RegMem_With_Reg => 2,
+            // only read
+            _ if index == 1 => 1,
+            Operation::Mov_RegMem_ToFrom_Reg
+            | Operation::Mov_Imm_To_RegMem
+            | Operation::Mov_Acc_To_Mem
+            | Operation::Mov_SegReg_To_RegMem
+            | Operation::Cmp_RegMem_And_Reg
+            | Operation::Cmp_Imm_With_RegMem
+            | Operation::Test_RegMem_And_Reg
+            | Operation::Test_Imm_And_RegMem
+            | Operation::Mul
+            | Operation::Imul
+            | Operation::Div
+            | Operation::Idiv
+            | Operation::Push_RegMem
+            | Operation::Pop_RegMem
+            | Operation::Call_Indirect_Within_Segment
+            | Operation::Jmp_Indirect_Within_Segment => 1,
+            // read, modified and written back
+            _ => 2,
+        };
+        transfer(offset, memory_transfers, instruction.flags.wide);
+    }
+
+    let (uses_si, uses_di) = match instruction.operation {
+        Operation::Movs | Operation::Cmps => (true, true),
+        Operation::Lods => (true, false),
+        Operation::Scas | Operation::Stos => (false, true),
+        _ => (false, false),
+    };
+    if uses_si { transfer(registers.get_register_value(SI, &RegisterAccess::Full), 1, instruction.flags.wide); }
+    if uses_di { transfer(registers.get_register_value(DI, &RegisterAccess::Full), 1, instruction.flags.wide); }
+
+    // xlatb reads a byte, in and out move their data to or from the port over the same bus
+    match (instruction.operation, &instruction.operands) {
+        (Operation::Translate, _) => transfer(0, 1, false),
+        (Operation::In_Fixed_Port, [ _, Some(Operand::ImmediateData(port)) ])
+        | (Operation::Out_Fixed_Port, [ Some(Operand::ImmediateData(port)), _ ]) => transfer(*port, 1, instruction.flags.wide),
+        (Operation::In_Variable_Port | Operation::Out_Variable_Port, _) => {
+            const DX: u8 = 2;
+            transfer(registers.get_register_value(DX, &RegisterAccess::Full), 1, instruction.flags.wide);
+        },
+        _ => {},
+    }
+
+    (transfers, penalized_transfers)
+}
+
+pub struct Machine {
+    pub registers: RegisterSet,
+    pub flags: Flags,
+    pub instruction_pointer: u16,
+    pub memory: Vec<u8>,
+    pub total_clocks: u64,
+    pub cpu: Cpu,
+    pub prefetch_model: Option<PrefetchModel>,
+    pub dos: Option<Dos>, // handles dos interrupts instead of the interrupt vectors when set
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        Self {
+            registers: RegisterSet::new(),
+            flags: Flags::new(),
+            instruction_pointer: 0,
+            memory: vec![0u8; 1 << 20],
+            total_clocks: 0,
+            cpu: Cpu::default(),
+            prefetch_model: None,
+            dos: None,
+        }
+    }
+
+    // Loads a flat binary at 0000:0000 and puts a hlt right after it so execution stops at the end
+    // of the program. All registers are left as they are, so execution starts at 0000:0000 too.
+    pub fn load_program(&mut self, program: &[u8]) {
+        self.memory[..program.len()].copy_from_slice(program);
+        self.memory[program.len()] = 0b11110100;
+    }
+
+    // Steps until the cpu halts, hits an instruction it can't decode, or has executed max_steps
+    // instructions.
+    pub fn run_until(&mut self, max_steps: usize) -> StopReason {
+        for _ in 0 .. max_steps {
+            if let Err(stop_reason) = self.step() { return stop_reason; }
+        }
+
+        StopReason::MaxSteps
+    }
+
+    // Executes the instruction at cs:ip. Stops without executing anything when the instruction is hlt
+    // or can't be decoded, decode errors report physical addresses.
+    pub fn step(&mut self) -> Result<Step, StopReason> {
+        if let Some(exit_code) = self.dos.as_ref().and_then(|dos| dos.exit_code) { return Err(StopReason::Exited(exit_code)); }
+        if self.dos.as_ref().is_some_and(|dos| dos.divide_overflow) { return Err(StopReason::DivideOverflow); }
+
+        let code_segment = self.registers.segment_registers[CS as usize];
+        let instruction_address = get_physical_address(code_segment, self.instruction_pointer);
+        let instruction = decode_instruction(&self.memory, instruction_address).map_err(StopReason::DecodeError)?;
+        if instruction.operation == Operation::Halt { return Err(StopReason::Halted); }
+        let interrupt_type = match (&instruction.operation, &instruction.operands[0]) {
+            (Operation::Interrupt, Some(Operand::ImmediateData(interrupt_type))) => Some(*interrupt_type as u8),
+            (Operation::Interrupt_Type_3, _) => Some(3),
+            _ => None,
+        };
+        if let (Some(dos), Some(interrupt_type)) = (&self.dos, interrupt_type) {
+            dos.check_interrupt(interrupt_type, instruction_address, &self.registers, &self.memory)?;
+        }
+
+        let instruction_pointer_before = self.instruction_pointer;
+        // relative jumps and calls are relative to the start of the next instruction
+        self.instruction_pointer = self.instruction_pointer.wrapping_add(instruction.size as u16);
+
+        let flags_before = self.flags;
+        let (mut transfers, penalized_transfers) = count_transfers(self.cpu, &instruction, &self.registers);
+        let mut clock_context = ClockContext { penalized_transfers, ..ClockContext::default() };
+        let mut events: Vec<StepEvent> = vec![];
+
+        match &instruction.operands {
+            [ Some(destination), Some(source) ] => {
+                let source_value = read_operand(source, instruction.flags.wide, &self.registers, &self.memory);
+                let destination_value_before = read_operand(destination, instruction.flags.wide, &self.registers, &self.memory);
+
+                match instruction.operation {
+                    Operation::Mov_RegMem_ToFrom_Reg
+                    | Operation::Mov_Imm_To_Reg
+                    | Operation::Mov_Imm_To_RegMem
+                    | Operation::Mov_Mem_To_Acc
+                    | Operation::Mov_Acc_To_Mem
+                    | Operation::Mov_RegMem_To_SegReg
+                    | Operation::Mov_SegReg_To_RegMem => {
+                        write_operand(destination, instruction.flags.wide, source_value, &mut self.registers, &mut self.memory);
+                    },
+
+                    Operation::Add_RegMem_With_Reg_To_Either
+                    | Operation::Add_Imm_To_RegMem
+                    | Operation::Add_Imm_To_Acc
+                    | Operation::Sub_RegMem_And_Reg_From_Either
+                    | Operation::Sub_Imm_From_RegMem
+                    | Operation::Sub_Imm_From_Acc
+                    | Operation::Sbb_RegMem_And_Reg_From_Either
+                    | Operation::Sbb_Imm_From_RegMem
+                    | Operation::Sbb_Imm_From_Acc
+                    | Operation::Cmp_RegMem_And_Reg
+                    | Operation::Cmp_Imm_With_RegMem
+                    | Operation::Cmp_Imm_With_Acc
+                    | Operation::Adc_RegMem_With_Reg_To_Either
+                    | Operation::Adc_Imm_To_RegMem
+                    | Operation::Adc_Imm_To_Acc
+                    | Operation::And_RegMem_With_Reg_To_Either
+                    | Operation::And_Imm_To_RegMem
+                    | Operation::And_Imm_To_Acc
+                    | Operation::Or_RegMem_And_Reg_To_Either
+                    | Operation::Or_Imm_To_RegMem
+                    | Operation::Or_Imm_To_Acc
+                    | Operation::Xor_RegMem_And_Reg_To_Either
+                    | Operation::Xor_Imm_To_RegMem
+                    | Operation::Xor_Imm_To_Acc
+                    | Operation::Test_RegMem_And_Reg
+                    | Operation::Test_Imm_And_RegMem
+                    | Operation::Test_Imm_And_Acc => {
+                        if let Some(result) = alu(&instruction.operation, destination_value_before, source_value, instruction.flags.wide, &mut self.flags) {
+                            write_operand(destination, instruction.flags.wide, result, &mut self.registers, &mut self.memory);
+                        }
+                    },
+
+                    Operation::Shl
+                    | Operation::Shr
+                    | Operation::Sar
+                    | Operation::Rol
+                    | Operation::Ror
+                    | Operation::Rcl
+                    | Operation::Rcr => {
+                        clock_context.shift_count = source_value;
+                        let result = shift_rotate(&instruction.operation, destination_value_before, source_value, instruction.flags.wide, &mut self.flags);
+                        write_operand(destination, instruction.flags.wide, result, &mut self.registers, &mut self.memory);
+                    },
+
+                    Operation::Xchg_RegMem_With_Reg
+                    | Operation::Xchg_Reg_With_Acc => {
+                        write_operand(source, instruction.flags.wide, destination_value_before, &mut self.registers, &mut self.memory);
+                        write_operand(destination, instruction.flags.wide, source_value, &mut self.registers, &mut self.memory);
+                        events.push(self.get_operand_event(source, source_value, destination_value_before));
+                    },
+
+                    // the offset of the address, nothing is read from memory
+                    Operation::Load_EA_To_Reg => {
+                        let Operand::Memory(effective_address) = source else { unreachable!("lea takes a memory operand") };
+                        let (_, offset) = self.registers.resolve_effective_address(effective_address);
+                        write_operand(destination, true, offset, &mut self.registers, &mut self.memory);
+                    },
+
+                    // a far pointer, the offset goes to the register and the segment after it to ds or es
+                    Operation::Load_Pointer_To_DS
+                    | Operation::Load_Pointer_To_ES => {
+                        let Operand::Memory(effective_address) = source else { unreachable!("lds and les take a memory operand") };
+                        let (segment, offset) = self.registers.resolve_effective_address(effective_address);
+                        let pointer_segment = read_memory(&self.memory, segment, offset.wrapping_add(2), true);
+                        let segment_register = if instruction.operation == Operation::Load_Pointer_To_DS { DS } else { ES };
+                        let segment_before = self.registers.segment_registers[segment_register as usize];
+                        self.registers.segment_registers[segment_register as usize] = pointer_segment;
+                        write_operand(destination, true, source_value, &mut self.registers, &mut self.memory);
+                        events.push(StepEvent::SegmentRegister { encoding: segment_register, before: segment_before, after: pointer_segment });
+                    },
+
+                    // nothing is connected to the i/o ports, reads float high and writes go nowhere
+                    Operation::In_Fixed_Port
+                    | Operation::In_Variable_Port => {
+                        write_operand(destination, instruction.flags.wide, 0xFFFF, &mut self.registers, &mut self.memory);
+                    },
+                    Operation::Out_Fixed_Port
+                    | Operation::Out_Variable_Port => {},
+
+                    _ => panic!("Invalid 2-operand instruction encountered")
+                };
+
+                // out's destination is the port, not something it changes
+                if !matches!(instruction.operation, Operation::Out_Fixed_Port | Operation::Out_Variable_Port) {
+                    let destination_value_after = read_operand(destination, instruction.flags.wide, &self.registers, &self.memory);
+                    events.push(self.get_operand_event(destination, destination_value_before, destination_value_after));
+                }
+            },
+
+            [ Some(Operand::LabelOffset(offset)), None ] => {
+                // TODO stop hardcoding register indices
+                const CX: u8 = 1;
+                match instruction.operation {
+                    Operation::Jmp_On_Equal
+                    | Operation::Jmp_On_Less
+                    | Operation::Jmp_On_Less_Or_Equal
+                    | Operation::Jmp_On_Below
+                    | Operation::Jmp_On_Below_Or_Equal
+                    | Operation::Jmp_On_Greater
+                    | Operation::Jmp_On_Above
+                    | Operation::Jmp_On_Parity
+                    | Operation::Jmp_On_Overflow
+                    | Operation::Jmp_On_Sign
+                    | Operation::Jmp_On_Not_Equal
+                    | Operation::Jmp_On_Not_Less
+                    | Operation::Jmp_On_Not_Below
+                    | Operation::Jmp_On_Not_Parity
+                    | Operation::Jmp_On_Not_Overflow
+                    | Operation::Jmp_On_Not_Sign => {
+                        clock_context.jump_taken = self.flags.is_condition_met(&instruction.operation);
+                        if clock_context.jump_taken { self.instruction_pointer = self.instruction_pointer.wrapping_add(*offset as u16); }
+                    },
+
+                    Operation::Jmp_On_CX_Zero => {
+                        clock_context.jump_taken = self.registers.get_register_value(CX, &RegisterAccess::Full) == 0;
+                        if clock_context.jump_taken { self.instruction_pointer = self.instruction_pointer.wrapping_add(*offset as u16); }
+                    },
+
+                    // loops decrement cx without touching the self.flags, loopz and loopnz additionally test
+                    // the zero flag left by a previous instruction
+                    Operation::Loop
+                    | Operation::Loop_While_Zero
+                    | Operation::Loop_While_Not_Zero => {
+                        let registers_before = self.registers.clone();
+                        let cx_value = self.registers.get_register_value(CX, &RegisterAccess::Full).wrapping_sub(1);
+                        self.registers.set_register_value(CX, &RegisterAccess::Full, cx_value);
+                        clock_context.jump_taken = cx_value != 0 && match instruction.operation {
+                            Operation::Loop_While_Zero => self.flags.zero,
+                            Operation::Loop_While_Not_Zero => !self.flags.zero,
+                            _ => true,
+                        };
+                        if clock_context.jump_taken { self.instruction_pointer = self.instruction_pointer.wrapping_add(*offset as u16); }
+                        push_register_changes(&mut events, &registers_before, &self.registers);
+                    },
+
+                    Operation::Call_Direct_Within_Segment => {
+                        let registers_before = self.registers.clone();
+                        push(&mut self.registers, &mut self.memory, self.instruction_pointer);
+                        self.instruction_pointer = self.instruction_pointer.wrapping_add(*offset as u16);
+                        push_register_changes(&mut events, &registers_before, &self.registers);
+                    },
+
+                    Operation::Jmp_Direct_Within_Segment_Short
+                    | Operation::Jmp_Direct_Within_Segment => self.instruction_pointer = self.instruction_pointer.wrapping_add(*offset as u16),
+
+                    _ => panic!("only jumps, calls and loops take a label offset"),
+                };
+            },
+            [ Some(Operand::FarAddress { segment, offset }), None ] => {
+                let registers_before = self.registers.clone();
+                match instruction.operation {
+                    Operation::Call_Direct_Intersegment => {
+                        let cs = self.registers.segment_registers[CS as usize];
+                        push(&mut self.registers, &mut self.memory, cs);
+                        push(&mut self.registers, &mut self.memory, self.instruction_pointer);
+                    },
+                    Operation::Jmp_Direct_Intersegment => {},
+                    _ => panic!("only intersegment jmp and call take a far address"),
+                };
+                self.registers.segment_registers[CS as usize] = *segment;
+                self.instruction_pointer = *offset;
+                push_register_changes(&mut events, &registers_before, &self.registers);
+            },
+            [ Some(destination), None ] => match instruction.operation {
+                Operation::Inc_RegMem
+                | Operation::Inc_Reg
+                | Operation::Dec_RegMem
+                | Operation::Dec_Reg
+                | Operation::Neg
+                | Operation::Not => {
+                    let dst_bef = read_operand(destination, instruction.flags.wide, &self.registers, &self.memory);
+                    if let Some(result) = alu(&instruction.operation, dst_bef, 0, instruction.flags.wide, &mut self.flags) {
+                        write_operand(destination, instruction.flags.wide, result, &mut self.registers, &mut self.memory);
+                    }
+                    let dst_aft = read_operand(destination, instruction.flags.wide, &self.registers, &self.memory);
+                    events.push(self.get_operand_event(destination, dst_bef, dst_aft));
+                },
+
+                Operation::Mul
+                | Operation::Imul
+                | Operation::Div
+                | Operation::Idiv => {
+                    let source_value = read_operand(destination, instruction.flags.wide, &self.registers, &self.memory);
+
+                    let registers_before = self.registers.clone();
+                    let result = execute_multiply_divide(&instruction.operation, source_value, instruction.flags.wide, &mut self.registers, &mut self.flags);
+                    push_register_changes(&mut events, &registers_before, &self.registers);
+                    if let Err(DivideError) = result {
+                        events.push(StepEvent::DivideError);
+                        self.execute_interrupt(0, &mut events);
+                    }
+                },
+
+                Operation::Aam | Operation::Aad => {
+                    let Operand::ImmediateData(base) = destination else { panic!("aam and aad take an immediate base") };
+                    let registers_before = self.registers.clone();
+                    let result = execute_accumulator_adjust(&instruction.operation, *base, &mut self.registers, &mut self.flags);
+                    push_register_changes(&mut events, &registers_before, &self.registers);
+                    if let Err(DivideError) = result {
+                        events.push(StepEvent::DivideError);
+                        self.execute_interrupt(0, &mut events);
+                    }
+                },
+
+                Operation::Push_RegMem
+                | Operation::Push_Reg
+                | Operation::Push_SegReg
+                | Operation::Call_Indirect_Within_Segment
+                | Operation::Jmp_Indirect_Within_Segment => {
+                    let value = read_operand(destination, true, &self.registers, &self.memory);
+
+                    let registers_before = self.registers.clone();
+                    match instruction.operation {
+                        Operation::Call_Indirect_Within_Segment => {
+                            push(&mut self.registers, &mut self.memory, self.instruction_pointer);
+                            self.instruction_pointer = value;
+                        },
+                        Operation::Jmp_Indirect_Within_Segment => self.instruction_pointer = value,
+                        // the 8086 decrements sp before reading it, the 80286 and later push the old value
+                        _ if matches!(destination, Operand::Register(SP, _)) => push(&mut self.registers, &mut self.memory, value.wrapping_sub(2)),
+                        _ => push(&mut self.registers, &mut self.memory, value),
+                    };
+                    push_register_changes(&mut events, &registers_before, &self.registers);
+                },
+
+                Operation::Jmp_Indirect_Intersegment
+                | Operation::Call_Indirect_Intersegment => {
+                    let Operand::Memory(effective_address) = destination else { panic!("far pointer must be read from self.memory") };
+                    let (pointer_segment, pointer_offset) = self.registers.resolve_effective_address(effective_address);
+                    let offset = read_memory(&self.memory, pointer_segment, pointer_offset, true);
+                    let segment = read_memory(&self.memory, pointer_segment, pointer_offset.wrapping_add(2), true);
+
+                    let registers_before = self.registers.clone();
+                    if instruction.operation == Operation::Call_Indirect_Intersegment {
+                        let cs = self.registers.segment_registers[CS as usize];
+                        push(&mut self.registers, &mut self.memory, cs);
+                        push(&mut self.registers, &mut self.memory, self.instruction_pointer);
+                    }
+                    self.registers.segment_registers[CS as usize] = segment;
+                    self.instruction_pointer = offset;
+                    push_register_changes(&mut events, &registers_before, &self.registers);
+                },
+
+                Operation::Pop_RegMem
+                | Operation::Pop_Reg
+                | Operation::Pop_SegReg => {
+                    let registers_before = self.registers.clone();
+                    let value = pop(&mut self.registers, &self.memory);
+                    write_operand(destination, true, value, &mut self.registers, &mut self.memory);
+                    push_register_changes(&mut events, &registers_before, &self.registers);
+                },
+
+                Operation::Ret_Within_Segment_Imm
+                | Operation::Ret_Intersegment_Imm => {
+                    let Operand::ImmediateData(data) = destination else { panic!("ret can only add immediate data to sp") };
+                    let registers_before = self.registers.clone();
+                    self.instruction_pointer = pop(&mut self.registers, &self.memory);
+                    if instruction.operation == Operation::Ret_Intersegment_Imm {
+                        self.registers.segment_registers[CS as usize] = pop(&mut self.registers, &self.memory);
+                    }
+                    let sp = self.registers.get_register_value(SP, &RegisterAccess::Full);
+                    self.registers.set_register_value(SP, &RegisterAccess::Full, sp.wrapping_add(*data));
+                    push_register_changes(&mut events, &registers_before, &self.registers);
+                },
+
+                Operation::Interrupt => self.execute_interrupt(interrupt_type.expect("int takes an immediate type"), &mut events),
+
+                // the decoder table gives every other operation two operands or none
+                _ => unreachable!("{} doesn't take a single operand", instruction),
+            },
+            [ None, None ] => match instruction.operation {
+                Operation::Movs
+                | Operation::Cmps
+                | Operation::Scas
+                | Operation::Lods
+                | Operation::Stos => {
+                    let registers_before = self.registers.clone();
+                    clock_context.repetitions = execute_string_instruction(&instruction, &mut self.registers, &mut self.flags, &mut self.memory);
+                    // si and di keep their alignment, so every repetition makes the same transfers
+                    if instruction.flags.repeat {
+                        transfers *= clock_context.repetitions;
+                        clock_context.penalized_transfers *= clock_context.repetitions;
+                    }
+                    push_register_changes(&mut events, &registers_before, &self.registers);
+                },
+
+                Operation::Push_Flags => {
+                    let registers_before = self.registers.clone();
+                    push(&mut self.registers, &mut self.memory, self.flags.to_word());
+                    push_register_changes(&mut events, &registers_before, &self.registers);
+                },
+
+                Operation::Pop_Flags => {
+                    let registers_before = self.registers.clone();
+                    self.flags = Flags::from_word(pop(&mut self.registers, &self.memory));
+                    push_register_changes(&mut events, &registers_before, &self.registers);
+                },
+
+                Operation::Ret_Within_Segment => {
+                    let registers_before = self.registers.clone();
+                    self.instruction_pointer = pop(&mut self.registers, &self.memory);
+                    push_register_changes(&mut events, &registers_before, &self.registers);
+                },
+
+                Operation::Ret_Intersegment => {
+                    let registers_before = self.registers.clone();
+                    self.instruction_pointer = pop(&mut self.registers, &self.memory);
+                    self.registers.segment_registers[CS as usize] = pop(&mut self.registers, &self.memory);
+                    push_register_changes(&mut events, &registers_before, &self.registers);
+                },
+
+                Operation::Interrupt_Type_3 => self.execute_interrupt(3, &mut events),
+
+                Operation::Interrupt_Return => {
+                    let registers_before = self.registers.clone();
+                    self.instruction_pointer = pop(&mut self.registers, &self.memory);
+                    self.registers.segment_registers[CS as usize] = pop(&mut self.registers, &self.memory);
+                    self.flags = Flags::from_word(pop(&mut self.registers, &self.memory));
+                    push_register_changes(&mut events, &registers_before, &self.registers);
+                },
+
+                Operation::Cbw
+                | Operation::Cwd
+                | Operation::Aaa
+                | Operation::Aas
+                | Operation::Daa
+                | Operation::Das => {
+                    let registers_before = self.registers.clone();
+                    // only aam with a base of 0 can fail
+                    let _ = execute_accumulator_adjust(&instruction.operation, 10, &mut self.registers, &mut self.flags);
+                    push_register_changes(&mut events, &registers_before, &self.registers);
+                },
+
+                Operation::Nop => {},
+
+                // al is replaced by the byte at [bx + al], ds can be overridden
+                Operation::Translate => {
+                    const BX: u8 = 3;
+                    let registers_before = self.registers.clone();
+                    let segment = self.registers.segment_registers[instruction.flags.segment_override.unwrap_or(DS) as usize];
+                    let offset = self.registers.get_register_value(BX, &RegisterAccess::Full)
+                        .wrapping_add(self.registers.get_register_value(AX, &RegisterAccess::Low));
+                    let value = read_memory(&self.memory, segment, offset, false);
+                    self.registers.set_register_value(AX, &RegisterAccess::Low, value);
+                    push_register_changes(&mut events, &registers_before, &self.registers);
+                },
+
+                // the low byte of the flags word, sign, zero, auxiliary carry, parity and carry
+                Operation::Load_AH_With_Flags => {
+                    let registers_before = self.registers.clone();
+                    self.registers.set_register_value(AX, &RegisterAccess::High, self.flags.to_word() & 0xFF);
+                    push_register_changes(&mut events, &registers_before, &self.registers);
+                },
+                Operation::Store_AH_Into_Flags => {
+                    let ah = self.registers.get_register_value(AX, &RegisterAccess::High);
+                    self.flags = Flags::from_word(self.flags.to_word() & 0xFF00 | ah);
+                },
+
+                Operation::Clear_Direction => self.flags.direction = false,
+                Operation::Set_Direction => self.flags.direction = true,
+                Operation::Clear_Carry => self.flags.carry = false,
+                Operation::Set_Carry => self.flags.carry = true,
+                Operation::Complement_Carry => self.flags.carry = !self.flags.carry,
+                Operation::Clear_Interrupt => self.flags.interrupt = false,
+                Operation::Set_Interrupt => self.flags.interrupt = true,
+
+                // every operation without operands is handled above, or stopped on before executing like hlt
+                _ => unreachable!("{} takes operands", instruction),
+            },
+            _ => panic!("invalid operand configuration [ None, Some(...) ]"),
+        };
+
+        let (clocks, clock_explanation) = instruction.get_clocks_estimate(&clock_context);
+        self.total_clocks += clocks as u64;
+
+        let cycles = self.prefetch_model.as_mut().map(|prefetch_model| {
+            let jumped = self.instruction_pointer != instruction_pointer_before.wrapping_add(instruction.size as u16)
+                || self.registers.segment_registers[CS as usize] != code_segment;
+            let bus_clocks = 4 * (transfers + clock_context.penalized_transfers);
+            prefetch_model.execute(self.cpu, instruction_pointer_before, instruction.size as u16, clocks, bus_clocks, jumped)
+        });
+
+        if self.flags != flags_before { events.push(StepEvent::Flags { before: flags_before, after: self.flags }); }
+
+        Ok(Step {
+            instruction,
+            code_segment,
+            instruction_pointer_before,
+            instruction_pointer_after: self.instruction_pointer,
+            clocks,
+            clock_explanation,
+            cycles,
+            events,
+        })
+    }
+
+    // Interrupts go to the dos services when there are any, otherwise through the vector table
+    fn execute_interrupt(&mut self, interrupt_type: u8, events: &mut Vec<StepEvent>) {
+        let registers_before = self.registers.clone();
+        match &mut self.dos {
+            Some(dos) => dos.handle_interrupt(interrupt_type, &mut self.registers, &self.memory, events),
+            None => interrupt(interrupt_type, &mut self.registers, &mut self.memory, &mut self.flags, &mut self.instruction_pointer),
+        }
+        push_register_changes(events, &registers_before, &self.registers);
+    }
+
+    fn get_operand_event(&self, operand: &Operand, before: u16, after: u16) -> StepEvent {
+        match operand {
+            Operand::Register(encoding, access) => StepEvent::Register { encoding: *encoding, access: *access, before, after },
+            Operand::SegmentRegister(encoding) => StepEvent::SegmentRegister { encoding: *encoding, before, after },
+            Operand::Memory(effective_address) => {
+                let (segment, offset) = self.registers.resolve_effective_address(effective_address);
+                StepEvent::Memory {
+                    effective_address: *effective_address,
+                    physical_address: get_physical_address(segment, offset),
+                    before,
+                    after,
+                }
+            },
+            _ => panic!("immediates and label offsets cannot be changed"),
+        }
+    }
+}
+
+impl Default for Machine {
+    fn default() -> Self { Self::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AX: u8 = 0;
+    const CX: u8 = 1;
+    const BX: u8 = 3;
+
+    fn machine_with_program(program: &[u8]) -> Machine {
+        let mut machine = Machine::new();
+        machine.load_program(program);
+        machine
+    }
+
+    // flags with the ones named in active set, in the letters get_active_flags_string uses
+    fn flags(active: &str) -> Flags {
+        let mut flags = Flags::new();
+        for flag in active.chars() {
+            match flag {
+                'C' => flags.carry = true,
+                'P' => flags.parity = true,
+                'A' => flags.auxiliary_carry = true,
+                'S' => flags.sign = true,
+                'Z' => flags.zero = true,
+                'O' => flags.overflow = true,
+                'D' => flags.direction = true,
+                'I' => flags.interrupt = true,
+                'T' => flags.trap = true,
+                _ => panic!("unknown flag {}", flag),
+            }
+        }
+        flags
+    }
+
+    fn register(machine: &Machine, encoding: u8) -> u16 { machine.registers.get_register_value(encoding, &RegisterAccess::Full) }
+
+    #[track_caller]
+    fn check_alu(operation: Operation, wide: bool, destination: u16, source: u16, flags_before: &str, result: Option<u16>, flags_after: &str) {
+        let mut flags = flags(flags_before);
+        assert_eq!(alu(&operation, destination, source, wide, &mut flags), result);
+        assert_eq!(flags.get_active_flags_string(), flags_after);
+    }
+
+    #[test]
+    fn run_until_halt() {
+        let mut machine = machine_with_program(&[
+            0xb9, 0x03, 0x00, // mov cx, 3
+            0xb8, 0x00, 0x00, // mov ax, 0
+            0x05, 0x01, 0x00, // add ax, 1
+            0xe2, 0xfb,       // loop -5
+        ]);
+
+        assert_eq!(machine.run_until(100), StopReason::Halted);
+        assert_eq!(machine.registers.get_register_value(AX, &RegisterAccess::Full), 3);
+        assert_eq!(machine.registers.get_register_value(CX, &RegisterAccess::Full), 0);
+        assert_eq!(machine.instruction_pointer, 11);
+    }
+
+    #[test]
+    fn run_until_max_steps() {
+        let mut machine = machine_with_program(&[ 0xeb, 0xfe ]); // jmp -2
+        assert_eq!(machine.run_until(10), StopReason::MaxSteps);
+        assert_eq!(machine.instruction_pointer, 0);
+    }
+
+    #[test]
+    fn illegal_instruction_stops_execution() {
+        let mut machine = machine_with_program(&[ 0xb8, 0x01, 0x00, 0xf1 ]);
+        assert_eq!(machine.run_until(10), StopReason::DecodeError(DecodeError::UnknownOpcode { at: 3, byte: 0xf1 }));
+    }
+
+    #[test]
+    fn every_decodable_instruction_executes() {
+        // every opcode with every mod reg r/m byte, from a clean state so rep strings don't repeat
+        let mut machine = Machine::new();
+        for opcode in 0..=0xff {
+            for second_byte in 0..=0xff {
+                let bytes = [ opcode, second_byte, 0, 0, 0, 0 ];
+                machine.memory[0..6].copy_from_slice(&bytes);
+                machine.registers = RegisterSet::new();
+                machine.flags = Flags::new();
+                machine.instruction_pointer = 0;
+
+                let instruction = match decode_instruction(&bytes, 0) {
+                    Ok(instruction) => instruction,
+                    Err(error) => {
+                        assert_eq!(machine.step().err(), Some(StopReason::DecodeError(error)), "{:02x} {:02x}", opcode, second_byte);
+                        continue;
+                    },
+                };
+                let mnemonic = instruction.get_mnemonic();
+                if mnemonic == "hlt" {
+                    assert_eq!(machine.step().err(), Some(StopReason::Halted));
+                    continue;
+                }
+
+                let step = machine.step().unwrap_or_else(|reason| panic!("{} stopped with {}", instruction, reason));
+                assert_eq!(step.instruction.size, instruction.size, "{}", instruction);
+                assert!(step.clocks > 0, "{} took no clocks", instruction);
+                // anything that doesn't transfer control goes on to the next instruction, a divide by
+                // zero (div, idiv or aam 0) goes to the divide error handler
+                let is_transfer = mnemonic.starts_with('j') || mnemonic.starts_with("loop") || mnemonic.starts_with("ret")
+                    || [ "call", "iret", "int", "int3", "div", "idiv", "aam" ].contains(&mnemonic.as_str());
+                if !is_transfer {
+                    assert_eq!(step.instruction_pointer_after, instruction.size as u16, "{} didn't go on to the next instruction", instruction);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn step_reports_memory_destination_and_flags() {
+        let mut machine = machine_with_program(&[
+            0xbd, 0x00, 0x01,             // mov bp, 256
+            0xc7, 0x46, 0x04, 0x10, 0x00, // mov [bp + 4], word 16
+            0x83, 0x6e, 0x04, 0x04,       // sub [bp + 4], word 4
+        ]);
+        // bp based addresses are relative to the stack segment
+        machine.registers.segment_registers[SS as usize] = 0x1000;
+
+        machine.step().ok().unwrap();
+        machine.step().ok().unwrap();
+        let step = machine.step().ok().unwrap();
+
+        assert_eq!(step.instruction_pointer_before, 8);
+        assert_eq!(step.instruction_pointer_after, 12);
+        assert!(matches!(
+            step.events[0],
+            StepEvent::Memory { physical_address: 0x10104, before: 0x10, after: 0x0c, .. }
+        ));
+        let StepEvent::Flags { before, after } = step.events[1] else { panic!("expected a flags change") };
+        assert_eq!(before.get_active_flags_string(), "");
+        assert_eq!(after.get_active_flags_string(), "PA");
+        assert_eq!(read_memory(&machine.memory, 0x1000, 0x104, true), 0x0c);
+    }
+
+    #[test]
+    fn memory_destination_sub_and_cmp() {
+        let mut machine = machine_with_program(&[
+            0xbb, 0x00, 0x01,             // mov bx, 0x100
+            0xb8, 0x01, 0x00,             // mov ax, 1
+            0x29, 0x07,                   // sub [bx], ax
+            0x80, 0x6f, 0x02, 0x10,       // sub byte [bx + 2], 0x10
+            0x83, 0x3e, 0x00, 0x01, 0x05, // cmp word [0x100], 5
+        ]);
+        machine.memory[0x100..0x103].copy_from_slice(&[ 0x05, 0x00, 0x08 ]);
+
+        for _ in 0..3 { machine.step().ok().unwrap(); }
+        assert_eq!(read_memory(&machine.memory, 0, 0x100, true), 0x0004);
+        machine.step().ok().unwrap();
+        assert_eq!(machine.memory[0x102], 0xf8);
+        assert_eq!(machine.flags.get_active_flags_string(), "CS");
+
+        // cmp sets flags without writing back
+        let step = machine.step().ok().unwrap();
+        assert!(matches!(step.events[0], StepEvent::Memory { physical_address: 0x100, before: 0x0004, after: 0x0004, .. }));
+        assert_eq!(read_memory(&machine.memory, 0, 0x100, true), 0x0004);
+        assert_eq!(machine.flags.get_active_flags_string(), "CPAS");
+    }
+
+    #[test]
+    fn exchanges_and_loads() {
+        let mut machine = machine_with_program(&[
+            0xb8, 0x34, 0x12,             // mov ax, 0x1234
+            0xbb, 0x00, 0x02,             // mov bx, 0x200
+            0x93,                         // xchg ax, bx
+            0x87, 0x07,                   // xchg ax, [bx]
+            0x8d, 0x70, 0x04,             // lea si, [bx + si + 4]
+            0xc5, 0x3f,                   // lds di, [bx]
+            0x90,                         // nop
+        ]);
+        machine.memory[0x200..0x204].copy_from_slice(&[ 0x78, 0x56, 0x00, 0x30 ]);
+
+        for _ in 0..3 { machine.step().ok().unwrap(); }
+        assert_eq!((register(&machine, AX), register(&machine, BX)), (0x0200, 0x1234));
+        machine.registers.set_register_value(BX, &RegisterAccess::Full, 0x200);
+        machine.step().ok().unwrap();
+        assert_eq!(register(&machine, AX), 0x5678);
+        assert_eq!(read_memory(&machine.memory, 0, 0x200, true), 0x0200);
+
+        // lea only calculates the address, bx + si + 4 = 0x204 costs 2 + 11ea
+        let step = machine.step().ok().unwrap();
+        assert_eq!(register(&machine, 6), 0x204);
+        assert_eq!(step.clocks, 13);
+
+        let step = machine.step().ok().unwrap();
+        assert_eq!(register(&machine, 7), 0x0200);
+        assert_eq!(machine.registers.segment_registers[DS as usize], 0x3000);
+        assert!(matches!(step.events[..], [ StepEvent::SegmentRegister { before: 0, after: 0x3000, .. }, StepEvent::Register { before: 0, after: 0x0200, .. } ]));
+
+        let step = machine.step().ok().unwrap();
+        assert!(step.events.is_empty());
+        assert_eq!(step.clocks, 3);
+    }
+
+    #[test]
+    fn translate_flags_to_ah_and_ports() {
+        let mut machine = machine_with_program(&[
+            0xbb, 0x00, 0x01, // mov bx, 0x100
+            0xb0, 0x02,       // mov al, 2
+            0xd7,             // xlatb
+            0x9f,             // lahf
+            0xb4, 0xd5,       // mov ah, 0xd5
+            0x9e,             // sahf
+            0xe4, 0x60,       // in al, 0x60
+            0xef,             // out dx, ax
+        ]);
+        machine.memory[0x100..0x104].copy_from_slice(b"abcd");
+        machine.flags = flags("CZO");
+
+        for _ in 0..3 { machine.step().ok().unwrap(); }
+        assert_eq!(machine.registers.get_register_value(AX, &RegisterAccess::Low), b'c' as u16);
+        // bit 1 of the flags word is always set
+        machine.step().ok().unwrap();
+        assert_eq!(machine.registers.get_register_value(AX, &RegisterAccess::High), 0x43);
+        // sahf leaves overflow alone
+        for _ in 0..2 { machine.step().ok().unwrap(); }
+        assert_eq!(machine.flags.get_active_flags_string(), "CPASZO");
+
+        // nothing answers on the ports
+        machine.step().ok().unwrap();
+        assert_eq!(register(&machine, AX), 0xd5ff);
+        let step = machine.step().ok().unwrap();
+        assert!(step.events.is_empty());
+    }
+
+    #[test]
+    fn default_segments_and_overrides() {
+        let mut machine = machine_with_program(&[
+            0xbb, 0x10, 0x00,                   // mov bx, 0x10
+            0xbd, 0x10, 0x00,                   // mov bp, 0x10
+            0xbe, 0x30, 0x00,                   // mov si, 0x30
+            0xc7, 0x07, 0x01, 0x00,             // mov [bx], word 1
+            0xc7, 0x46, 0x00, 0x02, 0x00,       // mov [bp], word 2
+            0xc7, 0x02, 0x03, 0x00,             // mov [bp + si], word 3
+            0xc7, 0x06, 0x20, 0x00, 0x04, 0x00, // mov [0x20], word 4
+            0x26, 0xc7, 0x07, 0x05, 0x00,       // mov es:[bx], word 5
+            0x3e, 0xc7, 0x46, 0x02, 0x06, 0x00, // mov ds:[bp + 2], word 6
+        ]);
+        machine.registers.segment_registers[DS as usize] = 0x1000;
+        machine.registers.segment_registers[SS as usize] = 0x2000;
+        machine.registers.segment_registers[ES as usize] = 0x3000;
+        // the program is still fetched from cs
+        assert_eq!(machine.run_until(100), StopReason::Halted);
+
+        assert_eq!(read_memory(&machine.memory, 0x1000, 0x10, true), 1);
+        assert_eq!(read_memory(&machine.memory, 0x2000, 0x10, true), 2);
+        assert_eq!(read_memory(&machine.memory, 0x2000, 0x40, true), 3);
+        assert_eq!(read_memory(&machine.memory, 0x1000, 0x20, true), 4);
+        assert_eq!(read_memory(&machine.memory, 0x3000, 0x10, true), 5);
+        assert_eq!(read_memory(&machine.memory, 0x1000, 0x12, true), 6);
+    }
+
+    #[test]
+    fn alu_flags() {
+        check_alu(Operation::Add_RegMem_With_Reg_To_Either, false, 0xff, 0x01, "", Some(0x00), "CPAZ");
+        check_alu(Operation::Add_RegMem_With_Reg_To_Either, false, 0x7f, 0x01, "", Some(0x80), "ASO");
+        check_alu(Operation::Add_RegMem_With_Reg_To_Either, true, 0xffff, 0x0001, "", Some(0x0000), "CPAZ");
+        // parity only looks at the low byte
+        check_alu(Operation::Add_RegMem_With_Reg_To_Either, true, 0x7fff, 0x0001, "", Some(0x8000), "PASO");
+        check_alu(Operation::Adc_RegMem_With_Reg_To_Either, false, 0x0f, 0x00, "C", Some(0x10), "A");
+        check_alu(Operation::Adc_RegMem_With_Reg_To_Either, true, 0xffff, 0xffff, "C", Some(0xffff), "CPAS");
+        check_alu(Operation::Sub_RegMem_And_Reg_From_Either, false, 0x00, 0x01, "", Some(0xff), "CPAS");
+        check_alu(Operation::Sub_RegMem_And_Reg_From_Either, false, 0x80, 0x01, "", Some(0x7f), "AO");
+        check_alu(Operation::Sub_RegMem_And_Reg_From_Either, true, 0x8000, 0x0001, "", Some(0x7fff), "PAO");
+        check_alu(Operation::Sbb_RegMem_And_Reg_From_Either, false, 0x10, 0x0f, "C", Some(0x00), "PAZ");
+        check_alu(Operation::Sbb_RegMem_And_Reg_From_Either, true, 0x0000, 0x0000, "C", Some(0xffff), "CPAS");
+        // cmp only sets flags
+        check_alu(Operation::Cmp_RegMem_And_Reg, false, 0x05, 0x07, "", None, "CAS");
+        check_alu(Operation::Cmp_RegMem_And_Reg, true, 0x8000, 0x0001, "", None, "PAO");
+        // inc and dec keep the carry flag, whatever it was
+        check_alu(Operation::Inc_Reg, false, 0x7f, 0, "C", Some(0x80), "CASO");
+        check_alu(Operation::Inc_Reg, true, 0xffff, 0, "", Some(0x0000), "PAZ");
+        check_alu(Operation::Dec_Reg, false, 0x80, 0, "C", Some(0x7f), "CAO");
+        check_alu(Operation::Dec_Reg, true, 0x0001, 0, "", Some(0x0000), "PZ");
+        check_alu(Operation::Neg, false, 0x80, 0, "", Some(0x80), "CSO");
+        check_alu(Operation::Neg, false, 0x01, 0, "", Some(0xff), "CPAS");
+        check_alu(Operation::Neg, true, 0x0000, 0, "C", Some(0x0000), "PZ");
+    }
+
+    #[test]
+    fn stack_and_near_procedures() {
+        let mut machine = machine_with_program(&[
+            0xbc, 0x00, 0x01,             // mov sp, 0x100
+            0xb8, 0x34, 0x12,             // mov ax, 0x1234
+            0x50, 0x50, 0x50,             // push ax / push ax / push ax
+            0xe8, 0x06, 0x00,             // call f
+            0x5b,                         // pop bx
+            0x8f, 0x06, 0x00, 0x02,       // pop [0x200]
+            0xf4,                         // hlt
+            0xc2, 0x02, 0x00,             // f: ret 2
+        ]);
+
+        for _ in 0..6 { machine.step().ok().unwrap(); }
+        assert_eq!(machine.instruction_pointer, 0x12);
+        assert_eq!(register(&machine, SP), 0xf8);
+        assert_eq!(read_memory(&machine.memory, 0, 0xf8, true), 0x0c);
+
+        // ret 2 drops one of the pushes on the way back
+        machine.step().ok().unwrap();
+        assert_eq!(machine.instruction_pointer, 0x0c);
+        assert_eq!(register(&machine, SP), 0xfc);
+
+        assert_eq!(machine.run_until(10), StopReason::Halted);
+        assert_eq!(register(&machine, BX), 0x1234);
+        assert_eq!(read_memory(&machine.memory, 0, 0x200, true), 0x1234);
+        assert_eq!(register(&machine, SP), 0x100);
+    }
+
+    #[test]
+    fn push_sp_pushes_the_decremented_value() {
+        let mut machine = machine_with_program(&[
+            0xbc, 0x00, 0x01, // mov sp, 0x100
+            0x54,             // push sp
+            0xff, 0xf4,       // push sp, through the reg/mem form
+        ]);
+        assert_eq!(machine.run_until(10), StopReason::Halted);
+        assert_eq!(register(&machine, SP), 0xfc);
+        assert_eq!(read_memory(&machine.memory, 0, 0xfe, true), 0xfe);
+        assert_eq!(read_memory(&machine.memory, 0, 0xfc, true), 0xfc);
+    }
+
+    #[test]
+    fn far_and_indirect_transfers() {
+        let mut machine = machine_with_program(&[
+            0xbc, 0x00, 0x01,             // mov sp, 0x100
+            0x50,                         // push ax
+            0x9a, 0x00, 0x00, 0x20, 0x00, // call 0x0020:0x0000
+            0xff, 0x1e, 0x80, 0x02,       // call far [0x280]
+            0xbb, 0x40, 0x00,             // mov bx, 0x40
+            0xff, 0xe3,                   // jmp bx
+        ]);
+        machine.memory[0x40..0x45].copy_from_slice(&[ 0xea, 0x00, 0x00, 0x30, 0x00 ]); // jmp 0x0030:0x0000
+        machine.memory[0x200..0x203].copy_from_slice(&[ 0xca, 0x02, 0x00 ]); // retf 2
+        machine.memory[0x204] = 0xcb; // retf
+        machine.memory[0x280..0x284].copy_from_slice(&[ 0x04, 0x00, 0x20, 0x00 ]); // 0x0020:0x0004
+        machine.memory[0x300] = 0xf4; // hlt
+        let get_cs_ip_sp = |machine: &Machine| (
+            machine.registers.segment_registers[CS as usize],
+            machine.instruction_pointer,
+            register(machine, SP),
+        );
+
+        // far calls push cs, then the offset of the next instruction
+        for _ in 0..3 { machine.step().ok().unwrap(); }
+        assert_eq!(get_cs_ip_sp(&machine), (0x20, 0x00, 0xfa));
+        assert_eq!((read_memory(&machine.memory, 0, 0xfa, true), read_memory(&machine.memory, 0, 0xfc, true)), (0x09, 0x00));
+        machine.step().ok().unwrap();
+        assert_eq!(get_cs_ip_sp(&machine), (0x00, 0x09, 0x100));
+
+        machine.step().ok().unwrap();
+        assert_eq!(get_cs_ip_sp(&machine), (0x20, 0x04, 0xfc));
+        machine.step().ok().unwrap();
+        assert_eq!(get_cs_ip_sp(&machine), (0x00, 0x0d, 0x100));
+
+        for _ in 0..2 { machine.step().ok().unwrap(); }
+        assert_eq!(get_cs_ip_sp(&machine), (0x00, 0x40, 0x100));
+        machine.step().ok().unwrap();
+        assert_eq!(get_cs_ip_sp(&machine), (0x30, 0x00, 0x100));
+        assert_eq!(machine.run_until(10), StopReason::Halted);
+    }
+
+    #[test]
+    fn alu_immediate_forms() {
+        let mut machine = machine_with_program(&[
+            0xb8, 0x34, 0x12,                   // mov ax, 0x1234
+            0x25, 0xff, 0x00,                   // and ax, 0x00ff
+            0x0c, 0x80,                         // or al, 0x80
+            0x83, 0xf0, 0xff,                   // xor ax, byte -1
+            0x83, 0x06, 0x00, 0x01, 0xfe,       // add word [0x100], byte -2
+            0x80, 0x3e, 0x00, 0x01, 0xfe,       // cmp [0x100], byte 0xfe
+            0x83, 0xeb, 0x01,                   // sub bx, byte 1
+            0xa8, 0x81,                         // test al, 0x81
+        ]);
+
+        // byte immediates in the 0x83 group are sign extended to the width of the destination
+        for _ in 0..4 { machine.step().ok().unwrap(); }
+        assert_eq!(register(&machine, AX), 0xff4b);
+        machine.step().ok().unwrap();
+        assert_eq!(read_memory(&machine.memory, 0, 0x100, true), 0xfffe);
+        machine.step().ok().unwrap();
+        assert_eq!(machine.flags.get_active_flags_string(), "PZ");
+        machine.step().ok().unwrap();
+        assert_eq!(register(&machine, BX), 0xffff);
+        assert_eq!(machine.flags.get_active_flags_string(), "CPAS");
+        machine.step().ok().unwrap();
+        assert_eq!(register(&machine, AX), 0xff4b);
+        assert_eq!(machine.flags.get_active_flags_string(), "");
+    }
+
+    #[test]
+    fn logic_ops_clear_carry_and_overflow() {
+        check_alu(Operation::And_RegMem_With_Reg_To_Either, false, 0xf0, 0x3c, "CO", Some(0x30), "P");
+        check_alu(Operation::Or_Imm_To_RegMem, true, 0x8000, 0x0001, "CO", Some(0x8001), "S");
+        check_alu(Operation::Xor_Imm_To_Acc, false, 0x5a, 0x5a, "CO", Some(0x00), "PZ");
+        // test only sets flags
+        check_alu(Operation::Test_Imm_And_Acc, false, 0x4b, 0x81, "CO", None, "");
+        // not doesn't change any
+        check_alu(Operation::Not, true, 0x00ff, 0, "CO", Some(0xff00), "CO");
+    }
+
+    #[track_caller]
+    fn check_shift(operation: Operation, wide: bool, destination: u16, count: u16, flags_before: &str, result: u16, flags_after: &str) {
+        let mut flags = flags(flags_before);
+        assert_eq!(shift_rotate(&operation, destination, count, wide, &mut flags), result);
+        assert_eq!(flags.get_active_flags_string(), flags_after);
+    }
+
+    #[test]
+    fn shift_rotate_flags() {
+        check_shift(Operation::Shl, false, 0x81, 1, "", 0x02, "CO");
+        check_shift(Operation::Shl, false, 0x40, 1, "", 0x80, "SO");
+        // shr overflows when the sign changes, sar never does
+        check_shift(Operation::Shr, false, 0x81, 1, "", 0x40, "CO");
+        check_shift(Operation::Sar, false, 0x81, 1, "", 0xc0, "CPS");
+        // rotates only change carry and overflow
+        check_shift(Operation::Rol, false, 0x81, 1, "", 0x03, "CO");
+        check_shift(Operation::Ror, false, 0x01, 1, "", 0x80, "CO");
+        check_shift(Operation::Rcl, false, 0x80, 1, "", 0x00, "CO");
+        check_shift(Operation::Rcr, false, 0x01, 1, "C", 0x80, "CO");
+        // a count of zero changes nothing
+        check_shift(Operation::Shl, false, 0x81, 0, "C", 0x81, "C");
+        // carry is the last bit shifted out
+        check_shift(Operation::Shl, true, 0x8001, 4, "", 0x0010, "");
+        check_shift(Operation::Shr, true, 0x0018, 4, "", 0x0001, "C");
+        check_shift(Operation::Sar, true, 0x8000, 16, "", 0xffff, "CPS");
+        // the 8086 doesn't mask the count to 5 bits
+        check_shift(Operation::Rol, true, 0x8000, 17, "", 0x0001, "CO");
+    }
+
+    #[test]
+    fn shift_by_cl() {
+        // mov cl, 3 / shl ax, cl
+        let mut machine = machine_with_program(&[ 0xb1, 0x03, 0xd3, 0xe0 ]);
+        machine.registers.set_register_value(AX, &RegisterAccess::Full, 0x2001);
+        machine.step().ok().unwrap();
+        let step = machine.step().ok().unwrap();
+        assert_eq!(register(&machine, AX), 0x0008);
+        assert_eq!(machine.flags.get_active_flags_string(), "CO");
+        assert_eq!(step.clocks, 8 + 4 * 3);
+    }
+
+    // jcc +2, taken with one set of flags and not with the other
+    #[track_caller]
+    fn check_conditional_jump(opcode: u8, taken_flags: &str, not_taken_flags: &str) {
+        for (active_flags, instruction_pointer_after, clocks) in [ (taken_flags, 4, 16), (not_taken_flags, 2, 4) ] {
+            let mut machine = machine_with_program(&[ opcode, 0x02 ]);
+            machine.flags = flags(active_flags);
+            let step = machine.step().ok().unwrap();
+            assert_eq!((step.instruction_pointer_after, step.clocks), (instruction_pointer_after, clocks), "{} with {}", step.instruction, active_flags);
+        }
+    }
+
+    #[test]
+    fn conditional_jumps_test_flags() {
+        check_conditional_jump(0x70, "O", ""); // jo
+        check_conditional_jump(0x71, "", "O"); // jno
+        check_conditional_jump(0x72, "C", ""); // jb
+        check_conditional_jump(0x73, "", "C"); // jnb
+        check_conditional_jump(0x74, "Z", ""); // je
+        check_conditional_jump(0x75, "", "Z"); // jne
+        check_conditional_jump(0x76, "Z", ""); // jbe
+        check_conditional_jump(0x77, "", "C"); // ja
+        check_conditional_jump(0x78, "S", ""); // js
+        check_conditional_jump(0x79, "", "S"); // jns
+        check_conditional_jump(0x7a, "P", ""); // jp
+        check_conditional_jump(0x7b, "", "P"); // jnp
+        // the signed comparisons look at sign and overflow together
+        check_conditional_jump(0x7c, "S", "SO"); // jl
+        check_conditional_jump(0x7d, "SO", "O"); // jnl
+        check_conditional_jump(0x7e, "O", "SO"); // jle
+        check_conditional_jump(0x7f, "SO", "SZO"); // jg
+    }
+
+    // loop +2 with cx and the zero flag set up beforehand
+    #[track_caller]
+    fn check_loop(opcode: u8, cx: u16, active_flags: &str, is_taken: bool, cx_after: u16, clocks: u32) {
+        let mut machine = machine_with_program(&[ opcode, 0x02 ]);
+        machine.registers.set_register_value(CX, &RegisterAccess::Full, cx);
+        machine.flags = flags(active_flags);
+        let step = machine.step().ok().unwrap();
+        assert_eq!(step.instruction_pointer_after, if is_taken { 4 } else { 2 });
+        assert_eq!(register(&machine, CX), cx_after);
+        assert_eq!(step.clocks, clocks);
+    }
+
+    #[test]
+    fn loops_count_cx_and_clocks() {
+        check_loop(0xe2, 2, "", true, 1, 17); // loop
+        check_loop(0xe2, 1, "", false, 0, 5);
+        check_loop(0xe1, 2, "Z", true, 1, 18); // loopz
+        check_loop(0xe1, 2, "", false, 1, 6);
+        check_loop(0xe1, 1, "Z", false, 0, 6);
+        check_loop(0xe0, 2, "", true, 1, 19); // loopnz
+        check_loop(0xe0, 2, "Z", false, 1, 5);
+        // jcxz doesn't decrement cx
+        check_loop(0xe3, 0, "", true, 0, 18);
+        check_loop(0xe3, 1, "", false, 1, 6);
+    }
+
+    // ax and dx after, or None for a divide error
+    #[track_caller]
+    fn check_multiply_divide(operation: Operation, wide: bool, ax: u16, dx: u16, source: u16, result: Option<(u16, u16)>, carry_and_overflow: bool) {
+        const DX: u8 = 2;
+        let mut registers = RegisterSet::new();
+        registers.set_register_value(AX, &RegisterAccess::Full, ax);
+        registers.set_register_value(DX, &RegisterAccess::Full, dx);
+        let mut flags = Flags::new();
+        let is_ok = execute_multiply_divide(&operation, source, wide, &mut registers, &mut flags).is_ok();
+
+        let registers_after = (registers.get_register_value(AX, &RegisterAccess::Full), registers.get_register_value(DX, &RegisterAccess::Full));
+        assert_eq!(is_ok.then_some(registers_after), result);
+        assert_eq!((flags.carry, flags.overflow), (carry_and_overflow, carry_and_overflow));
+    }
+
+    #[test]
+    fn multiply_and_divide() {
+        check_multiply_divide(Operation::Mul, false, 0x0010, 0, 0x10, Some((0x0100, 0)), true);
+        check_multiply_divide(Operation::Mul, false, 0x0010, 0, 0x0f, Some((0x00f0, 0)), false);
+        check_multiply_divide(Operation::Mul, true, 0x1000, 0, 0x0010, Some((0x0000, 0x0001)), true);
+        check_multiply_divide(Operation::Mul, true, 0x00ff, 0, 0x0101, Some((0xffff, 0x0000)), false);
+        // -1 * -128 doesn't fit in a signed byte
+        check_multiply_divide(Operation::Imul, false, 0x00ff, 0, 0x80, Some((0x0080, 0)), true);
+        check_multiply_divide(Operation::Imul, false, 0x00fe, 0, 0x03, Some((0xfffa, 0)), false);
+        check_multiply_divide(Operation::Imul, true, 0xffff, 0, 0x8000, Some((0x8000, 0x0000)), true);
+        check_multiply_divide(Operation::Imul, true, 0xfffe, 0, 0x0003, Some((0xfffa, 0xffff)), false);
+        // 263 / 16 is 16 remainder 7
+        check_multiply_divide(Operation::Div, false, 0x0107, 0, 0x10, Some((0x0710, 0)), false);
+        check_multiply_divide(Operation::Div, false, 0x1000, 0, 0x10, None, false);
+        check_multiply_divide(Operation::Div, false, 0x0107, 0, 0x00, None, false);
+        check_multiply_divide(Operation::Div, true, 0x0005, 0x0001, 0x0010, Some((0x1000, 0x0005)), false);
+        check_multiply_divide(Operation::Div, true, 0x0000, 0x0010, 0x0010, None, false);
+        // -7 / 2 is -3 remainder -1, the remainder takes the sign of the dividend
+        check_multiply_divide(Operation::Idiv, false, 0xfff9, 0, 0x02, Some((0xfffd, 0)), false);
+        check_multiply_divide(Operation::Idiv, false, 0x0007, 0, 0xfe, Some((0x01fd, 0)), false);
+        // the 8086 can't return -128
+        check_multiply_divide(Operation::Idiv, false, 0xff00, 0, 0x02, None, false);
+        check_multiply_divide(Operation::Idiv, true, 0xfff9, 0xffff, 0x0002, Some((0xfffd, 0xffff)), false);
+        check_multiply_divide(Operation::Idiv, true, 0x0007, 0x0000, 0x0000, None, false);
+    }
+
+    // base is only used by aam and aad, ax after is None for a divide error
+    #[track_caller]
+    fn check_adjust(operation: Operation, base: u16, ax: u16, flags_before: &str, result: Option<u16>, flags_after: &str) {
+        let mut registers = RegisterSet::new();
+        registers.set_register_value(AX, &RegisterAccess::Full, ax);
+        let mut flags = flags(flags_before);
+        let is_ok = execute_accumulator_adjust(&operation, base, &mut registers, &mut flags).is_ok();
+
+        assert_eq!(is_ok.then_some(registers.get_register_value(AX, &RegisterAccess::Full)), result);
+        assert_eq!(flags.get_active_flags_string(), flags_after);
+    }
+
+    #[test]
+    fn decimal_adjusts() {
+        // 79h + 35h = aeh is 114 in bcd
+        check_adjust(Operation::Daa, 0, 0x00ae, "", Some(0x0014), "CPA");
+        check_adjust(Operation::Daa, 0, 0x0012, "", Some(0x0012), "P");
+        // 35h - 47h = eeh with a borrow is 88 in bcd, borrowing
+        check_adjust(Operation::Das, 0, 0x00ee, "CA", Some(0x0088), "CPAS");
+        // the low adjustment borrows on its own
+        check_adjust(Operation::Das, 0, 0x0003, "A", Some(0x00fd), "CAS");
+        check_adjust(Operation::Aaa, 0, 0x000d, "", Some(0x0103), "CA");
+        check_adjust(Operation::Aaa, 0, 0x0005, "", Some(0x0005), "");
+        check_adjust(Operation::Aas, 0, 0x00fd, "A", Some(0xff07), "CA");
+        check_adjust(Operation::Aam, 10, 0x003f, "", Some(0x0603), "P");
+        check_adjust(Operation::Aam, 0, 0x003f, "", None, "");
+        check_adjust(Operation::Aad, 10, 0x0603, "", Some(0x003f), "P");
+    }
+
+    #[test]
+    fn divide_errors_raise_interrupt_0() {
+        // div bl / aam 0, at 0100:0000 to keep the interrupt vectors free
+        let program = [ 0xf6, 0xf3, 0xd4, 0x00 ];
+        let mut machine = Machine::new();
+        machine.memory[0x1000..0x1000 + program.len()].copy_from_slice(&program);
+        machine.registers.segment_registers[CS as usize] = 0x0100;
+        machine.registers.segment_registers[SS as usize] = 0x0300;
+        machine.registers.set_register_value(SP, &RegisterAccess::Full, 0x0100);
+        machine.memory[0..4].copy_from_slice(&[ 0x02, 0x00, 0x00, 0x01 ]); // int 0 goes to 0100:0002
+
+        // the 8086 pushes the address after the instruction that faulted
+        let step = machine.step().ok().unwrap();
+        assert!(matches!(step.events[0], StepEvent::DivideError));
+        assert_eq!((machine.registers.segment_registers[CS as usize], machine.instruction_pointer), (0x0100, 0x0002));
+        assert_eq!(read_memory(&machine.memory, 0x0300, 0x00fa, true), 0x0002);
+        assert_eq!(read_memory(&machine.memory, 0x0300, 0x00fc, true), 0x0100);
+
+        let step = machine.step().ok().unwrap();
+        assert!(matches!(step.events[0], StepEvent::DivideError));
+        assert_eq!(machine.instruction_pointer, 0x0002);
+        assert_eq!(machine.registers.get_register_value(SP, &RegisterAccess::Full), 0x00f4);
+    }
+
+    #[test]
+    fn word_transfer_penalties() {
+        let program = [
+            0xbb, 0x01, 0x00, // mov bx, 1
+            0x8b, 0x07,       // mov ax, [bx]
+            0x43,             // inc bx
+            0x01, 0x07,       // add [bx], ax
+        ];
+
+        let mut machine = machine_with_program(&program);
+        machine.step().ok().unwrap();
+        let step = machine.step().ok().unwrap();
+        assert_eq!((step.clocks, step.clock_explanation.as_deref()), (17, Some("8 + 5ea + 4p")));
+        machine.step().ok().unwrap();
+        let step = machine.step().ok().unwrap();
+        assert_eq!((step.clocks, step.clock_explanation.as_deref()), (21, Some("16 + 5ea")));
+
+        // every word transfer takes an extra bus cycle on the 8088, add reads and writes back
+        let mut machine = machine_with_program(&program);
+        machine.cpu = Cpu::I8088;
+        for _ in 0..3 { machine.step().ok().unwrap(); }
+        let step = machine.step().ok().unwrap();
+        assert_eq!((step.clocks, step.clock_explanation.as_deref()), (29, Some("16 + 5ea + 8p")));
+    }
+
+    #[test]
+    fn rep_strings_stop_and_follow_direction() {
+        const SI: u8 = 6;
+        const DI: u8 = 7;
+        let string_machine = |program: &[u8], cx: u16, si: u16, di: u16| {
+            let mut machine = machine_with_program(program);
+            machine.memory[0x200..0x204].copy_from_slice(b"abcx");
+            machine.memory[0x300..0x304].copy_from_slice(b"abcy");
+            machine.registers.set_register_value(CX, &RegisterAccess::Full, cx);
+            machine.registers.set_register_value(SI, &RegisterAccess::Full, si);
+            machine.registers.set_register_value(DI, &RegisterAccess::Full, di);
+            machine
+        };
+        let get_cx_si_di = |machine: &Machine| [ CX, SI, DI ].map(|encoding| register(machine, encoding));
+
+        // repe cmpsb stops after the first difference
+        let mut machine = string_machine(&[ 0xf3, 0xa6 ], 10, 0x200, 0x300);
+        machine.step().ok().unwrap();
+        assert_eq!(get_cx_si_di(&machine), [ 6, 0x204, 0x304 ]);
+        assert!(!machine.flags.zero);
+
+        // repne scasb stops after the first match
+        let mut machine = string_machine(&[ 0xf2, 0xae ], 10, 0, 0x300);
+        machine.registers.set_register_value(AX, &RegisterAccess::Low, b'c' as u16);
+        machine.step().ok().unwrap();
+        assert_eq!(get_cx_si_di(&machine), [ 7, 0, 0x303 ]);
+        assert!(machine.flags.zero);
+
+        // rep with cx = 0 doesn't run the instruction at all
+        let mut machine = string_machine(&[ 0xf3, 0xa4 ], 0, 0x200, 0x300);
+        machine.step().ok().unwrap();
+        assert_eq!(get_cx_si_di(&machine), [ 0, 0x200, 0x300 ]);
+        assert_eq!(&machine.memory[0x300..0x304], b"abcy");
+
+        // std / rep movsw copies downwards
+        let mut machine = string_machine(&[ 0xfd, 0xf3, 0xa5 ], 2, 0x202, 0x302);
+        machine.step().ok().unwrap();
+        machine.step().ok().unwrap();
+        assert_eq!(get_cx_si_di(&machine), [ 0, 0x1fe, 0x2fe ]);
+        assert_eq!(&machine.memory[0x300..0x304], b"abcx");
+    }
+
+    #[test]
+    fn long_rep_strings_count_every_clock() {
+        // mov cx, 4096 / rep movsw
+        let mut machine = machine_with_program(&[ 0xb9, 0x00, 0x10, 0xf3, 0xa5 ]);
+        machine.step().ok().unwrap();
+        let step = machine.step().ok().unwrap();
+        assert_eq!((step.clocks, step.clock_explanation.as_deref()), (69641, Some("9 + 17*4096rep")));
+
+        // mov cx, 65535 / rep stosb, with the prefetch queue waiting on all of it
+        let mut machine = machine_with_program(&[ 0xb9, 0xff, 0xff, 0xf3, 0xaa ]);
+        machine.cpu = Cpu::I8088;
+        machine.prefetch_model = Some(PrefetchModel::new());
+        machine.step().ok().unwrap();
+        let step = machine.step().ok().unwrap();
+        assert_eq!(step.clocks, 9 + 10 * 65535);
+        assert!(step.cycles.unwrap() >= step.clocks);
+    }
+}
@@ -1,64 +1,45 @@
 use std::{
     env,
     process,
-    io::prelude::*,
+    io::{self, prelude::*},
+    fmt::Write as _,
     fs,
+    net::TcpListener,
 };
 
-mod decoder;
-use decoder::*;
-
-struct Flags { sign: bool, zero: bool }
-impl Flags {
-    fn new() -> Self { Self { sign: false, zero: false } }
-
-    fn get_active_flags_string(&self) -> String {
-        let mut str = String::with_capacity(2); // should match number of flag fields
-        if self.sign { str += "S"; }
-        if self.zero { str += "Z"; }
+use rust_impl::{
+    debugger::*,
+    decoder::*,
+    disassembler::*,
+    dos::*,
+    gdb::*,
+    limits::*,
+    machine::*,
+    prefetch::*,
+    profile::*,
+    trace::*,
+};
 
-        str
-    }
+fn get_total_cycles(machine: &Machine) -> Option<u64> {
+    machine.prefetch_model.as_ref().map(|prefetch_model| prefetch_model.total_cycles)
 }
 
-struct RegisterSet { registers: [u16; 8] }
-impl RegisterSet {
-    fn new() -> Self { Self { registers: [0u16; 8] } }
-
-    fn get_register_value(&self, encoding: u8, access: &RegisterAccess) -> u16 {
-        match access {
-            RegisterAccess::Low => self.registers[encoding as usize].to_le_bytes()[0] as u16,
-            RegisterAccess::High => self.registers[encoding as usize].to_le_bytes()[1] as u16,
-            RegisterAccess::Full => self.registers[encoding as usize],
-        }
+// The registers, ip, flags and clocks a run ended with
+fn get_final_state_text(machine: &Machine) -> String {
+    let mut text = String::from("Final register states:\n");
+    for (register_index, value) in machine.registers.registers.iter().enumerate() {
+        writeln!(text, "\t{}: {:#06x} ({})", get_register_name(register_index as u8, true).expect("Invalid register"), value, value).unwrap();
     }
-
-    fn set_register_value(&mut self, encoding: u8, access: &RegisterAccess, value: u16) {
-        let register = &mut self.registers[encoding as usize];
-
-        match access {
-            RegisterAccess::Full => *register = value,
-            RegisterAccess::High => set_high_byte(register, value as u8),
-            RegisterAccess::Low => set_low_byte(register, value as u8),
-        };
+    for (register_index, value) in machine.registers.segment_registers.iter().enumerate() {
+        writeln!(text, "\t{}: {:#06x} ({})", get_segment_register_name(register_index as u8).expect("Invalid segment register"), value, value).unwrap();
     }
-
-    fn calculate_effective_address(&self, base: &EffectiveAddressBase, displacement: u16) -> u16 {
-        base.get_register_encodings()
-            .iter()
-            .filter_map(|v| *v)
-            .fold(displacement, |acc, reg| acc + self.get_register_value(reg, &RegisterAccess::Full))
+    writeln!(text).unwrap();
+    writeln!(text, "ip: {:#x} ({})", machine.instruction_pointer, machine.instruction_pointer).unwrap();
+    writeln!(text, "flags: {}", machine.flags.get_active_flags_string()).unwrap();
+    if let Some(total_cycles) = get_total_cycles(machine) {
+        writeln!(text, "clocks: {} from the timing tables, {} with the prefetch queue", machine.total_clocks, total_cycles).unwrap();
     }
-}
-
-fn set_high_byte(value: &mut u16, to: u8) {
-    let ptr: *mut u16 = value;
-    unsafe { *((ptr as *mut u8).offset(1)) = to };
-}
-
-fn set_low_byte(value: &mut u16, to: u8) {
-    let ptr: *mut u16 = value;
-    unsafe { *(ptr as *mut u8) = to };
+    text
 }
 
 fn main() {
@@ -70,6 +51,16 @@ fn main() {
     let mut should_dump_memory = false;
     let mut should_show_clocks = false;
     let mut should_explain_clocks = false;
+    let mut should_print_listing = false;
+    let mut should_trace_json = false;
+    let mut cpu = Cpu::default();
+    let mut should_model_prefetch = false;
+    let mut should_run_com = false;
+    let mut should_run_exe = false;
+    let mut should_debug = false;
+    let mut gdb_port: Option<u16> = None;
+    let mut run_limits = RunLimits::new();
+    let mut should_profile = false;
     // we'll skip the first arg since it should just be the executable filename
     let mut arg_index = 1;
     // probably dumb way to parse args
@@ -103,6 +94,103 @@ fn main() {
                 arg_index += 1;
             },
 
+            "--listing" => {
+                should_print_listing = true;
+                arg_index += 1;
+            },
+
+            "--trace-format" => {
+                should_trace_json = match args.get(arg_index + 1).map(|format| format.as_str()) {
+                    Some("text") => false,
+                    Some("json") => true,
+                    _ => {
+                        println!("trace-format arg requires a format, text or json");
+                        process::exit(1);
+                    },
+                };
+
+                arg_index += 2;
+            },
+
+            "--cpu" => {
+                cpu = match args.get(arg_index + 1).map(|cpu| cpu.as_str()) {
+                    Some("8086") => Cpu::I8086,
+                    Some("8088") => Cpu::I8088,
+                    _ => {
+                        println!("cpu arg requires a cpu, 8086 or 8088");
+                        process::exit(1);
+                    },
+                };
+
+                arg_index += 2;
+            },
+
+            "--com" => {
+                should_run_com = true;
+                arg_index += 1;
+            },
+
+            "--exe" => {
+                should_run_exe = true;
+                arg_index += 1;
+            },
+
+            "--debug" => {
+                should_debug = true;
+                arg_index += 1;
+            },
+
+            "--gdb" => {
+                gdb_port = match args.get(arg_index + 1).and_then(|port| port.parse().ok()) {
+                    Some(port) => Some(port),
+                    None => {
+                        println!("gdb arg requires a port to listen on");
+                        process::exit(1);
+                    },
+                };
+
+                arg_index += 2;
+            },
+
+            "--max-instructions" => {
+                run_limits.max_instructions = match args.get(arg_index + 1).and_then(|count| count.parse().ok()) {
+                    Some(count) => Some(count),
+                    None => {
+                        println!("max-instructions arg requires a number of instructions");
+                        process::exit(1);
+                    },
+                };
+
+                arg_index += 2;
+            },
+
+            "--max-clocks" => {
+                run_limits.max_clocks = match args.get(arg_index + 1).and_then(|count| count.parse().ok()) {
+                    Some(count) => Some(count),
+                    None => {
+                        println!("max-clocks arg requires a number of clocks");
+                        process::exit(1);
+                    },
+                };
+
+                arg_index += 2;
+            },
+
+            "--detect-loops" => {
+                run_limits.should_detect_loops = true;
+                arg_index += 1;
+            },
+
+            "--profile" => {
+                should_profile = true;
+                arg_index += 1;
+            },
+
+            "--prefetch" => {
+                should_model_prefetch = true;
+                arg_index += 1;
+            },
+
             _ => {
                 if assembly_filename.is_some() {
                     println!("more than one input file specified, aborting");
@@ -131,270 +219,238 @@ fn main() {
         println!("Can't explain clocks if not showing clocks, include --showclocks");
     }
 
-    if should_dump_memory && !should_execute {
-        println!("Memory can only be dumped if executing a program");
+    if should_run_com && should_run_exe {
+        println!("A program is either a .com or an .exe, remove --com or --exe");
         process::exit(1);
     }
+    let should_run_dos = should_run_com || should_run_exe;
 
-    let mut file = fs::File::open(assembly_filename).unwrap_or_else(|_| panic!("Failed to open file {}", assembly_filename));
-    let mut instruction_stream: Vec<u8> = vec![];
-    file.read_to_end(&mut instruction_stream).expect("Failed to read file");
-
-    // TODO move flags, instruction pointer into RegisterSet
-    let mut register_set = RegisterSet::new();
-    let mut flags = Flags::new();
-    let mut instruction_pointer = 0;
-    let mut total_clocks = 0;
-    let mut memory = [0u8; u16::MAX as usize]; // 64k instead of 1MB since not using segment registers
-    memory[..instruction_stream.len()].copy_from_slice(&instruction_stream);
-    memory[instruction_stream.len()] = 0b11110100; // insert HALT at end of instructions
-    drop(instruction_stream);
-
-    loop {
-        let instruction = decode_instruction(&memory, instruction_pointer);
-        if instruction.is_none() {
-            println!("illegal or unimplemented operation encountered, halting");
-            break;
-        }
-
-        let instruction = instruction.unwrap();
-        if instruction.operation == Operation::Halt { break; }
-
-        instruction_pointer += instruction.size as usize;
+    if should_run_dos && should_execute {
+        println!("A dos program runs without a trace, remove --execute");
+        process::exit(1);
+    }
 
-        if !should_execute {
-            println!("{}", instruction);
-            continue;
-        }
+    if should_debug && should_execute {
+        println!("The debugger runs the program itself, remove --execute");
+        process::exit(1);
+    }
 
-        print!("{} ;", instruction);
+    if gdb_port.is_some() && (should_execute || should_debug) {
+        println!("gdb runs the program itself, remove --execute and --debug");
+        process::exit(1);
+    }
 
-        let flags_before = flags.get_active_flags_string();
-        let instruction_pointer_before = instruction_pointer;
+    if should_profile && (should_debug || gdb_port.is_some()) {
+        println!("A program can only be profiled when it runs on its own, remove --debug and --gdb");
+        process::exit(1);
+    }
 
-        match &instruction.operands {
-            [ Some(destination), Some(source) ] => {
-                let source_value: u16 = match source {
-                    Operand::Register(encoding, access) => register_set.get_register_value(*encoding, access),
-                    Operand::ImmediateData(data) => *data,
+    if should_profile && should_trace_json {
+        println!("The profile is printed after the final state, remove --trace-format json");
+        process::exit(1);
+    }
 
-                    Operand::Memory(EffectiveAddress::Direct(address)) => if instruction.flags.wide {
-                        read_word(&memory, *address as usize)
-                    } else {
-                        memory[*address as usize] as u16
-                    },
+    let running_flags: Vec<&str> = [
+        (should_execute, "--execute"),
+        (should_run_com, "--com"),
+        (should_run_exe, "--exe"),
+        (should_debug, "--debug"),
+        (gdb_port.is_some(), "--gdb"),
+        (should_profile, "--profile"),
+    ].into_iter().filter_map(|(is_given, flag)| is_given.then_some(flag)).collect();
+    if should_print_listing && !running_flags.is_empty() {
+        println!("A listing can only be printed when disassembling, remove {}", running_flags.join(" and "));
+        process::exit(1);
+    }
 
-                    Operand::Memory(EffectiveAddress::Calculated { base, displacement }) => {
-                        let address = register_set.calculate_effective_address(base, *displacement);
+    if should_trace_json && !should_execute {
+        println!("A trace format can only be given when executing a program, include --execute");
+        process::exit(1);
+    }
 
-                        if instruction.flags.wide {
-                            read_word(&memory, address as usize)
-                        } else {
-                            memory[address as usize] as u16
-                        }
-                    },
+    if should_model_prefetch && !should_execute && !should_debug && gdb_port.is_none() && !should_profile {
+        println!("The prefetch queue can only be modelled when executing a program, include --execute");
+        process::exit(1);
+    }
 
-                    Operand::LabelOffset(_) => panic!("offset value cannot be a source"),
-                };
+    let has_run_limits = run_limits.max_instructions.is_some() || run_limits.max_clocks.is_some() || run_limits.should_detect_loops;
+    if has_run_limits && !should_execute && !should_run_dos && !should_profile {
+        println!("Run limits only apply when executing a program, include --execute");
+        process::exit(1);
+    }
 
-                let mut destination_value_before: Option<u16> = None;
-                let mut destination_value_after: Option<u16> = None;
-
-                match instruction.operation {
-                    Operation::Mov_RegMem_ToFrom_Reg
-                    | Operation::Mov_Imm_To_Reg
-                    | Operation::Mov_Imm_To_RegMem
-                    | Operation::Mov_Mem_To_Acc
-                    | Operation::Mov_Acc_To_Mem => {
-                        match destination {
-                            Operand::Register(encoding, access) => {
-                                destination_value_before = Some(register_set.get_register_value(*encoding, &RegisterAccess::Full));
-                                register_set.set_register_value(*encoding, access, source_value);
-                                destination_value_after = Some(register_set.get_register_value(*encoding, &RegisterAccess::Full));
-                            },
-
-                            Operand::Memory(EffectiveAddress::Direct(address)) => {
-                                let address = *address as usize;
-                                let [ lo, hi ] = source_value.to_le_bytes();
-                                if instruction.flags.wide {
-                                    memory[address] = lo;
-                                    memory[address + 1] = hi;
-                                } else {
-                                    memory[address] = lo;
-                                }
-                            },
-
-                            Operand::Memory(EffectiveAddress::Calculated { base, displacement }) => {
-                                let address = register_set.calculate_effective_address(base, *displacement) as usize;
-                                let [ lo, hi ] = source_value.to_le_bytes();
-                                if instruction.flags.wide {
-                                    memory[address] = lo;
-                                    memory[address + 1] = hi;
-                                } else {
-                                    memory[address] = lo;
-                                }
-                            },
-
-                            _ => panic!("cannot move into immediate or label offset"),
-                        };
-                    },
+    if should_dump_memory && !should_execute && !should_run_dos && !should_debug && gdb_port.is_none() && !should_profile {
+        println!("Memory can only be dumped if executing a program");
+        process::exit(1);
+    }
 
-                    Operation::Add_RegMem_With_Reg_To_Either
-                    | Operation::Add_Imm_To_RegMem
-                    | Operation::Add_Imm_To_Acc => {
-                        match destination {
-                            Operand::Register(encoding, access) => {
-                                let reg_val = register_set.get_register_value(*encoding, &RegisterAccess::Full);
-                                destination_value_before = Some(reg_val);
-
-                                register_set.set_register_value(*encoding, access, reg_val + source_value);
-                                let reg_val_after = register_set.get_register_value(*encoding, access);
-                                flags.zero = reg_val_after == 0;
-                                flags.sign = match access {
-                                    RegisterAccess::Full => (reg_val_after as i16) < 0,
-                                    RegisterAccess::Low => (reg_val_after.to_le_bytes()[0] as i8) < 0,
-                                    RegisterAccess::High => (reg_val_after.to_le_bytes()[1] as i8) < 0,
-                                };
-
-                                destination_value_after = Some(register_set.get_register_value(*encoding, &RegisterAccess::Full));
-                            },
-
-                            Operand::Memory(EffectiveAddress::Direct(address)) => {
-                                let address = *address as usize;
-                                if instruction.flags.wide {
-                                    let [ lo, hi ] = (read_word(&memory, address) + source_value).to_le_bytes();
-                                    memory[address] = lo;
-                                    memory[address + 1] = hi;
-                                } else {
-                                    memory[address] += (source_value & 0xFF) as u8;
-                                }
-                            },
-
-                            Operand::Memory(EffectiveAddress::Calculated { base, displacement }) => {
-                                let address = register_set.calculate_effective_address(base, *displacement) as usize;
-                                if instruction.flags.wide {
-                                    let [ lo, hi ] = (read_word(&memory, address) + source_value).to_le_bytes();
-                                    memory[address] = lo;
-                                    memory[address + 1] = hi;
-                                } else {
-                                    memory[address] += (source_value & 0xFF) as u8;
-                                }
-                            },
-
-                            _ => panic!("cannot add into immediate or label offset"),
-                        }
-                    },
+    let mut file = fs::File::open(assembly_filename).unwrap_or_else(|_| panic!("Failed to open file {}", assembly_filename));
+    let mut instruction_stream: Vec<u8> = vec![];
+    file.read_to_end(&mut instruction_stream).expect("Failed to read file");
 
-                    Operation::Sub_RegMem_And_Reg_From_Either
-                    | Operation::Sub_Imm_From_RegMem
-                    | Operation::Sub_Imm_From_Acc => {
-                        match destination {
-                            Operand::Register(encoding, access) => {
-                                let reg_val = register_set.get_register_value(*encoding, &RegisterAccess::Full);
-                                destination_value_before = Some(reg_val);
-
-                                register_set.set_register_value(*encoding, access, reg_val - source_value);
-                                let reg_val_after = register_set.get_register_value(*encoding, access);
-                                flags.zero = reg_val_after == 0;
-                                flags.sign = match access {
-                                    RegisterAccess::Full => (reg_val_after as i16) < 0,
-                                    RegisterAccess::Low => (reg_val_after.to_le_bytes()[0] as i8) < 0,
-                                    RegisterAccess::High => (reg_val_after.to_le_bytes()[1] as i8) < 0,
-                                };
-
-                                destination_value_after = Some(register_set.get_register_value(*encoding, &RegisterAccess::Full));
-                            },
-
-                            Operand::Memory(_) => todo!(),
-
-                            _ => panic!("cannot sub from immediate or label offset"),
-                        }
-                    },
+    if should_run_com && instruction_stream.len() > MAX_COM_SIZE {
+        println!("A .com program can be at most {} bytes", MAX_COM_SIZE);
+        process::exit(1);
+    }
 
-                    Operation::Cmp_RegMem_And_Reg
-                    | Operation::Cmp_Imm_With_RegMem
-                    | Operation::Cmp_Imm_With_Acc => {
-                        match destination {
-                            Operand::Register(encoding, access) => {
-                                let reg_val = register_set.get_register_value(*encoding, &RegisterAccess::Full);
-                                destination_value_before = Some(reg_val);
+    if !should_execute && !should_run_dos && !should_debug && gdb_port.is_none() && !should_profile {
+        let (listing, error) = if should_print_listing {
+            disassemble_listing(&instruction_stream)
+        } else {
+            disassemble(&instruction_stream)
+        };
+        print!("{}", listing);
+        if let Some(error) = error {
+            println!("{}, halting", error);
+            process::exit(1);
+        }
+    }
 
-                                let test_val = ((reg_val as i16) - (source_value as i16)) as u16;
-                                flags.zero = test_val == 0;
-                                flags.sign = match access {
-                                    RegisterAccess::Full => (test_val as i16) < 0,
-                                    RegisterAccess::Low => (test_val.to_le_bytes()[0] as i8) < 0,
-                                    RegisterAccess::High => (test_val.to_le_bytes()[1] as i8) < 0,
-                                };
+    let mut machine = Machine::new();
+    machine.cpu = cpu;
+    if should_model_prefetch { machine.prefetch_model = Some(PrefetchModel::new()); }
+    if should_run_com {
+        machine.load_com(&instruction_stream, Box::new(io::stdin()));
+    } else if should_run_exe {
+        if let Err(error) = machine.load_exe(&instruction_stream, EXE_LOAD_SEGMENT, Box::new(io::stdin())) {
+            println!("{}, aborting", error);
+            process::exit(1);
+        }
+    } else {
+        machine.load_program(&instruction_stream);
+    }
 
-                                destination_value_after = Some(register_set.get_register_value(*encoding, &RegisterAccess::Full));
-                            },
+    // labels come from the program's own bytes, wherever it was loaded
+    let labels = (should_debug || should_profile).then(|| {
+        let (program_start, program) = if should_run_com {
+            (get_physical_address(COM_SEGMENT, 0x100), &instruction_stream[..])
+        } else if should_run_exe {
+            let header = parse_exe_header(&instruction_stream).expect("exe was already loaded");
+            (get_physical_address(EXE_LOAD_SEGMENT, 0), &instruction_stream[header.header_size..header.image_end])
+        } else {
+            (0, &instruction_stream[..])
+        };
+        get_labels(program).into_iter().map(|(address, label)| (program_start + address, label)).collect()
+    });
+    drop(instruction_stream);
+    let (debugger, mut profile) = match labels {
+        Some(labels) if should_debug => (Some(Debugger::new(labels)), None),
+        Some(labels) => (None, Some(Profile::new(labels))),
+        None => (None, None),
+    };
+
+    if let Some(port) = gdb_port {
+        // only reachable from this machine, the protocol has no authentication
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap_or_else(|error| {
+            println!("Failed to listen on port {}: {}", port, error);
+            process::exit(1);
+        });
+        println!("waiting for gdb on 127.0.0.1:{}", port);
+        let (stream, address) = listener.accept().expect("Failed to accept gdb connection");
+        println!("gdb connected from {}", address);
+        if let Err(error) = serve(&mut machine, stream) {
+            println!("gdb connection failed: {}", error);
+        }
 
-                            Operand::Memory(_) => todo!(),
+        if should_dump_memory {
+            fs::write(memdump_filename, &machine.memory).expect("Failed to write memdump to file");
+        }
+    } else if let Some(mut debugger) = debugger {
+        println!("{}", debugger.get_current_location(&machine));
+        let mut line = String::new();
+        loop {
+            print!("(8086) ");
+            io::stdout().flush().expect("Failed to write prompt");
+            line.clear();
+            if io::stdin().read_line(&mut line).expect("Failed to read command") == 0 { break; }
+            match debugger.run_command(&mut machine, &line) {
+                Some(output) => print!("{}", output),
+                None => break,
+            }
+        }
 
-                            _ => panic!("cannot cmp immediate or label offset"),
+        if should_dump_memory {
+            fs::write(memdump_filename, &machine.memory).expect("Failed to write memdump to file");
+        }
+    } else if should_run_dos {
+        // only the program's own output goes to stdout
+        let mut stdout = io::stdout();
+        let stop_reason = loop {
+            match run_limits.step(&mut machine) {
+                Ok(step) => {
+                    for event in &step.events {
+                        if let StepEvent::Output(bytes) = event {
+                            stdout.write_all(bytes).and_then(|_| stdout.flush()).expect("Failed to write program output");
                         }
-                    },
-
-                    _ => panic!("Invalid 2-operand instruction encountered")
-                };
+                    }
+                    if let Some(profile) = &mut profile { profile.record(&step); }
+                    // there's no debugger attached to continue from an int 3
+                    if step.events.iter().any(|event| matches!(event, StepEvent::Breakpoint)) {
+                        break StopReason::Breakpoint { at: get_physical_address(step.code_segment, step.instruction_pointer_before) };
+                    }
+                },
+                Err(stop_reason) => break stop_reason,
+            }
+        };
 
+        // a run limit, loop detection, a breakpoint or a decode error stopped it, the state it stopped in says where
+        if !matches!(stop_reason, StopReason::Exited(_)) {
+            eprintln!("program stopped without exiting: {}", stop_reason);
+            eprint!("\n{}", get_final_state_text(&machine));
+        }
 
-                if destination_value_before.is_some() && destination_value_after.is_some() {
-                    let dst_bef = destination_value_before.unwrap();
-                    let dst_aft = destination_value_after.unwrap();
-                    print!(" {}:{:#x}({})->{:#x}({})", destination, dst_bef, dst_bef, dst_aft, dst_aft);
-                }
-            },
+        if let Some(profile) = &profile {
+            eprint!("\n{}", profile.report());
+        }
 
-            [ Some(Operand::LabelOffset(offset)), None ] => {
-                match instruction.operation {
-                    Operation::Jmp_On_Not_Equal => if !flags.zero { instruction_pointer = ((instruction_pointer as isize) + *offset as isize) as usize },
-                    // TODO stop hardcoding register indices
-                    Operation::Loop => {
-                        let cx_value = register_set.get_register_value(1 /* C */, &RegisterAccess::Full);
-                        register_set.set_register_value(1 /* C */, &RegisterAccess::Full, cx_value - 1);
-                        let cx_value = register_set.get_register_value(1 /* C */, &RegisterAccess::Full);
-                        if cx_value != 0 {
-                            instruction_pointer = ((instruction_pointer as isize) + *offset as isize) as usize;
-                        }
-                    },
+        if should_dump_memory {
+            fs::write(memdump_filename, &machine.memory).expect("Failed to write memdump to file");
+        }
 
-                    _ => todo!("this conditional jump not implemented")
-                };
-            },
-            [ Some(_), None ] => todo!("single-operand non-label-offset encountered"),
-            [ None, None ] => todo!("0-operand instructions not implemented"),
-            _ => panic!("invalid operand configuration [ None, Some(...) ]"),
+        match stop_reason {
+            StopReason::Exited(exit_code) => process::exit(exit_code as i32),
+            _ => process::exit(1),
+        }
+    } else if should_execute && should_trace_json {
+        // one json object per line, nothing else goes to stdout
+        let stop_reason = loop {
+            match run_limits.step(&mut machine) {
+                Ok(step) => println!("{}", step_to_json(&step, machine.total_clocks, get_total_cycles(&machine), &machine.memory)),
+                Err(stop_reason) => break stop_reason,
+            }
         };
+        println!("{}", final_state_to_json(&machine, &stop_reason));
 
-        if should_show_clocks {
-            let (clocks, explanation) = instruction.get_clocks_estimate();
-            total_clocks += clocks;
-            if should_explain_clocks && explanation.is_some() {
-                print!(" Clocks: +{} = {} ({}) |", clocks, total_clocks, explanation.unwrap());
-            } else {
-                print!(" Clocks: +{} = {} |", clocks, total_clocks);
+        if should_dump_memory {
+            fs::write(memdump_filename, &machine.memory).expect("Failed to write memdump to file");
+        }
+    } else if should_execute || should_profile {
+        // profiling on its own runs the program without a trace
+        loop {
+            match run_limits.step(&mut machine) {
+                Ok(step) => {
+                    if should_execute {
+                        println!("{}", step_to_text(&step, machine.total_clocks, get_total_cycles(&machine), should_show_clocks, should_explain_clocks));
+                    }
+                    if let Some(profile) = &mut profile { profile.record(&step); }
+                },
+                Err(StopReason::Halted) => break,
+                // decode errors and run limits
+                Err(stop_reason) => {
+                    println!("{}, halting", stop_reason);
+                    break;
+                },
             }
         }
 
-        print!(" ip:{:#x}->{:#x}", instruction_pointer_before, instruction_pointer);
-        let flags_after = flags.get_active_flags_string();
-        if flags_after.len() != flags_before.len() { print!(" flags:{}->{}", flags_before, flags_after); }
-        println!();
-    }
-
-    if should_execute {
-        println!("\nFinal register states:");
-        for (register_index, value) in register_set.registers.iter().enumerate() {
-            println!("\t{}: {:#06x} ({})", get_register_name(register_index as u8, true).expect("Invalid register"), value, value);
+        print!("\n{}", get_final_state_text(&machine));
+        if let Some(profile) = &profile {
+            println!();
+            print!("{}", profile.report());
         }
-        println!();
-        println!("ip: {:#x} ({})", instruction_pointer, instruction_pointer);
-        println!("flags: {}", flags.get_active_flags_string());
 
         if should_dump_memory {
-            fs::write(memdump_filename, memory).expect("Failed to write memdump to file");
+            fs::write(memdump_filename, &machine.memory).expect("Failed to write memdump to file");
             println!();
             println!("memory dumped to {}", memdump_filename);
         }
@@ -0,0 +1,112 @@
+use crate::machine::Cpu;
+
+// Cycle model of the two halves of the cpu. The bus interface unit (BIU) fetches instruction bytes
+// into the prefetch queue whenever the bus is free, in parallel with the execution unit (EU) running
+// instructions out of the queue. The EU stalls when the bytes of its next instruction haven't been
+// fetched yet and a jump throws away everything that was fetched past it.
+//
+// The EU takes the table clocks of every instruction, during which its own memory transfers keep the
+// BIU off the bus. It needs all bytes of an instruction in the queue before it starts, the real EU
+// can start decoding after the first byte so this is a little pessimistic for long instructions.
+
+const BUS_CYCLE_CLOCKS: u16 = 4;
+
+fn get_queue_size(cpu: Cpu) -> u16 {
+    match cpu {
+        Cpu::I8086 => 6,
+        Cpu::I8088 => 4,
+    }
+}
+
+// bytes one bus cycle fetches, the 8086 fetches words but only a byte from an odd address
+fn get_fetch_width(cpu: Cpu, offset: u16) -> u16 {
+    match cpu {
+        Cpu::I8086 => 2 - (offset & 1),
+        Cpu::I8088 => 1,
+    }
+}
+
+#[derive(Default)]
+pub struct PrefetchModel {
+    pub total_cycles: u64,
+    queued_bytes: u16,
+    fetch_offset: Option<u16>, // ip of the next byte to fetch, None after the queue was flushed
+    fetch_clocks: u16, // clocks into the bus cycle of the current fetch
+}
+
+impl PrefetchModel {
+    pub fn new() -> Self { Self::default() }
+
+    fn tick(&mut self, cpu: Cpu, is_bus_free: bool) {
+        self.total_cycles += 1;
+        if !is_bus_free { return; }
+
+        let Some(fetch_offset) = self.fetch_offset else { return; };
+        let width = get_fetch_width(cpu, fetch_offset);
+        if self.fetch_clocks == 0 && self.queued_bytes + width > get_queue_size(cpu) { return; }
+
+        self.fetch_clocks += 1;
+        if self.fetch_clocks == BUS_CYCLE_CLOCKS {
+            self.fetch_clocks = 0;
+            self.queued_bytes += width;
+            self.fetch_offset = Some(fetch_offset.wrapping_add(width));
+        }
+    }
+
+    // Runs one instruction at instruction_pointer through the model and returns the cycles it took,
+    // stalls included. bus_clocks are the clocks of execution_clocks the EU spends on its own memory
+    // transfers. A jump flushes the queue so fetching starts over at the jump target.
+    pub fn execute(
+        &mut self,
+        cpu: Cpu,
+        instruction_pointer: u16,
+        instruction_size: u16,
+        execution_clocks: u32,
+        bus_clocks: u32,
+        jumped: bool,
+    ) -> u32 {
+        let cycles_before = self.total_cycles;
+        if self.fetch_offset.is_none() { self.fetch_offset = Some(instruction_pointer); }
+
+        while self.queued_bytes < instruction_size { self.tick(cpu, true); }
+        self.queued_bytes -= instruction_size;
+
+        // the EU's transfers are spread out by real instructions, putting them first is close enough
+        for clock in 0..execution_clocks { self.tick(cpu, clock >= bus_clocks); }
+
+        if jumped {
+            self.queued_bytes = 0;
+            self.fetch_offset = None;
+            self.fetch_clocks = 0;
+        }
+
+        (self.total_cycles - cycles_before) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_instructions_wait_on_fetches() {
+        // inc ax is 1 byte and 2 clocks, the 8088 can only fetch a byte every 4 clocks
+        let mut model = PrefetchModel::new();
+        let cycles: Vec<u32> = (0..4).map(|index| model.execute(Cpu::I8088, index, 1, 2, 0, false)).collect();
+        assert_eq!(cycles, [ 6, 4, 4, 4 ]);
+
+        // the 8086 fetches 2 bytes every 4 clocks and keeps up
+        let mut model = PrefetchModel::new();
+        let cycles: Vec<u32> = (0..4).map(|index| model.execute(Cpu::I8086, index, 1, 2, 0, false)).collect();
+        assert_eq!(cycles, [ 6, 2, 2, 2 ]);
+    }
+
+    #[test]
+    fn jumps_flush_the_queue() {
+        // mov ax, 1 runs long enough to fill the queue, a jump after it throws the queue away
+        let mut model = PrefetchModel::new();
+        model.execute(Cpu::I8086, 0, 3, 4, 0, false);
+        model.execute(Cpu::I8086, 3, 2, 15, 0, true);
+        assert_eq!(model.execute(Cpu::I8086, 0x100, 1, 2, 0, false), 6);
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+};
+
+use crate::{
+    decoder::*,
+    machine::*,
+};
+
+// Where a program spends its clocks. Every executed instruction adds its hit and its estimated
+// clocks to its address, and every taken jump back to an earlier address marks a loop from the jump
+// target to the jump. A loop's clocks are the clocks of the instructions in that range, so whatever
+// it calls out of the range isn't counted.
+
+const MAX_HOTSPOTS: usize = 20;
+
+struct InstructionProfile {
+    segment: u16,
+    offset: u16,
+    instruction: String,
+    hits: u64,
+    clocks: u64,
+}
+
+struct LoopProfile {
+    segment: u16,
+    offset: u16,
+    end: usize, // physical address of the furthest jump back to the start
+    jumps_back: u64,
+}
+
+#[derive(Default)]
+pub struct Profile {
+    labels: BTreeMap<usize, String>, // by physical address
+    instructions: BTreeMap<usize, InstructionProfile>, // by physical address
+    loops: BTreeMap<usize, LoopProfile>, // by physical address of the start
+    total_clocks: u64,
+}
+
+fn is_jump_back(step: &Step) -> bool {
+    let is_relative_jump = step.instruction.operation != Operation::Call_Direct_Within_Segment
+        && matches!(step.instruction.operands[0], Some(Operand::LabelOffset(_)));
+    let instruction_pointer_next = step.instruction_pointer_before.wrapping_add(step.instruction.size as u16);
+    is_relative_jump && step.instruction_pointer_after != instruction_pointer_next && step.instruction_pointer_after <= step.instruction_pointer_before
+}
+
+// jumps, calls and loops to a label are shown with the label, like in the listing
+fn format_instruction(step: &Step, labels: &BTreeMap<usize, String>) -> String {
+    let target = match step.instruction.operands {
+        [ Some(Operand::LabelOffset(relative)), None ] => {
+            let target = step.instruction_pointer_before.wrapping_add(step.instruction.size as u16).wrapping_add(relative as u16);
+            labels.get(&get_physical_address(step.code_segment, target))
+        },
+        _ => None,
+    };
+    match target {
+        Some(label) => LabelledInstruction { instruction: &step.instruction, label }.to_string(),
+        None => step.instruction.to_string(),
+    }
+}
+
+fn get_share(clocks: u64, total_clocks: u64) -> f64 {
+    if total_clocks == 0 { 0.0 } else { 100.0 * clocks as f64 / total_clocks as f64 }
+}
+
+impl Profile {
+    pub fn new(labels: BTreeMap<usize, String>) -> Self { Self { labels, ..Self::default() } }
+
+    pub fn record(&mut self, step: &Step) {
+        let address = get_physical_address(step.code_segment, step.instruction_pointer_before);
+        let labels = &self.labels;
+        let instruction = self.instructions.entry(address).or_insert_with(|| InstructionProfile {
+            segment: step.code_segment,
+            offset: step.instruction_pointer_before,
+            instruction: format_instruction(step, labels),
+            hits: 0,
+            clocks: 0,
+        });
+        instruction.hits += 1;
+        instruction.clocks += step.clocks as u64;
+        self.total_clocks += step.clocks as u64;
+
+        if is_jump_back(step) {
+            let start = get_physical_address(step.code_segment, step.instruction_pointer_after);
+            let profile = self.loops.entry(start).or_insert_with(|| LoopProfile {
+                segment: step.code_segment,
+                offset: step.instruction_pointer_after,
+                end: address,
+                jumps_back: 0,
+            });
+            profile.end = profile.end.max(address);
+            profile.jumps_back += 1;
+        }
+    }
+
+    fn format_location(&self, address: usize, segment: u16, offset: u16) -> String {
+        match self.labels.get(&address) {
+            Some(label) => format!("{:04x}:{:04x} <{}>", segment, offset, label),
+            None => format!("{:04x}:{:04x}", segment, offset),
+        }
+    }
+
+    // The instructions that took the most clocks, then every loop by the clocks spent in it
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+
+        let mut hotspots: Vec<_> = self.instructions.iter().collect();
+        hotspots.sort_by(|(address_a, a), (address_b, b)| b.clocks.cmp(&a.clocks).then(address_a.cmp(address_b)));
+        writeln!(report, "Hotspots ({} clocks over {} addresses):", self.total_clocks, hotspots.len()).unwrap();
+        writeln!(report, "\t{:<28} {:>10} {:>12} {:>7}  instruction", "address", "hits", "clocks", "share").unwrap();
+        for (address, profile) in hotspots.iter().take(MAX_HOTSPOTS) {
+            writeln!(
+                report,
+                "\t{:<28} {:>10} {:>12} {:>6.2}%  {}",
+                self.format_location(**address, profile.segment, profile.offset),
+                profile.hits,
+                profile.clocks,
+                get_share(profile.clocks, self.total_clocks),
+                profile.instruction,
+            ).unwrap();
+        }
+        if hotspots.len() > MAX_HOTSPOTS {
+            writeln!(report, "\t... {} more", hotspots.len() - MAX_HOTSPOTS).unwrap();
+        }
+
+        if self.loops.is_empty() { return report; }
+        let mut loops: Vec<_> = self.loops.iter()
+            .map(|(start, profile)| {
+                let clocks: u64 = self.instructions.range(start..=&profile.end).map(|(_, instruction)| instruction.clocks).sum();
+                let iterations = self.instructions.get(start).map_or(0, |instruction| instruction.hits);
+                (start, profile, clocks, iterations)
+            })
+            .collect();
+        loops.sort_by(|(start_a, _, clocks_a, _), (start_b, _, clocks_b, _)| clocks_b.cmp(clocks_a).then(start_a.cmp(start_b)));
+        writeln!(report).unwrap();
+        writeln!(report, "Loops (by jump back target):").unwrap();
+        writeln!(report, "\t{:<28} {:<10} {:>10} {:>12} {:>7} {:>10}", "start", "end", "iterations", "clocks", "share", "per iter").unwrap();
+        for (start, profile, clocks, iterations) in loops {
+            let end = &self.instructions[&profile.end];
+            writeln!(
+                report,
+                "\t{:<28} {:<10} {:>10} {:>12} {:>6.2}% {:>10.1}",
+                self.format_location(*start, profile.segment, profile.offset),
+                format!("{:04x}:{:04x}", end.segment, end.offset),
+                iterations,
+                clocks,
+                get_share(clocks, self.total_clocks),
+                clocks as f64 / iterations.max(1) as f64,
+            ).unwrap();
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile_program(program: &[u8]) -> Profile {
+        let mut machine = Machine::new();
+        machine.load_program(program);
+        let mut profile = Profile::new(BTreeMap::from([ (3, String::from("label_0")) ]));
+        while let Ok(step) = machine.step() { profile.record(&step); }
+        profile
+    }
+
+    #[test]
+    fn counts_hits_and_clocks_per_address() {
+        // mov cx, 3 / dec cx / jne -3
+        let profile = profile_program(&[ 0xb9, 0x03, 0x00, 0x49, 0x75, 0xfd ]);
+        let hits_and_clocks: Vec<_> = profile.instructions.iter().map(|(address, instruction)| (*address, instruction.hits, instruction.clocks)).collect();
+        assert_eq!(hits_and_clocks, [ (0, 1, 4), (3, 3, 6), (4, 3, 36) ]);
+        assert_eq!(profile.total_clocks, 46);
+
+        let report = profile.report();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines[0], "Hotspots (46 clocks over 3 addresses):");
+        assert!(lines[2].starts_with("\t0000:0004 ") && lines[2].ends_with("78.26%  jne label_0"), "{}", lines[2]);
+    }
+
+    #[test]
+    fn loops_are_keyed_by_jump_back_target() {
+        // mov cx, 3 / dec cx / jne -3 is one loop from dec cx to the jne, the jne falling through at
+        // the end doesn't count
+        let profile = profile_program(&[ 0xb9, 0x03, 0x00, 0x49, 0x75, 0xfd ]);
+        assert_eq!(profile.loops.len(), 1);
+        assert_eq!((profile.loops[&3].end, profile.loops[&3].jumps_back), (4, 2));
+
+        let report = profile.report();
+        let loop_line = report.lines().last().unwrap();
+        assert_eq!(loop_line, "\t0000:0003 <label_0>          0000:0004           3           42  91.30%       14.0");
+    }
+}
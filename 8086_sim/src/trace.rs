@@ -0,0 +1,246 @@
+use std::fmt::Write;
+
+use crate::{
+    decoder::*,
+    machine::*,
+};
+
+// Traces of an execution. The text trace is one line per executed instruction. The newline delimited
+// JSON trace has one "step" object per executed instruction and ends with one "final" object holding
+// the end state.
+
+pub fn step_to_text(step: &Step, total_clocks: u64, total_cycles: Option<u64>, should_show_clocks: bool, should_explain_clocks: bool) -> String {
+    let mut text = format!("{} ;", step.instruction);
+
+    for event in &step.events {
+        match event {
+            StepEvent::Register { encoding, access, before, after } => {
+                let name = get_register_name(*encoding, *access == RegisterAccess::Full).expect("Invalid register");
+                write!(text, " {}:{:#x}({})->{:#x}({})", name, before, before, after, after).unwrap();
+            },
+            StepEvent::SegmentRegister { encoding, before, after } => {
+                let name = get_segment_register_name(*encoding).expect("Invalid segment register");
+                write!(text, " {}:{:#x}({})->{:#x}({})", name, before, before, after, after).unwrap();
+            },
+            StepEvent::Memory { effective_address, before, after, .. } => {
+                write!(text, " {}:{:#x}({})->{:#x}({})", effective_address, before, before, after, after).unwrap();
+            },
+            StepEvent::DivideError => text.push_str(" divide error"),
+            // left to whatever runs the program
+            StepEvent::Breakpoint | StepEvent::Output(_) => {},
+            // written last, after the instruction pointer
+            StepEvent::Flags { .. } => {},
+        }
+    }
+
+    if should_show_clocks {
+        match &step.clock_explanation {
+            Some(explanation) if should_explain_clocks => write!(text, " Clocks: +{} = {} ({}) |", step.clocks, total_clocks, explanation).unwrap(),
+            _ => write!(text, " Clocks: +{} = {} |", step.clocks, total_clocks).unwrap(),
+        }
+        if let (Some(cycles), Some(total_cycles)) = (step.cycles, total_cycles) {
+            write!(text, " Cycles: +{} = {} |", cycles, total_cycles).unwrap();
+        }
+    }
+
+    write!(text, " ip:{:#x}->{:#x}", step.instruction_pointer_before, step.instruction_pointer_after).unwrap();
+    for event in &step.events {
+        if let StepEvent::Flags { before, after } = event {
+            write!(text, " flags:{}->{}", before.get_active_flags_string(), after.get_active_flags_string()).unwrap();
+        }
+    }
+    text
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for character in text.chars() {
+        match character {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            character if (character as u32) < 0x20 => write!(json, "\\u{:04x}", character as u32).unwrap(),
+            character => json.push(character),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn json_optional_string(text: Option<&str>) -> String {
+    text.map_or(String::from("null"), json_string)
+}
+
+fn json_array(values: impl Iterator<Item = String>) -> String {
+    format!("[{}]", values.collect::<Vec<_>>().join(","))
+}
+
+fn get_operand_text(instruction: &Instruction, operand: &Operand) -> String {
+    match operand {
+        // relative to the start of the instruction, the same as the text trace
+        Operand::LabelOffset(offset) => format!("${:+}", *offset as i32 + instruction.size as i32),
+        operand => operand.to_string(),
+    }
+}
+
+// cycles from the prefetch model are only included when it's enabled
+fn json_cycles(name: &str, cycles: Option<u64>) -> String {
+    cycles.map_or(String::new(), |cycles| format!(",\"{}\":{}", name, cycles))
+}
+
+pub fn step_to_json(step: &Step, total_clocks: u64, total_cycles: Option<u64>, memory: &[u8]) -> String {
+    let instruction = &step.instruction;
+    let bytes = (0..instruction.size as u16)
+        .map(|index| memory[get_physical_address(step.code_segment, step.instruction_pointer_before.wrapping_add(index))].to_string());
+    let operands = instruction.operands.iter()
+        .flatten()
+        .map(|operand| json_string(&get_operand_text(instruction, operand)));
+
+    let mut registers: Vec<String> = vec![];
+    let mut memory_writes: Vec<String> = vec![];
+    let mut flags = String::from("null");
+    let mut divide_error = false;
+    for event in &step.events {
+        match event {
+            StepEvent::Register { encoding, access, before, after } => {
+                let name = get_register_name(*encoding, *access == RegisterAccess::Full).expect("Invalid register");
+                registers.push(format!("{{\"name\":{},\"before\":{},\"after\":{}}}", json_string(name), before, after));
+            },
+            StepEvent::SegmentRegister { encoding, before, after } => {
+                let name = get_segment_register_name(*encoding).expect("Invalid segment register");
+                registers.push(format!("{{\"name\":{},\"before\":{},\"after\":{}}}", json_string(name), before, after));
+            },
+            StepEvent::Memory { effective_address, physical_address, before, after } => {
+                memory_writes.push(format!(
+                    "{{\"address\":{},\"effective_address\":{},\"wide\":{},\"before\":{},\"after\":{}}}",
+                    physical_address, json_string(&effective_address.to_string()), instruction.flags.wide, before, after
+                ));
+            },
+            StepEvent::Flags { before, after } => {
+                flags = format!(
+                    "{{\"before\":{},\"after\":{}}}",
+                    json_string(&before.get_active_flags_string()), json_string(&after.get_active_flags_string())
+                );
+            },
+            StepEvent::DivideError => divide_error = true,
+            // left to whatever runs the program
+            StepEvent::Breakpoint | StepEvent::Output(_) => {},
+        }
+    }
+
+    format!(
+        concat!(
+            "{{\"type\":\"step\",\"cs\":{},\"ip\":{},\"next_ip\":{},\"bytes\":{},\"instruction\":{},\"mnemonic\":{},\"operands\":{},",
+            "\"registers\":{},\"memory\":{},\"flags\":{},\"divide_error\":{},\"clocks\":{},\"clock_explanation\":{},\"total_clocks\":{}{}{}}}"
+        ),
+        step.code_segment,
+        step.instruction_pointer_before,
+        step.instruction_pointer_after,
+        json_array(bytes),
+        json_string(&instruction.to_string()),
+        json_string(&instruction.get_mnemonic()),
+        json_array(operands),
+        json_array(registers.into_iter()),
+        json_array(memory_writes.into_iter()),
+        flags,
+        divide_error,
+        step.clocks,
+        json_optional_string(step.clock_explanation.as_deref()),
+        total_clocks,
+        json_cycles("cycles", step.cycles.map(u64::from)),
+        json_cycles("total_cycles", total_cycles),
+    )
+}
+
+pub fn final_state_to_json(machine: &Machine, stop_reason: &StopReason) -> String {
+    let registers = machine.registers.registers.iter().enumerate()
+        .map(|(encoding, value)| (get_register_name(encoding as u8, true).expect("Invalid register"), value))
+        .chain(machine.registers.segment_registers.iter().enumerate()
+            .map(|(encoding, value)| (get_segment_register_name(encoding as u8).expect("Invalid segment register"), value)))
+        .map(|(name, value)| format!("{}:{}", json_string(name), value))
+        .collect::<Vec<_>>()
+        .join(",");
+    let (stop, error) = match stop_reason {
+        StopReason::Halted => ("halted", None),
+        StopReason::DecodeError(error) => ("decode_error", Some(error.to_string())),
+        StopReason::MaxSteps => ("max_steps", None),
+        StopReason::MaxClocks => ("max_clocks", None),
+        StopReason::InfiniteLoop { .. } => ("infinite_loop", Some(stop_reason.to_string())),
+        StopReason::Exited(_) => ("exited", None),
+        StopReason::DivideOverflow => ("divide_overflow", None),
+        StopReason::Breakpoint { .. } => ("breakpoint", Some(stop_reason.to_string())),
+        StopReason::UnsupportedInterrupt { .. } => ("unsupported_interrupt", Some(stop_reason.to_string())),
+        StopReason::UnterminatedString { .. } => ("unterminated_string", Some(stop_reason.to_string())),
+    };
+
+    format!(
+        "{{\"type\":\"final\",\"registers\":{{{}}},\"ip\":{},\"flags\":{},\"total_clocks\":{}{},\"stop\":{},\"error\":{}}}",
+        registers,
+        machine.instruction_pointer,
+        json_string(&machine.flags.get_active_flags_string()),
+        machine.total_clocks,
+        json_cycles("total_cycles", machine.prefetch_model.as_ref().map(|prefetch_model| prefetch_model.total_cycles)),
+        json_string(stop),
+        json_optional_string(error.as_deref()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_object() {
+        // mov cx, 3 / dec cx
+        let mut machine = Machine::new();
+        machine.load_program(&[ 0xb9, 0x03, 0x00, 0x49 ]);
+        machine.step().unwrap();
+        let step = machine.step().unwrap();
+
+        assert_eq!(step_to_json(&step, machine.total_clocks, None, &machine.memory), concat!(
+            "{\"type\":\"step\",\"cs\":0,\"ip\":3,\"next_ip\":4,\"bytes\":[73],\"instruction\":\"dec cx\",\"mnemonic\":\"dec\",\"operands\":[\"cx\"],",
+            "\"registers\":[{\"name\":\"cx\",\"before\":3,\"after\":2}],\"memory\":[],\"flags\":null,\"divide_error\":false,",
+            "\"clocks\":2,\"clock_explanation\":null,\"total_clocks\":6}",
+        ));
+
+        let stop_reason = machine.step().err().unwrap();
+        let final_state = final_state_to_json(&machine, &stop_reason);
+        assert!(final_state.starts_with("{\"type\":\"final\",\"registers\":{\"ax\":0,\"cx\":2,"));
+        assert!(final_state.ends_with("\"ip\":4,\"flags\":\"\",\"total_clocks\":6,\"stop\":\"halted\",\"error\":null}"));
+    }
+
+    #[test]
+    fn memory_destinations() {
+        let mut machine = Machine::new();
+        machine.load_program(&[
+            0xbb, 0x10, 0x00,       // mov bx, 0x10
+            0x83, 0x47, 0x02, 0x05, // add [bx + 2], word 5
+            0xd0, 0x26, 0x00, 0x01, // shl byte [0x100], 1
+            0xf7, 0x1f,             // neg word [bx]
+        ]);
+        machine.memory[0x10..0x14].copy_from_slice(&[ 0x01, 0x00, 0xfb, 0x00 ]);
+        machine.memory[0x100] = 0x81;
+        machine.step().unwrap();
+
+        let mut traces = Vec::new();
+        while let Ok(step) = machine.step() {
+            traces.push(step_to_text(&step, machine.total_clocks, None, false, false));
+            if traces.len() == 1 {
+                assert!(step_to_json(&step, machine.total_clocks, None, &machine.memory).contains(
+                    "\"memory\":[{\"address\":18,\"effective_address\":\"[bx + 2]\",\"wide\":true,\"before\":251,\"after\":256}]"
+                ));
+            }
+        }
+        assert_eq!(traces, [
+            "add word [bx + 2], byte 5 ; [bx + 2]:0xfb(251)->0x100(256) ip:0x3->0x7 flags:->PA",
+            "shl byte [256], 1 ; [256]:0x81(129)->0x2(2) ip:0x7->0xb flags:PA->CAO",
+            "neg word [bx] ; [bx]:0x1(1)->0xffff(65535) ip:0xb->0xd flags:CAO->CPAS",
+        ]);
+    }
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(json_string("a \"b\"\\\n\u{1}"), "\"a \\\"b\\\"\\\\\\n\\u0001\"");
+    }
+}
@@ -1,7 +1,20 @@
 #!/bin/bash
 
-nasm $1 -o orig
-cargo run orig > disassembled.asm
-nasm disassembled.asm -o new
-diff new orig
-rm orig disassembled.asm new
+# checks every listing against nasm: its source assembles to the committed binary, and the
+# disassembly of the binary reassembles to the same bytes
+cd "$(dirname "$0")"
+status=0
+for source in listings/*.asm; do
+    binary="${source%.asm}.bin"
+    nasm "$source" -o assembled || { status=1; continue; }
+    cargo run -q "$binary" > disassembled.asm
+    nasm disassembled.asm -o reassembled
+    if cmp -s assembled "$binary" && cmp -s reassembled "$binary"; then
+        echo "ok $source"
+    else
+        echo "FAILED $source"
+        status=1
+    fi
+done
+rm -f assembled disassembled.asm reassembled
+exit $status